@@ -20,12 +20,14 @@ pub const FILEHBB: Bitboard = FILEABB << 7;
 pub const RANK1BB: Bitboard = 0xFF;
 pub const RANK2BB: Bitboard = RANK1BB << (8 * 1);
 pub const RANK3BB: Bitboard = RANK1BB << (8 * 2);
-pub const RANK4BB: Bitboard = RANK1BB << (8 * 2);
+pub const RANK4BB: Bitboard = RANK1BB << (8 * 3);
 pub const RANK5BB: Bitboard = RANK1BB << (8 * 4);
 pub const RANK6BB: Bitboard = RANK1BB << (8 * 5);
 pub const RANK7BB: Bitboard = RANK1BB << (8 * 6);
 pub const RANK8BB: Bitboard = RANK1BB << (8 * 7);
 
+pub const DARKSQUARES: Bitboard = 0xAA55AA55AA55AA55;
+
 // pub const SQNB: usize = Square::SquareNb as usize - 1; //Poissibly move these constants to the types file
 // pub const PNB: usize = Piece::PieceNb as usize;
 // pub const PTNB: usize = PieceType::PieceTypeNb as usize;
@@ -47,7 +49,7 @@ static ROOK_TABLE: OnceLock<Vec<Bitboard>> = OnceLock::new();
 static BISHOP_TABLE: OnceLock<Vec<Bitboard>> = OnceLock::new();
 
 pub const fn more_than_one(bb: Bitboard) -> bool {
-    bb & (bb - 1) != 0 // Resets the highest bit
+    bb & bb.wrapping_sub(1) != 0 // Resets the lowest bit
 }
 
 fn distance(x: Square, y: Square) -> u8 {
@@ -124,7 +126,7 @@ pub fn attacks_bb_helper(
     pt: PieceType,
     s: Square,
     occupied: Bitboard,
-    pseudo_attacks: &[[u64; 64]; 8],
+    pseudo_attacks: &[[Bitboard; SQNB]; PTNB],
 ) -> Bitboard {
     match pt {
         PieceType::Bishop => bishop_attacks_bb(s, occupied),
@@ -158,8 +160,7 @@ pub fn pseudo_attacks_bb(pt: PieceType, s: Square) -> Bitboard {
         panic!("Attempt to access pseudo attacks table prior to initialization")
     }
 }
-//These two functions may not be needed
-const fn rank_bb(r: Rank) -> Bitboard {
+pub const fn rank_bb(r: Rank) -> Bitboard {
     RANK1BB << (8 * r as i32)
 }
 
@@ -171,10 +172,37 @@ pub fn pop_lsb(bb: &mut Bitboard) -> Square {
     ret
 }
 
-const fn file_bb(f: File) -> Bitboard {
+pub const fn file_bb(f: File) -> Bitboard {
     FILEABB << f as i32
 }
 
+pub const fn adjacent_files_bb(s: Square) -> Bitboard {
+    let f = file_bb(s.file_of());
+    shift(f, Direction::East) | shift(f, Direction::West)
+}
+
+//All squares on the ranks in front of the given square, from the point of view of color c
+pub const fn forward_ranks_bb(c: Color, s: Square) -> Bitboard {
+    match c {
+        Color::White => !RANK1BB << (8 * s.rank_of() as u32),
+        _ => !RANK8BB >> (8 * (7 - s.rank_of() as u32)),
+    }
+}
+
+pub const fn forward_file_bb(c: Color, s: Square) -> Bitboard {
+    forward_ranks_bb(c, s) & file_bb(s.file_of())
+}
+
+//Squares that a pawn of color c could attack while advancing from s
+pub const fn pawn_attack_span(c: Color, s: Square) -> Bitboard {
+    forward_ranks_bb(c, s) & adjacent_files_bb(s)
+}
+
+//Squares that must be free of enemy pawns for a pawn on s to be passed
+pub const fn passed_pawn_span(c: Color, s: Square) -> Bitboard {
+    pawn_attack_span(c, s) | forward_file_bb(c, s)
+}
+
 #[inline]
 pub fn between_bb(s1: Square, s2: Square) -> Bitboard {
     if let Some(b_bb) = BETWEEN_BB.get() {
@@ -184,7 +212,7 @@ pub fn between_bb(s1: Square, s2: Square) -> Bitboard {
     }
 }
 #[inline]
-fn least_significant_square_bb(bb: Bitboard) -> Bitboard {
+pub fn least_significant_square_bb(bb: Bitboard) -> Bitboard {
     assert!(bb != 0, "bitboard is empty");
     bb & (!bb + 1)
 }
@@ -266,6 +294,11 @@ impl Magic {
 }
 
 pub fn init() {
+    //Finding the magics is slow, only do it once. PAWN_ATTACKS is the last table to be set
+    if PAWN_ATTACKS.get().is_some() {
+        return;
+    }
+
     init_square_distance();
     init_popcnt();
 
@@ -507,6 +540,7 @@ mod test {
 
     #[test]
     fn test_more_than_one() {
+        assert!(!more_than_one(0));
         assert!(!more_than_one(4));
         assert!(!more_than_one(2));
         assert!(!more_than_one(1));
@@ -514,4 +548,41 @@ mod test {
         assert!(more_than_one(7));
         assert!(more_than_one(9));
     }
+
+    #[test]
+    fn test_rank_constants() {
+        let ranks = [
+            RANK1BB, RANK2BB, RANK3BB, RANK4BB, RANK5BB, RANK6BB, RANK7BB, RANK8BB,
+        ];
+        for (i, &r) in ranks.iter().enumerate() {
+            assert_eq!(r, 0xFF << (8 * i));
+        }
+    }
+
+    #[test]
+    fn test_pawn_spans() {
+        assert_eq!(adjacent_files_bb(Square::SqA4), FILEBBB);
+        assert_eq!(adjacent_files_bb(Square::SqE4), FILEDBB | FILEFBB);
+        assert_eq!(
+            forward_ranks_bb(Color::White, Square::SqE6),
+            RANK7BB | RANK8BB
+        );
+        assert_eq!(
+            forward_ranks_bb(Color::Black, Square::SqE3),
+            RANK2BB | RANK1BB
+        );
+        assert_eq!(forward_ranks_bb(Color::White, Square::SqE8), 0);
+        assert_eq!(
+            forward_file_bb(Color::Black, Square::SqB3),
+            Square::SqB2.bb() | Square::SqB1
+        );
+        assert_eq!(
+            pawn_attack_span(Color::White, Square::SqH6),
+            Square::SqG7.bb() | Square::SqG8
+        );
+        assert_eq!(
+            passed_pawn_span(Color::White, Square::SqB6),
+            (FILEABB | FILEBBB | FILECBB) & (RANK7BB | RANK8BB)
+        );
+    }
 }
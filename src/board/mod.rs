@@ -1,4 +1,6 @@
-mod bitboard;
-mod position;
-mod zobrist;
-mod movegen;
\ No newline at end of file
+pub mod bitboard;
+pub mod movegen;
+pub mod perft;
+pub mod position;
+pub mod san;
+pub mod zobrist;
@@ -1,7 +1,5 @@
 use super::bitboard::pop_lsb;
-use crate::all_pieces;
 use crate::board::bitboard as bb;
-use crate::board::bitboard::get_pawn_attacks_bb;
 use crate::board::position as pos;
 use crate::pieces_by_color_and_pt;
 use crate::pieces_of_types;
@@ -9,12 +7,13 @@ use crate::types::*;
 
 const MAX_MOVES: usize = 256;
 
+#[derive(Debug, Clone, Copy)]
 pub struct ExtMove {
-    base: Move,
-    value: i32,
+    pub base: Move,
+    pub value: i32,
 }
 
-struct MoveList {
+pub struct MoveList {
     move_list: Vec<ExtMove>,
 }
 
@@ -38,6 +37,36 @@ impl MoveList {
     pub fn push_move_ext_move(&mut self, mv: ExtMove) {
         self.move_list.push(mv);
     }
+
+    pub fn len(&self) -> usize {
+        self.move_list.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.move_list.is_empty()
+    }
+
+    pub fn contains(&self, m: Move) -> bool {
+        self.move_list.iter().any(|em| em.base == m)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Move> + '_ {
+        self.move_list.iter().map(|em| em.base)
+    }
+
+    pub fn ext_moves_mut(&mut self) -> &mut [ExtMove] {
+        &mut self.move_list
+    }
+
+    pub fn into_ext_moves(self) -> Vec<ExtMove> {
+        self.move_list
+    }
+}
+
+impl Default for MoveList {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 const fn bind_color(n: i32) -> Color {
@@ -60,22 +89,26 @@ const fn bind_gentype(n: i32) -> GenType {
     }
 }
 
-pub fn make_promotions<const T: i32, const D: i32, const Enemy: bool>(
+//Queen promotions are generated together with the captures, underpromotions with the quiets
+//unless they also capture. Evasions and non evasions get all of them
+pub fn make_promotions<const T: i32, const ENEMY: bool>(
     move_list: &mut MoveList,
+    d: Direction,
     to: Square,
-) -> usize {
-    let d = D;
+) {
     let gen_type = bind_gentype(T);
-    let all = gen_type == GenType::Captures || gen_type == GenType::Evasions;
-    let mut cur: usize = 0;
+    let all = gen_type == GenType::Evasions || gen_type == GenType::NonEvasions;
+    let from = to - d;
+
     if gen_type == GenType::Captures || all {
-        cur += 1;
-        todo!();
+        move_list.push_move(Move::make(MoveType::Promotion, from, to, PieceType::Queen));
     }
-    if gen_type == GenType::Evasions && Enemy || (gen_type == GenType::Quiets && !Enemy) || all {
-        todo!();
+
+    if (gen_type == GenType::Captures && ENEMY) || (gen_type == GenType::Quiets && !ENEMY) || all {
+        move_list.push_move(Move::make(MoveType::Promotion, from, to, PieceType::Rook));
+        move_list.push_move(Move::make(MoveType::Promotion, from, to, PieceType::Bishop));
+        move_list.push_move(Move::make(MoveType::Promotion, from, to, PieceType::Knight));
     }
-    cur
 }
 
 pub fn generate_pawn_moves<const T: i32, const C: i32>(
@@ -86,12 +119,12 @@ pub fn generate_pawn_moves<const T: i32, const C: i32>(
     let us = bind_color(C);
     let them: Color = !us;
     let gen_type = bind_gentype(T);
-    let TRank7BB = if us == Color::White {
+    let t_rank7_bb = if us == Color::White {
         bb::RANK7BB
     } else {
         bb::RANK2BB
     };
-    let TRank3BB = if us == Color::White {
+    let t_rank3_bb = if us == Color::White {
         bb::RANK3BB
     } else {
         bb::RANK6BB
@@ -114,124 +147,412 @@ pub fn generate_pawn_moves<const T: i32, const C: i32>(
     } else {
         pos.pieces_by_color(them)
     };
-    let pawns_on_7th = pieces_by_color_and_pt!(pos, us, PieceType::Pawn) & TRank7BB;
-    let pawns_not_on_7th = pieces_by_color_and_pt!(pos, us, PieceType::Pawn) & !TRank7BB;
+    let pawns_on_7th = pieces_by_color_and_pt!(pos, us, PieceType::Pawn) & t_rank7_bb;
+    let pawns_not_on_7th = pieces_by_color_and_pt!(pos, us, PieceType::Pawn) & !t_rank7_bb;
 
+    //Single and double pawn pushes, no promotions
     if gen_type != GenType::Captures {
-        let mut b1 = bb::shift(pawns_on_7th, up) & empty_squares;
-        let mut b2 = bb::shift(b1 & TRank3BB, up) & empty_squares;
+        let mut b1 = bb::shift(pawns_not_on_7th, up) & empty_squares;
+        let mut b2 = bb::shift(b1 & t_rank3_bb, up) & empty_squares;
 
         if gen_type == GenType::Evasions {
             b1 &= target;
             b2 &= target;
         }
 
+        //A quiet pawn check either attacks the king directly or moves a blocker off the
+        //king's line. Discovered checks by promotion are already part of the captures
+        if gen_type == GenType::QuietChecks {
+            let ksq = pos.square(them, PieceType::King);
+            let dc_candidate_pawns = pos.blockers_for_king(them) & !ksq.file_bb();
+            b1 &= bb::get_pawn_attacks_bb(them, ksq) | bb::shift(dc_candidate_pawns, up);
+            b2 &= bb::get_pawn_attacks_bb(them, ksq)
+                | bb::shift(bb::shift(dc_candidate_pawns, up), up);
+        }
+
         while b1 != 0 {
-            let to = b1.trailing_zeros();
-            let to = Square::new_from_n(to as i32);
-            b1 = b1 & b1 - 1; // Pop LSB
+            let to = pop_lsb(&mut b1);
             move_list.push_move(Move::new_from_to_sq(to - up, to));
         }
 
         while b2 != 0 {
-            let to = b2.trailing_zeros();
-            let to = Square::new_from_n(to as i32);
-            b2 = b2 & b2 - 1;
+            let to = pop_lsb(&mut b2);
             move_list.push_move(Move::new_from_to_sq(to - up - up, to));
         }
+    }
 
-        if pawns_not_on_7th != 0 {
-            let mut b1 = bb::shift(pawns_not_on_7th, up_right) & enemies;
-            let mut b2 = bb::shift(pawns_not_on_7th, up_left) & enemies;
-            let mut b3 = bb::shift(pawns_not_on_7th, up) & empty_squares;
+    //Promotions and underpromotions
+    if pawns_on_7th != 0 {
+        let mut b1 = bb::shift(pawns_on_7th, up_right) & enemies;
+        let mut b2 = bb::shift(pawns_on_7th, up_left) & enemies;
+        let mut b3 = bb::shift(pawns_on_7th, up) & empty_squares;
 
-            if gen_type == GenType::Evasions {
-                b3 &= target;
-            }
-            while b1 > 0 {
-                if C == Color::White as i32 {
-                    make_promotions::<T, NORTH_EAST, true>(
-                        move_list,
-                        Square::new_from_n(b1.trailing_zeros() as i32),
-                    );
-                } else {
-                    make_promotions::<T, SOUTH_WEST, true>(
-                        move_list,
-                        Square::new_from_n(b1.trailing_zeros() as i32),
-                    );
-                }
-                b1 &= b1 - 1; //remove lsb
-            }
+        if gen_type == GenType::Evasions {
+            b3 &= target;
+        }
 
-            while b2 > 0 {
-                if C == Color::White as i32 {
-                    make_promotions::<T, NORTH_WEST, true>(
-                        move_list,
-                        Square::new_from_n(b1.trailing_zeros() as i32),
-                    );
-                } else {
-                    make_promotions::<T, SOUTH_EAST, true>(
-                        move_list,
-                        Square::new_from_n(b1.trailing_zeros() as i32),
-                    );
-                }
-                b2 &= b2 - 1; //remove lsb
-            }
+        while b1 != 0 {
+            make_promotions::<T, true>(move_list, up_right, pop_lsb(&mut b1));
+        }
 
-            while b3 > 0 {
-                if C == Color::White as i32 {
-                    make_promotions::<T, NORTH, false>(
-                        move_list,
-                        Square::new_from_n(b1.trailing_zeros() as i32),
-                    );
-                } else {
-                    make_promotions::<T, SOUTH, false>(
-                        move_list,
-                        Square::new_from_n(b1.trailing_zeros() as i32),
-                    );
-                }
-                b3 &= b3 - 1; //remove lsb
-            }
+        while b2 != 0 {
+            make_promotions::<T, true>(move_list, up_left, pop_lsb(&mut b2));
         }
 
-        if gen_type == GenType::Captures
-            || gen_type == GenType::Evasions
-            || gen_type == GenType::NonEvasions
-        {
-            let mut b1 = bb::shift(pawns_not_on_7th, up_left) & enemies;
-            let mut b2 = bb::shift(pawns_not_on_7th, up_right) & enemies;
+        while b3 != 0 {
+            make_promotions::<T, false>(move_list, up, pop_lsb(&mut b3));
+        }
+    }
 
-            while b1 != 0 {
-                let to = Square::new_from_n(b1.trailing_zeros() as i32);
-                move_list.push_move(Move::new_from_to_sq(to - up_right, to));
-                b1 &= b1 - 1; //pop lsb
-            }
+    //Standard and en passant captures
+    if gen_type == GenType::Captures
+        || gen_type == GenType::Evasions
+        || gen_type == GenType::NonEvasions
+    {
+        let mut b1 = bb::shift(pawns_not_on_7th, up_right) & enemies;
+        let mut b2 = bb::shift(pawns_not_on_7th, up_left) & enemies;
+
+        while b1 != 0 {
+            let to = pop_lsb(&mut b1);
+            move_list.push_move(Move::new_from_to_sq(to - up_right, to));
+        }
+
+        while b2 != 0 {
+            let to = pop_lsb(&mut b2);
+            move_list.push_move(Move::new_from_to_sq(to - up_left, to));
+        }
 
-            while b2 != 0 {
-                let to = Square::new_from_n(b2.trailing_zeros() as i32);
-                move_list.push_move(Move::new_from_to_sq(to - up_right, to));
-                b2 &= b2 - 1; //pop lsb
+        if pos.ep_square() != Square::SqNone {
+            assert!(pos.ep_square().rank_of() == relative_rank(us, Rank::Rank6));
+
+            //An en passant capture cannot resolve a discovered check
+            if gen_type == GenType::Evasions && target & (pos.ep_square() + up) != 0 {
+                return;
             }
 
-            if pos.ep_square() != Square::SqNone {
-                if gen_type == GenType::Evasions && target & (pos.ep_square() + up) != 0 {
-                    return;
-                }
+            b1 = pawns_not_on_7th & bb::get_pawn_attacks_bb(them, pos.ep_square());
+            assert!(b1 != 0);
 
-                b1 = pawns_not_on_7th & bb::get_pawn_attacks_bb(us, pos.ep_square());
-                //TODO: Push Enpassant Move
-                todo!();
+            while b1 != 0 {
+                move_list.push_move(Move::make(
+                    MoveType::EnPassant,
+                    pop_lsb(&mut b1),
+                    pos.ep_square(),
+                    PieceType::Knight,
+                ));
             }
         }
     }
 }
 
-pub fn generate_moves<const C: i32, const T: i32>() {
-    let gen_type: i32 = T;
+pub fn generate_moves<const C: i32, const CHECKS: bool>(
+    pos: &pos::Position,
+    move_list: &mut MoveList,
+    pt: PieceType,
+    target: Bitboard,
+) {
+    assert!(pt != PieceType::King && pt != PieceType::Pawn);
     let us = bind_color(C);
+    let mut pieces = pieces_by_color_and_pt!(pos, us, pt);
+
+    while pieces != 0 {
+        let from = pop_lsb(&mut pieces);
+        let mut b = bb::attacks_bb(pt, from, pos.all_pieces()) & target;
+
+        //To check, either move a blocker freely or give a direct check
+        if CHECKS && (pt == PieceType::Queen || pos.blockers_for_king(!us) & from == 0) {
+            b &= pos.check_squares(pt);
+        }
+
+        while b != 0 {
+            move_list.push_move(Move::new_from_to_sq(from, pop_lsb(&mut b)));
+        }
+    }
 }
 
-pub fn generate_all<const C: i32, const T: i32>() {
-    let gen_type: i32 = T;
+pub fn generate_all<const C: i32, const T: i32>(pos: &pos::Position, move_list: &mut MoveList) {
+    let gen_type = bind_gentype(T);
+    assert!(gen_type != GenType::Legal);
     let us = bind_color(C);
+    let checks = gen_type == GenType::QuietChecks;
+    let ksq = pos.square(us, PieceType::King);
+    let mut target: Bitboard = 0;
+
+    //Only king moves can get out of a double check
+    if gen_type != GenType::Evasions || !bb::more_than_one(pos.checkers()) {
+        target = match gen_type {
+            GenType::Evasions => bb::between_bb(
+                ksq,
+                Square::new_from_n(pos.checkers().trailing_zeros() as i32),
+            ),
+            GenType::NonEvasions => !pos.pieces_by_color(us),
+            GenType::Captures => pos.pieces_by_color(!us),
+            _ => !pos.all_pieces(),
+        };
+
+        generate_pawn_moves::<T, C>(pos, move_list, target);
+        for pt in [
+            PieceType::Knight,
+            PieceType::Bishop,
+            PieceType::Rook,
+            PieceType::Queen,
+        ] {
+            if checks {
+                generate_moves::<C, true>(pos, move_list, pt, target);
+            } else {
+                generate_moves::<C, false>(pos, move_list, pt, target);
+            }
+        }
+    }
+
+    if !checks || pos.blockers_for_king(!us) & ksq != 0 {
+        let mut b = bb::get_pseudo_attacks(PieceType::King, ksq)
+            & if gen_type == GenType::Evasions {
+                !pos.pieces_by_color(us)
+            } else {
+                target
+            };
+        if checks {
+            b &= !bb::get_pseudo_attacks(PieceType::Queen, pos.square(!us, PieceType::King));
+        }
+
+        while b != 0 {
+            move_list.push_move(Move::new_from_to_sq(ksq, pop_lsb(&mut b)));
+        }
+
+        if (gen_type == GenType::Quiets || gen_type == GenType::NonEvasions)
+            && pos.can_castle(us & CastlingRights::AnyCastling)
+        {
+            for cr in [
+                us & CastlingRights::KingSide,
+                us & CastlingRights::QueenSide,
+            ] {
+                if !pos.castling_impeded(cr) && pos.can_castle(cr) {
+                    move_list.push_move(Move::make(
+                        MoveType::Castling,
+                        ksq,
+                        pos.castling_rook_square(cr),
+                        PieceType::Knight,
+                    ));
+                }
+            }
+        }
+    }
+}
+
+//Generates all the pseudo legal moves of the requested type, or only the legal ones for LEGAL.
+//Evasions must be requested exactly when the side to move is in check
+pub fn generate<const T: i32>(pos: &pos::Position) -> MoveList {
+    let mut move_list = MoveList::new();
+
+    if bind_gentype(T) == GenType::Legal {
+        generate_legal(pos, &mut move_list);
+        return move_list;
+    }
+
+    assert!((bind_gentype(T) == GenType::Evasions) == (pos.checkers() != 0));
+    match pos.side_to_move() {
+        Color::White => generate_all::<WHITE, T>(pos, &mut move_list),
+        _ => generate_all::<BLACK, T>(pos, &mut move_list),
+    }
+    move_list
+}
+
+fn generate_legal(pos: &pos::Position, move_list: &mut MoveList) {
+    let us = pos.side_to_move();
+    let pinned = pos.blockers_for_king(us) & pos.pieces_by_color(us);
+    let ksq = pos.square(us, PieceType::King);
+
+    *move_list = if pos.checkers() != 0 {
+        generate::<EVASIONS>(pos)
+    } else {
+        generate::<NON_EVASIONS>(pos)
+    };
+
+    //Only moves of pinned pieces, king moves and en passant captures can be illegal
+    move_list.move_list.retain(|em| {
+        let m = em.base;
+        !((pinned & m.from_sq() != 0 || m.from_sq() == ksq || m.type_of() == MoveType::EnPassant)
+            && !pos.legal(m))
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::board::position::{Position, START_FEN};
+
+    const KIWIPETE: &str = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+
+    #[test]
+    fn test_generate_legal() {
+        assert_eq!(
+            generate::<LEGAL>(&Position::from_fen(START_FEN).unwrap()).len(),
+            20
+        );
+        assert_eq!(
+            generate::<LEGAL>(&Position::from_fen(KIWIPETE).unwrap()).len(),
+            48
+        );
+        assert_eq!(
+            generate::<LEGAL>(
+                &Position::from_fen("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1").unwrap()
+            )
+            .len(),
+            14
+        );
+        assert_eq!(
+            generate::<LEGAL>(
+                &Position::from_fen(
+                    "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+                )
+                .unwrap()
+            )
+            .len(),
+            6
+        );
+        assert_eq!(
+            generate::<LEGAL>(
+                &Position::from_fen("rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8")
+                    .unwrap()
+            )
+            .len(),
+            44
+        );
+    }
+
+    #[test]
+    fn test_generate_captures_and_quiets() {
+        let pos = Position::from_fen(KIWIPETE).unwrap();
+        let captures = generate::<CAPTURES>(&pos);
+        let quiets = generate::<QUIETS>(&pos);
+        let non_evasions = generate::<NON_EVASIONS>(&pos);
+
+        assert_eq!(captures.len(), 8);
+        assert_eq!(captures.len() + quiets.len(), non_evasions.len());
+        for m in captures.iter() {
+            assert!(!quiets.contains(m));
+            assert!(non_evasions.contains(m));
+        }
+        for m in quiets.iter() {
+            assert!(non_evasions.contains(m));
+        }
+
+        //Both castling moves are encoded as the king capturing its rook
+        let oo = Move::make(
+            MoveType::Castling,
+            Square::SqE1,
+            Square::SqH1,
+            PieceType::Knight,
+        );
+        let ooo = Move::make(
+            MoveType::Castling,
+            Square::SqE1,
+            Square::SqA1,
+            PieceType::Knight,
+        );
+        assert!(quiets.contains(oo));
+        assert!(quiets.contains(ooo));
+    }
+
+    #[test]
+    fn test_generate_promotions() {
+        let pos = Position::from_fen("1n2k3/P7/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+        let captures = generate::<CAPTURES>(&pos);
+        let quiets = generate::<QUIETS>(&pos);
+
+        //Queen promotions count as captures, underpromotions that do not capture as quiets
+        let push_queen = Move::make(
+            MoveType::Promotion,
+            Square::SqA7,
+            Square::SqA8,
+            PieceType::Queen,
+        );
+        let push_knight = Move::make(
+            MoveType::Promotion,
+            Square::SqA7,
+            Square::SqA8,
+            PieceType::Knight,
+        );
+        let take_rook = Move::make(
+            MoveType::Promotion,
+            Square::SqA7,
+            Square::SqB8,
+            PieceType::Rook,
+        );
+        assert!(captures.contains(push_queen));
+        assert!(!captures.contains(push_knight));
+        assert!(quiets.contains(push_knight));
+        assert!(captures.contains(take_rook));
+        assert_eq!(captures.len(), 5);
+        assert_eq!(generate::<LEGAL>(&pos).len(), 8 + 5);
+    }
+
+    #[test]
+    fn test_generate_evasions() {
+        //Double check, only king moves are generated
+        let pos = Position::from_fen("4k3/8/8/8/8/5n2/8/r3K3 w - - 0 1").unwrap();
+        let evasions = generate::<EVASIONS>(&pos);
+        assert!(evasions.iter().all(|m| m.from_sq() == Square::SqE1));
+
+        //Blocking or capturing the checker
+        let pos = Position::from_fen("4k3/8/8/8/8/8/3B4/r3K3 w - - 0 1").unwrap();
+        let legal = generate::<LEGAL>(&pos);
+        assert!(legal.contains(Move::new_from_to_sq(Square::SqD2, Square::SqC1)));
+        assert_eq!(legal.len(), 3);
+
+        //En passant that captures the checking pawn
+        let pos = Position::from_fen("8/8/8/2k5/3Pp3/8/8/4K3 b - d3 0 1").unwrap();
+        let ep = Move::make(
+            MoveType::EnPassant,
+            Square::SqE4,
+            Square::SqD3,
+            PieceType::Knight,
+        );
+        assert!(generate::<LEGAL>(&pos).contains(ep));
+    }
+
+    #[test]
+    fn test_generate_quiet_checks() {
+        let pos = Position::from_fen("4k3/8/8/8/6N1/8/8/R3K3 w - - 0 1").unwrap();
+        let checks = generate::<QUIET_CHECKS>(&pos);
+        for m in checks.iter() {
+            assert!(pos.gives_check(m));
+        }
+        assert!(checks.contains(Move::new_from_to_sq(Square::SqA1, Square::SqA8)));
+        assert!(checks.contains(Move::new_from_to_sq(Square::SqG4, Square::SqF6)));
+        assert_eq!(checks.len(), 2);
+
+        //Moving a blocker off the line to the king gives a discovered check
+        let pos = Position::from_fen("4k3/8/8/8/4N3/8/8/4R1K1 w - - 0 1").unwrap();
+        let checks = generate::<QUIET_CHECKS>(&pos);
+        assert_eq!(checks.len(), 8);
+        for m in checks.iter() {
+            assert!(pos.gives_check(m));
+        }
+    }
+
+    #[test]
+    fn test_legal_filters_pins_and_en_passant() {
+        //The e5 pawn may not capture en passant since that exposes the king along the rank
+        let pos = Position::from_fen("8/8/8/KPp4r/8/8/8/4k3 w - c6 0 1").unwrap();
+        let ep = Move::make(
+            MoveType::EnPassant,
+            Square::SqB5,
+            Square::SqC6,
+            PieceType::Knight,
+        );
+        assert!(!generate::<LEGAL>(&pos).contains(ep));
+        assert!(generate::<NON_EVASIONS>(&pos).contains(ep));
+
+        //Castling through an attacked square is illegal
+        let pos = Position::from_fen("4k3/8/8/8/8/8/5r2/4K2R w K - 0 1").unwrap();
+        let oo = Move::make(
+            MoveType::Castling,
+            Square::SqE1,
+            Square::SqH1,
+            PieceType::Knight,
+        );
+        assert!(!generate::<LEGAL>(&pos).contains(oo));
+    }
 }
@@ -0,0 +1,39 @@
+use crate::board::movegen::generate;
+use crate::board::position::{Position, StateInfo};
+use crate::types::*;
+
+//Counts the leaf nodes of the legal move tree of the given depth
+pub fn perft(pos: &mut Position, depth: Depth) -> u64 {
+    if depth <= 0 {
+        return 1;
+    }
+
+    let move_list = generate::<LEGAL>(pos);
+    if depth == 1 {
+        return move_list.len() as u64;
+    }
+
+    let mut nodes = 0;
+    for m in move_list.iter() {
+        let mut st = StateInfo::default();
+        let gives_check = pos.gives_check(m);
+        pos.do_move(m, &mut st, gives_check);
+        nodes += perft(pos, depth - 1);
+        pos.undo_move(m);
+    }
+    nodes
+}
+
+//Perft split by root move, useful for tracking down move generation bugs
+pub fn divide(pos: &mut Position, depth: Depth) -> Vec<(Move, u64)> {
+    let move_list = generate::<LEGAL>(pos);
+    let mut counts = Vec::with_capacity(move_list.len());
+    for m in move_list.iter() {
+        let mut st = StateInfo::default();
+        let gives_check = pos.gives_check(m);
+        pos.do_move(m, &mut st, gives_check);
+        counts.push((m, perft(pos, depth - 1)));
+        pos.undo_move(m);
+    }
+    counts
+}
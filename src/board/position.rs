@@ -4,6 +4,7 @@ use crate::board::bitboard::more_than_one;
 use crate::board::bitboard::pawn_attacks_bb;
 use crate::board::bitboard::RANK1BB;
 use crate::board::bitboard::RANK8BB;
+use crate::board::movegen::generate;
 use crate::board::zobrist;
 use crate::types::*;
 use std::fmt;
@@ -17,9 +18,12 @@ use super::bitboard::BETWEEN_BB;
 const PIECE_TYPE_NB: usize = PieceType::PieceTypeNb as usize;
 const PIECE_TO_CHAR: &str = " PNBRQK  pnbrqk";
 const MAX_PLY: usize = 246; // Maximum search depth
+pub const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
 
+//Keys of all reversible moves (piece, from, to and the side change) in a cuckoo hash table,
+//with the matching moves. Used to find a move that returns to an earlier position
 pub static CUCKOO: OnceLock<[Key; 8192]> = OnceLock::new();
-pub static CUCKOO_MOVE: OnceLock<[Key; 8192]> = OnceLock::new();
+pub static CUCKOO_MOVE: OnceLock<[Move; 8192]> = OnceLock::new();
 
 #[macro_export]
 macro_rules! pieces_of_types {
@@ -28,25 +32,25 @@ macro_rules! pieces_of_types {
     };
 
     ($pos: expr, $pt: expr, $($rest_pts: expr),+) => {
-        $pos.pieces_by_piecetype($pt) | pieces_of_types!($pos, $($rest_pts),+)
+        $pos.pieces_by_piecetype($pt) | $crate::pieces_of_types!($pos, $($rest_pts),+)
     }
 }
 
 #[macro_export]
 macro_rules! pieces_by_color_and_pt {
     ($pos: expr, $color: expr, $pt: expr) => {
-        pieces_of_types!($pos, $pt)
+        $pos.pieces_by_color($color) & $crate::pieces_of_types!($pos, $pt)
     };
 
     ($pos: expr, $color: expr, $pt: expr, $($rest_pt: expr),+) => {
-        $pos.pieces_by_color($color) & (pieces_by_color_and_pt!($pos, $color, $pt) | pieces_by_color_and_pt!($pos, $color, $($rest_pt),+))
+        $pos.pieces_by_color($color) & $crate::pieces_of_types!($pos, $pt, $($rest_pt),+)
     };
 }
 
 #[macro_export]
 macro_rules! all_pieces {
     ($pos: expr) => {
-        pieces_of_types!($pos, PieceType::AllPieces)
+        $crate::pieces_of_types!($pos, PieceType::AllPieces)
     };
 }
 
@@ -75,8 +79,85 @@ fn H2(h: Key) -> i32 {
     ((h >> 16) & 0x1fff) as i32
 }
 
+//The FEN of chess960 start position n in Scharnagl's numbering, 518 is the standard start
+//position. The bishops come from the lowest digits, then the queen and the knights, and
+//rook, king and rook fill the remaining squares
+pub fn chess960_fen(n: usize) -> Option<String> {
+    if n >= 960 {
+        return None;
+    }
+    const KNIGHTS: [(usize, usize); 10] = [
+        (0, 1),
+        (0, 2),
+        (0, 3),
+        (0, 4),
+        (1, 2),
+        (1, 3),
+        (1, 4),
+        (2, 3),
+        (2, 4),
+        (3, 4),
+    ];
+
+    let mut rank = [' '; FNB];
+    rank[2 * (n % 4) + 1] = 'b';
+    rank[2 * (n / 4 % 4)] = 'b';
+    let mut n = n / 16;
+    //Places pt on the i-th of the squares that are still empty
+    let mut place = |i: usize, pt: char| {
+        let f = (0..FNB).filter(|&f| rank[f] == ' ').nth(i).unwrap();
+        rank[f] = pt;
+    };
+    place(n % 6, 'q');
+    n /= 6;
+    let (n1, n2) = KNIGHTS[n];
+    //The second knight skips over the square the first one took
+    place(n1, 'n');
+    place(n2 - 1, 'n');
+    for pt in ['r', 'k', 'r'] {
+        place(0, pt);
+    }
+
+    let black: String = rank.iter().collect();
+    Some(format!(
+        "{}/pppppppp/8/8/8/8/PPPPPPPP/{} w KQkq - 0 1",
+        black,
+        black.to_ascii_uppercase()
+    ))
+}
+
+//Pieces that changed squares in the last move, up to three for a capturing promotion.
+//SqNone stands for the piece appearing or disappearing
+#[derive(Debug, Copy, Clone)]
+pub struct DirtyPiece {
+    pub dirty_num: usize,
+    pub piece: [Piece; 3],
+    pub from: [Square; 3],
+    pub to: [Square; 3],
+}
+
+impl Default for DirtyPiece {
+    fn default() -> Self {
+        Self {
+            dirty_num: 0,
+            piece: [Piece::NoPiece; 3],
+            from: [Square::SqNone; 3],
+            to: [Square::SqNone; 3],
+        }
+    }
+}
+
+impl DirtyPiece {
+    fn push(&mut self, pc: Piece, from: Square, to: Square) {
+        self.piece[self.dirty_num] = pc;
+        self.from[self.dirty_num] = from;
+        self.to[self.dirty_num] = to;
+        self.dirty_num += 1;
+    }
+}
+
 #[derive(Debug, Copy, Clone, Default)]
-struct StateInfo {
+pub struct StateInfo {
     //Copied when making a move
     material_key: Key,
     pawn_key: Key,
@@ -96,21 +177,31 @@ struct StateInfo {
     check_squares: [Bitboard; PIECE_TYPE_NB],
     captured_piece: Piece,
     repition: i32,
+    //Used by the nnue evaluation
+    dirty_piece: DirtyPiece,
 }
 
 impl StateInfo {
     pub fn copy_from_old_to_new(&self, newst: &mut StateInfo) {
         newst.material_key = self.material_key;
         newst.pawn_key = self.pawn_key;
-        newst.non_pawn_material = self.non_pawn_material.clone();
+        newst.major_piece_key = self.major_piece_key;
+        newst.minor_piece_key = self.minor_piece_key;
+        newst.non_pawn_key = self.non_pawn_key;
+        newst.non_pawn_material = self.non_pawn_material;
         newst.castling_rights = self.castling_rights;
         newst.rule_50 = self.rule_50;
         newst.plies_from_null = self.plies_from_null;
         newst.ep_square = self.ep_square;
     }
+
+    #[inline]
+    pub fn dirty_piece(&self) -> &DirtyPiece {
+        &self.dirty_piece
+    }
 }
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 struct StateStack {
     states: Vec<StateInfo>,
 }
@@ -131,6 +222,57 @@ impl StateStack {
     }
 }
 
+#[derive(Debug, PartialEq, Clone)]
+pub enum FenError {
+    MissingField(&'static str),
+    InvalidPiecePlacement(String),
+    InvalidKingCount,
+    InvalidSideToMove(String),
+    InvalidCastling(String),
+    InvalidEnPassant(String),
+    InvalidHalfmoveClock(String),
+    InvalidFullmoveNumber(String),
+    OpponentInCheck,
+}
+
+impl fmt::Display for FenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FenError::MissingField(field) => write!(f, "missing fen field: {}", field),
+            FenError::InvalidPiecePlacement(s) => write!(f, "invalid piece placement: {}", s),
+            FenError::InvalidKingCount => write!(f, "each side must have exactly one king"),
+            FenError::InvalidSideToMove(s) => write!(f, "invalid side to move: {}", s),
+            FenError::InvalidCastling(s) => write!(f, "invalid castling rights: {}", s),
+            FenError::InvalidEnPassant(s) => write!(f, "invalid en passant square: {}", s),
+            FenError::InvalidHalfmoveClock(s) => write!(f, "invalid halfmove clock: {}", s),
+            FenError::InvalidFullmoveNumber(s) => write!(f, "invalid fullmove number: {}", s),
+            FenError::OpponentInCheck => write!(f, "the side not to move is in check"),
+        }
+    }
+}
+
+impl std::error::Error for FenError {}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum DrawReason {
+    FiftyMoveRule,
+    Repetition,
+    InsufficientMaterial,
+}
+
+//How a game stands in the current position, Checkmate holds the winner
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Outcome {
+    Checkmate(Color),
+    Stalemate,
+    FiftyMove,
+    ThreefoldRepetition,
+    InsufficientMaterial,
+    Ongoing,
+}
+
+//Cloning copies the whole state stack, so a clone can undo the moves made before it was taken
+#[derive(Clone)]
 pub struct Position {
     board: [Piece; SQNB],
     by_type_bb: [Bitboard; PTNB],
@@ -143,10 +285,16 @@ pub struct Position {
     game_ply: i32,
     side_to_move: Color,
     state_idx: usize,
+    chess960: bool,
 }
 
-impl Position {
+impl Default for Position {
     fn default() -> Self {
+        let mut state_stack = StateStack::new();
+        state_stack.push(StateInfo {
+            ep_square: Square::SqNone,
+            ..StateInfo::default()
+        });
         Self {
             board: [Piece::NoPiece; SQNB],
             by_type_bb: [0; PTNB],
@@ -155,11 +303,260 @@ impl Position {
             castling_rights_mask: [CastlingRights::new_from_n(0); SQNB],
             castling_rook_square: [Square::default(); CRNB],
             castling_path: [0; CRNB],
-            state_stack: StateStack::default(),
+            state_stack,
             game_ply: 0,
             side_to_move: Color::White,
             state_idx: 0,
+            chess960: false,
+        }
+    }
+}
+
+impl Position {
+    //Builds the position described by the given FEN string, setting up the bitboard and zobrist
+    //tables first if this is the first position
+    pub fn from_fen(fen: &str) -> Result<Position, FenError> {
+        bb::init();
+        Position::init();
+        let mut pos = Position::default();
+        pos.set(fen)?;
+        Ok(pos)
+    }
+
+    //Same as from_fen for a chess960 game, where castling moves are written as king captures rook
+    pub fn from_chess960_fen(fen: &str) -> Result<Position, FenError> {
+        bb::init();
+        Position::init();
+        let mut pos = Position::default();
+        pos.set_chess960(true);
+        pos.set(fen)?;
+        Ok(pos)
+    }
+
+    //Sets up the position described by the given FEN string. On error the position is left untouched
+    pub fn set(&mut self, fen: &str) -> Result<(), FenError> {
+        let mut pos = Position::default();
+        let mut fields = fen.split_whitespace();
+
+        let placement = fields
+            .next()
+            .ok_or(FenError::MissingField("piece placement"))?;
+        let ranks: Vec<&str> = placement.split('/').collect();
+        if ranks.len() != RNB {
+            return Err(FenError::InvalidPiecePlacement(placement.to_string()));
+        }
+
+        for (i, rank) in ranks.iter().enumerate() {
+            let r = RNB - 1 - i;
+            let mut f = 0;
+            for token in rank.chars() {
+                if let Some(d) = token.to_digit(10) {
+                    if d == 0 || d > 8 {
+                        return Err(FenError::InvalidPiecePlacement(placement.to_string()));
+                    }
+                    f += d as usize;
+                } else if let Some(idx) = PIECE_TO_CHAR.find(token).filter(|_| token != ' ') {
+                    let pc = Piece::new_from_n(idx);
+                    if f >= FNB || (pc.type_of() == PieceType::Pawn && (r == 0 || r == RNB - 1)) {
+                        return Err(FenError::InvalidPiecePlacement(placement.to_string()));
+                    }
+                    pos.put_piece(pc, make_square(f, r));
+                    f += 1;
+                } else {
+                    return Err(FenError::InvalidPiecePlacement(placement.to_string()));
+                }
+            }
+            if f != FNB {
+                return Err(FenError::InvalidPiecePlacement(placement.to_string()));
+            }
+        }
+
+        if pos.piece_count[Piece::WKing as usize] != 1
+            || pos.piece_count[Piece::BKing as usize] != 1
+        {
+            return Err(FenError::InvalidKingCount);
         }
+
+        pos.side_to_move = match fields.next() {
+            Some("w") => Color::White,
+            Some("b") => Color::Black,
+            Some(token) => return Err(FenError::InvalidSideToMove(token.to_string())),
+            None => return Err(FenError::MissingField("side to move")),
+        };
+
+        //Besides KQkq the rook files of Shredder-FEN and X-FEN are accepted, like HAha or Kq
+        let castling = fields.next().ok_or(FenError::MissingField("castling"))?;
+        if castling != "-" {
+            for token in castling.chars() {
+                let c = if token.is_ascii_lowercase() {
+                    Color::Black
+                } else {
+                    Color::White
+                };
+                let rook = make_piece(c, PieceType::Rook);
+                let rsq = match token.to_ascii_lowercase() {
+                    'k' => pos.outermost_rook(c, true),
+                    'q' => pos.outermost_rook(c, false),
+                    f @ 'a'..='h' => Some(make_square(
+                        (f as u8 - b'a') as usize,
+                        Square::SqA1.relative_square(c).rank_of() as usize,
+                    ))
+                    .filter(|&s| pos.piece_on(s) == rook)
+                    .filter(|_| pos.square(c, PieceType::King).relative_rank(c) == Rank::Rank1),
+                    _ => None,
+                };
+                match rsq {
+                    Some(rsq) => pos.set_castling_right(c, rsq),
+                    None => return Err(FenError::InvalidCastling(castling.to_string())),
+                }
+            }
+        }
+
+        let ep = fields.next().ok_or(FenError::MissingField("en passant"))?;
+        if ep != "-" {
+            let ep_rank = if pos.side_to_move == Color::White {
+                Rank::Rank6
+            } else {
+                Rank::Rank3
+            };
+            let ep_square = Square::from_algebraic(ep)
+                .filter(|s| s.rank_of() == ep_rank)
+                .ok_or_else(|| FenError::InvalidEnPassant(ep.to_string()))?;
+
+            //The square must be behind a pawn that just moved two squares, it is only kept if
+            //a pawn can actually capture on it
+            let us = pos.side_to_move;
+            let target =
+                pieces_by_color_and_pt!(pos, !us, PieceType::Pawn) & (ep_square + pawn_push(!us));
+            let vacated = all_pieces!(pos) & (ep_square.bb() | (ep_square + pawn_push(us)));
+            if target == 0 || vacated != 0 {
+                return Err(FenError::InvalidEnPassant(ep.to_string()));
+            }
+            let pawns = bb::get_pawn_attacks_bb(!us, ep_square)
+                & pieces_by_color_and_pt!(pos, us, PieceType::Pawn);
+            if pawns != 0 {
+                pos.st_mut().ep_square = ep_square;
+            }
+        }
+
+        if let Some(token) = fields.next() {
+            pos.st_mut().rule_50 = token
+                .parse::<u32>()
+                .map_err(|_| FenError::InvalidHalfmoveClock(token.to_string()))?
+                as i32;
+        }
+
+        let fullmove = match fields.next() {
+            Some(token) => token
+                .parse::<u32>()
+                .map_err(|_| FenError::InvalidFullmoveNumber(token.to_string()))?
+                as i32,
+            None => 1,
+        };
+        pos.game_ply =
+            std::cmp::max(2 * (fullmove - 1), 0) + (pos.side_to_move == Color::Black) as i32;
+
+        pos.set_state();
+        //Otherwise the side to move could capture the king
+        let them = !pos.side_to_move;
+        let ksq = pos.square(them, PieceType::King);
+        if pos.attackers_to(ksq, all_pieces!(pos)) & pos.by_color_bb[pos.side_to_move as usize] != 0
+        {
+            return Err(FenError::OpponentInCheck);
+        }
+        pos.chess960 = self.chess960;
+        *self = pos;
+        Ok(())
+    }
+
+    //Returns the FEN string describing the current position
+    pub fn fen(&self) -> String {
+        let mut fen = String::new();
+        for r in (0..RNB).rev() {
+            let mut empty = 0;
+            for f in 0..FNB {
+                let pc = self.piece_on(make_square(f, r));
+                if pc == Piece::NoPiece {
+                    empty += 1;
+                    continue;
+                }
+                if empty > 0 {
+                    fen.push_str(&empty.to_string());
+                    empty = 0;
+                }
+                fen.push(PIECE_TO_CHAR.as_bytes()[pc as usize] as char);
+            }
+            if empty > 0 {
+                fen.push_str(&empty.to_string());
+            }
+            if r > 0 {
+                fen.push('/');
+            }
+        }
+
+        fen.push_str(if self.side_to_move == Color::White {
+            " w "
+        } else {
+            " b "
+        });
+
+        let rights = [
+            (CastlingRights::WhiteOO, 'K'),
+            (CastlingRights::WhiteOOO, 'Q'),
+            (CastlingRights::BlackOO, 'k'),
+            (CastlingRights::BlackOOO, 'q'),
+        ];
+        for (cr, token) in rights {
+            if !self.can_castle(cr) {
+                continue;
+            }
+            //Chess960 positions are written as Shredder-FEN with the files of the rooks
+            if self.chess960 {
+                let file = b'a' + self.castling_rook_square(cr).file_of() as u8;
+                let file = if token.is_ascii_uppercase() {
+                    file.to_ascii_uppercase()
+                } else {
+                    file
+                };
+                fen.push(file as char);
+            } else {
+                fen.push(token);
+            }
+        }
+        if !self.can_castle(CastlingRights::AnyCastling) {
+            fen.push('-');
+        }
+
+        fen.push_str(&format!(
+            " {} {} {}",
+            self.ep_square(),
+            self.st().rule_50,
+            1 + (self.game_ply - (self.side_to_move == Color::Black) as i32) / 2
+        ));
+        fen
+    }
+
+    //Finds the rook furthest from the king on its back rank, on the given side of the king
+    fn outermost_rook(&self, c: Color, kingside: bool) -> Option<Square> {
+        let rook = make_piece(c, PieceType::Rook);
+        let ksq = self.square(c, PieceType::King);
+        if ksq.relative_rank(c) != Rank::Rank1 {
+            return None;
+        }
+        let r = ksq.rank_of() as usize;
+        let kf = ksq.file_of() as usize;
+        let mut files: Vec<usize> = if kingside {
+            ((kf + 1)..FNB).collect()
+        } else {
+            (0..kf).collect()
+        };
+        if kingside {
+            files.reverse();
+        }
+        files
+            .into_iter()
+            .map(|f| make_square(f, r))
+            .find(|&s| self.piece_on(s) == rook)
     }
 
     fn st(&self) -> &StateInfo {
@@ -204,8 +601,8 @@ impl Position {
 
         if let Some(between_bb) = BETWEEN_BB.get() {
             self.castling_path[cr as usize] = (between_bb[rfrom as usize][rto as usize]
-                | between_bb[kfrom as usize][rto as usize])
-                & !(kfrom | rfrom as u64); // Can't get why we have to cast here ?
+                | between_bb[kfrom as usize][kto as usize])
+                & !(kfrom.bb() | rfrom.bb());
         } else {
             panic!("Attempted to access BETWEEN_BB prior to initialization when setting castling rights");
         }
@@ -237,38 +634,84 @@ impl Position {
         self.st_mut().blockers_for_king[c as usize] = 0;
         self.st_mut().pinners[!c as usize] = 0;
 
-        let mut snipers: Bitboard = (pseudo_attacks_bb(PieceType::Rook, ksq)
+        let mut snipers: Bitboard = ((pseudo_attacks_bb(PieceType::Rook, ksq)
             & pieces_of_types!(&self, PieceType::Queen, PieceType::Rook))
             | (pseudo_attacks_bb(PieceType::Bishop, ksq)
-                & pieces_of_types!(&self, PieceType::Queen, PieceType::Bishop))
-                & self.pieces_by_color(!c);
+                & pieces_of_types!(&self, PieceType::Queen, PieceType::Bishop)))
+            & self.pieces_by_color(!c);
 
         let occupancy: Bitboard = all_pieces!(self) ^ snipers;
 
         while snipers != 0 {
             let snipers_sq = bb::pop_lsb(&mut snipers);
-            let b: Bitboard = bb::between_bb(snipers_sq, ksq) & occupancy;
+            let b: Bitboard = bb::between_bb(ksq, snipers_sq) & occupancy;
 
-            if b != 0 && bb::more_than_one(b) {
+            if b != 0 && !bb::more_than_one(b) {
                 self.st_mut().blockers_for_king[c as usize] |= b;
-                if b & pieces_by_color_and_pt!(self, c, PieceType::AllPieces) != 0 {
+                if b & self.pieces_by_color(c) != 0 {
                     self.st_mut().pinners[!c as usize] |= snipers_sq;
                 }
             }
         }
     }
 
+    //Computes the hash keys and the check info of the current state from scratch.
+    //Incremental updates are done in do_move, this is only used when setting up a position
     fn set_state(&mut self) {
-        if let Some(nopawns) = zobrist::NOPAWNS.get() {
-            self.st_mut().pawn_key = *nopawns;
-        } else {
-            panic!("Attempted to access zobrist - nopawns before initialization");
+        let zobrist_psq = zobrist::get_zobrist_psq();
+        let us = self.side_to_move;
+
+        let mut st = *self.st();
+        st.pawn_key = zobrist::get_zorist_nopawns();
+        st.key = 0;
+        st.material_key = 0;
+        st.major_piece_key = 0;
+        st.minor_piece_key = 0;
+        st.non_pawn_key = [0; COLORNB];
+        st.non_pawn_material = [0; COLORNB];
+        st.checkers_bb = self.attackers_to(self.square(us, PieceType::King), all_pieces!(self))
+            & self.pieces_by_color(!us);
+
+        let mut b = all_pieces!(self);
+        while b != 0 {
+            let s = bb::pop_lsb(&mut b);
+            let pc = self.piece_on(s);
+            let pt = pc.type_of();
+            st.key ^= zobrist_psq[pc as usize][s as usize];
+
+            if pt == PieceType::Pawn {
+                st.pawn_key ^= zobrist_psq[pc as usize][s as usize];
+            } else {
+                st.non_pawn_key[pc.color() as usize] ^= zobrist_psq[pc as usize][s as usize];
+                if pt == PieceType::King {
+                    st.major_piece_key ^= zobrist_psq[pc as usize][s as usize];
+                    st.minor_piece_key ^= zobrist_psq[pc as usize][s as usize];
+                } else {
+                    st.non_pawn_material[pc.color() as usize] += PIECEVALUE[pc as usize];
+                    if pt == PieceType::Queen || pt == PieceType::Rook {
+                        st.major_piece_key ^= zobrist_psq[pc as usize][s as usize];
+                    } else {
+                        st.minor_piece_key ^= zobrist_psq[pc as usize][s as usize];
+                    }
+                }
+            }
+        }
+
+        if st.ep_square != Square::SqNone {
+            st.key ^= zobrist::get_zobrist_enpassant()[st.ep_square.file_of() as usize];
+        }
+        if us == Color::Black {
+            st.key ^= zobrist::get_zobrist_side();
         }
-        self.st_mut().key = 0;
-        self.st_mut().material_key = 0;
-        self.st_mut().non_pawn_material[Color::White as usize] = 0;
-        self.st_mut().non_pawn_material[Color::Black as usize] = 0;
+        st.key ^= zobrist::get_zobrist_castling()[st.castling_rights as usize];
 
+        for &pc in &pieces {
+            for cnt in 0..self.piece_count[pc as usize] {
+                st.material_key ^= zobrist_psq[pc as usize][cnt as usize];
+            }
+        }
+
+        *self.st_mut() = st;
         self.set_check_info();
     }
 
@@ -286,28 +729,37 @@ impl Position {
             | bb::get_pseudo_attacks(PieceType::King, s) & pieces_of_types!(self, PieceType::King);
     }
 
-    fn legal(self, m: Move) -> bool {
-        assert!(&m.is_ok());
+    //Tests whether a pseudo legal move leaves our king safe
+    pub fn legal(&self, m: Move) -> bool {
+        assert!(m.is_ok());
         let us: Color = self.side_to_move;
         let from = m.from_sq();
         let mut to = m.to_sq();
 
         assert!(self.moved_piece(m).color() == us);
 
+        //En passant removes two pieces from their squares at once, so check for any slider
+        //that would see the king on the resulting board
         if m.type_of() == MoveType::EnPassant {
             let ksq: Square = self.square(us, PieceType::King);
             let capsq: Square = to - pawn_push(us);
             let occupied: Bitboard = (all_pieces!(self) ^ from ^ capsq) | to;
-            return false;
+            return attacks_bb(PieceType::Rook, ksq, occupied)
+                & pieces_by_color_and_pt!(self, !us, PieceType::Queen, PieceType::Rook)
+                == 0
+                && attacks_bb(PieceType::Bishop, ksq, occupied)
+                    & pieces_by_color_and_pt!(self, !us, PieceType::Queen, PieceType::Bishop)
+                    == 0;
         }
 
-        
+        //Castling generation does not check whether the squares the king crosses are attacked
         if m.type_of() == MoveType::Castling {
             to = if to > from {
                 Square::SqG1
             } else {
                 Square::SqC1
-            }.relative_square(us);
+            }
+            .relative_square(us);
 
             let step: Direction = if to > from {
                 Direction::West
@@ -317,31 +769,32 @@ impl Position {
 
             let mut s = to;
             while s != from {
-                if self.attackers_to(s, all_pieces!(self))
-                    & pieces_by_color_and_pt!(self, !us, PieceType::AllPieces)
-                    != 0
-                {
+                if self.attackers_to(s, all_pieces!(self)) & self.pieces_by_color(!us) != 0 {
                     return false;
                 }
                 s += step;
             }
+            //In chess960 the castling rook may be the piece shielding the king from a slider
+            //on the back rank
+            return !self.chess960 || self.blockers_for_king(us) & m.to_sq() == 0;
         }
 
         if self.piece_on(from).type_of() == PieceType::King {
-            return self.attackers_to(to, all_pieces!(self) ^ from)
-                & pieces_by_color_and_pt!(self, c, PieceType::AllPieces)
+            return self.attackers_to(to, all_pieces!(self) ^ from) & self.pieces_by_color(!us)
                 == 0;
         }
 
-        return (self.blockers_for_king(us) & from) == 0
-            || bb::alligned(from, to, self.square(us, PieceType::King));
+        (self.blockers_for_king(us) & from) == 0
+            || bb::alligned(from, to, self.square(us, PieceType::King))
     }
 
     pub fn all_pieces(&self) -> Bitboard {
         return all_pieces!(self);
     }
 
-    fn pseudo_legal(&self, m: Move, state_stack: &StateStack) -> bool {
+    //Checks whether a move taken from somewhere else (the transposition table or a killer slot)
+    //could have been generated in the current position
+    pub fn pseudo_legal(&self, m: Move) -> bool {
         assert!(m.is_ok());
 
         let us: Color = self.side_to_move;
@@ -349,11 +802,21 @@ impl Position {
         let to: Square = m.to_sq();
         let pc: Piece = self.moved_piece(m);
 
+        //Uncommon move types go through the slower but simpler generator lookup
         if m.type_of() != MoveType::Normal {
-            todo!()
+            return if self.checkers() != 0 {
+                generate::<EVASIONS>(self).contains(m)
+            } else {
+                generate::<NON_EVASIONS>(self).contains(m)
+            };
         }
 
-        if pc != Piece::NoPiece || pc.color() != us {
+        //Only promotions carry a promotion piece, a corrupted tt move may not
+        if (m.raw() >> 12) & 3 != 0 {
+            return false;
+        }
+
+        if pc == Piece::NoPiece || pc.color() != us {
             return false;
         }
 
@@ -370,13 +833,15 @@ impl Position {
                 & to
                 == 0)
                 && !(from + pawn_push(us) == to && self.empty(to))
-                && !(from + pawn_push(us) * 2 == to && self.empty(to))
-                && !(relative_rank_of_square(us, from) == Rank::Rank2
+                && !(from + pawn_push(us) * 2 == to
+                    && relative_rank_of_square(us, from) == Rank::Rank2
                     && self.empty(to)
                     && self.empty(to - pawn_push(us)))
             {
                 return false;
             }
+        } else if bb::attacks_bb(pc.type_of(), from, all_pieces!(self)) & to == 0 {
+            return false;
         }
 
         if self.checkers() != 0 {
@@ -402,7 +867,208 @@ impl Position {
         true
     }
 
-    fn gives_check(&self, m: Move) -> bool {
+    //Static exchange evaluation. Tests whether the sequence of captures on the destination
+    //square, each side always recapturing with its least valuable piece, wins at least
+    //threshold for the side to move. Only normal moves are resolved, others count as 0
+    pub fn see_ge(&self, m: Move, threshold: Value) -> bool {
+        assert!(m.is_ok());
+
+        if m.type_of() != MoveType::Normal {
+            return VALUE_ZERO >= threshold;
+        }
+
+        let from = m.from_sq();
+        let to = m.to_sq();
+
+        let mut swap = PIECEVALUE[self.piece_on(to) as usize] - threshold;
+        if swap < 0 {
+            return false;
+        }
+
+        swap = PIECEVALUE[self.piece_on(from) as usize] - swap;
+        if swap <= 0 {
+            return true;
+        }
+
+        //Removing the destination square as well keeps pieces pinned to the king until the
+        //pinner itself gets captured
+        let mut occupied = all_pieces!(self) ^ from ^ to;
+        let mut stm = self.side_to_move;
+        let mut attackers = self.attackers_to(to, occupied);
+        let bishops_queens = pieces_of_types!(self, PieceType::Bishop, PieceType::Queen);
+        let rooks_queens = pieces_of_types!(self, PieceType::Rook, PieceType::Queen);
+        let mut res = true;
+
+        loop {
+            stm = !stm;
+            attackers &= occupied;
+
+            let mut stm_attackers = attackers & self.pieces_by_color(stm);
+            if stm_attackers == 0 {
+                break;
+            }
+
+            //Pinned pieces may not recapture while their pinner is still on the board
+            if self.pinners(!stm) & occupied != 0 {
+                stm_attackers &= !self.blockers_for_king(stm);
+                if stm_attackers == 0 {
+                    break;
+                }
+            }
+
+            res = !res;
+
+            //Remove the least valuable attacker and add the sliders it was hiding
+            let (pt, value) = match [
+                PieceType::Pawn,
+                PieceType::Knight,
+                PieceType::Bishop,
+                PieceType::Rook,
+                PieceType::Queen,
+            ]
+            .into_iter()
+            .find(|&pt| stm_attackers & self.pieces_by_piecetype(pt) != 0)
+            {
+                Some(pt) => (pt, PIECEVALUE[pt as usize]),
+                //Capturing with the king is only possible if the other side has no attackers left
+                None => {
+                    return if attackers & !self.pieces_by_color(stm) != 0 {
+                        !res
+                    } else {
+                        res
+                    }
+                }
+            };
+
+            swap = value - swap;
+            if swap < res as Value {
+                break;
+            }
+            occupied ^=
+                bb::least_significant_square_bb(stm_attackers & self.pieces_by_piecetype(pt));
+
+            if matches!(pt, PieceType::Pawn | PieceType::Bishop | PieceType::Queen) {
+                attackers |= bb::attacks_bb(PieceType::Bishop, to, occupied) & bishops_queens;
+            }
+            if matches!(pt, PieceType::Rook | PieceType::Queen) {
+                attackers |= bb::attacks_bb(PieceType::Rook, to, occupied) & rooks_queens;
+            }
+        }
+
+        res
+    }
+
+    //The exact material outcome of the exchange started by m, the largest threshold that
+    //see_ge still accepts. It always lies between the loss of the moving piece and the gain
+    //of the captured one
+    pub fn see(&self, m: Move) -> Value {
+        let (mut lo, mut hi) = (-QueenValue, QueenValue);
+        while lo < hi {
+            let mid = lo + (hi - lo + 1) / 2;
+            if self.see_ge(m, mid) {
+                lo = mid;
+            } else {
+                hi = mid - 1;
+            }
+        }
+        lo
+    }
+
+    //Whether the position is a draw and why. Inside the search tree ply plies deep, a single
+    //repetition after the root is enough, positions before the root have to occur three times
+    pub fn is_draw(&self, ply: i32) -> Option<DrawReason> {
+        //Being checkmated on the hundredth half move still loses
+        if self.st().rule_50 > 99 && (self.checkers() == 0 || !generate::<LEGAL>(self).is_empty()) {
+            return Some(DrawReason::FiftyMoveRule);
+        }
+
+        let repetition = self.st().repition;
+        if repetition != 0 && repetition < ply {
+            return Some(DrawReason::Repetition);
+        }
+
+        if self.insufficient_material() {
+            return Some(DrawReason::InsufficientMaterial);
+        }
+        None
+    }
+
+    //The result of the game if it is over. Unlike inside the search, a repetition only ends the
+    //game on the third occurrence of the position
+    pub fn outcome(&self) -> Outcome {
+        if generate::<LEGAL>(self).is_empty() {
+            return if self.checkers() != 0 {
+                Outcome::Checkmate(!self.side_to_move)
+            } else {
+                Outcome::Stalemate
+            };
+        }
+
+        match self.is_draw(0) {
+            Some(DrawReason::FiftyMoveRule) => Outcome::FiftyMove,
+            Some(DrawReason::Repetition) => Outcome::ThreefoldRepetition,
+            Some(DrawReason::InsufficientMaterial) => Outcome::InsufficientMaterial,
+            None => Outcome::Ongoing,
+        }
+    }
+
+    //Whether the side to move can repeat an earlier position with a single reversible move.
+    //Earlier positions after the root count once, the ones before it have to be repetitions
+    //already, like in is_draw
+    pub fn upcoming_repetition(&self, ply: i32) -> bool {
+        let end = std::cmp::min(self.st().rule_50, self.st().plies_from_null) as usize;
+        if end < 3 {
+            return false;
+        }
+
+        let cuckoo = CUCKOO.get().expect("cuckoo tables are not initialized");
+        let cuckoo_move = CUCKOO_MOVE.get().unwrap();
+        let zside = zobrist::get_zobrist_side();
+        let states = &self.state_stack.states;
+        let original_key = self.st().key;
+        //other is zero when the pieces of the side not to move are back where they were
+        let mut other = original_key ^ states[self.state_idx - 1].key ^ zside;
+
+        for i in (3..=end).step_by(2) {
+            let stp = &states[self.state_idx - i];
+            other ^= states[self.state_idx - i + 1].key ^ stp.key ^ zside;
+            if other != 0 {
+                continue;
+            }
+
+            //Only a single move of ours differs, look it up among all reversible moves
+            let move_key = original_key ^ stp.key;
+            let j = [H1(move_key), H2(move_key)]
+                .into_iter()
+                .find(|&j| cuckoo[j as usize] == move_key);
+            if let Some(j) = j {
+                let m = cuckoo_move[j as usize];
+                let (s1, s2) = (m.from_sq(), m.to_sq());
+                if (bb::between_bb(s1, s2) ^ s2) & all_pieces!(self) == 0
+                    && (ply > i as i32 || stp.repition != 0)
+                {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    //Neither side can ever mate: bare kings plus at most one minor piece, or only bishops that
+    //all stand on squares of the same color
+    pub fn insufficient_material(&self) -> bool {
+        if pieces_of_types!(self, PieceType::Pawn, PieceType::Rook, PieceType::Queen) != 0 {
+            return false;
+        }
+        if !more_than_one(pieces_of_types!(self, PieceType::Knight, PieceType::Bishop)) {
+            return true;
+        }
+        let bishops = self.pieces_by_piecetype(PieceType::Bishop);
+        self.pieces_by_piecetype(PieceType::Knight) == 0
+            && (bishops & bb::DARKSQUARES == 0 || bishops & !bb::DARKSQUARES == 0)
+    }
+
+    pub fn gives_check(&self, m: Move) -> bool {
         assert!(m.is_ok());
         assert!(self.moved_piece(m).color() == self.side_to_move);
         let from: Square = m.from_sq();
@@ -435,7 +1101,7 @@ impl Position {
                     b,
                 ) & pieces_by_color_and_pt!(
                     self,
-                    !self.side_to_move,
+                    self.side_to_move,
                     PieceType::Queen,
                     PieceType::Rook
                 )) | (attacks_bb(
@@ -474,15 +1140,16 @@ impl Position {
 
         //Setting up to add the new state
         self.st().copy_from_old_to_new(new_state);
-        self.state_idx = self.state_stack.states.len();
+        new_state.dirty_piece = DirtyPiece::default();
         self.state_stack.push(*new_state);
+        self.state_idx = self.state_stack.states.len() - 1;
         self.game_ply += 1;
         self.st_mut().rule_50 += 1;
         self.st_mut().plies_from_null += 1;
 
         let us: Color = self.side_to_move;
         let them: Color = !us;
-        let mut from: Square = m.from_sq();
+        let from: Square = m.from_sq();
         let mut to: Square = m.to_sq();
         let pc: Piece = self.piece_on(from);
 
@@ -496,15 +1163,27 @@ impl Position {
         };
 
         assert!(pc.color() == us);
-        assert!(captured.type_of() != PieceType::King);
+        assert!(captured == Piece::NoPiece || captured.type_of() != PieceType::King);
 
-        //Handle Castling
+        //The moved piece always comes first in the dirty piece list
+        if m.type_of() != MoveType::Castling {
+            self.st_mut().dirty_piece.push(pc, from, to);
+        }
+
+        //Handle Castling. The move is encoded as the king capturing its own rook
         if m.type_of() == MoveType::Castling {
-            let mut rfrom: Square = Square::SqNone;
-            let mut rto: Square = Square::SqNone;
-            self.do_castling(us, &mut from, &mut to, &mut rfrom, &mut rto);
-            k ^= zobrist_psq[captured as usize][rfrom as usize]
+            assert!(pc == make_piece(us, PieceType::King));
+            assert!(captured == make_piece(us, PieceType::Rook));
+
+            let (rfrom, rto) = self.do_castling::<true>(us, from, &mut to);
+            let dp = &mut self.st_mut().dirty_piece;
+            dp.push(pc, from, to);
+            dp.push(captured, rfrom, rto);
+            let rook_key = zobrist_psq[captured as usize][rfrom as usize]
                 ^ zobrist_psq[captured as usize][rto as usize];
+            k ^= rook_key;
+            self.st_mut().non_pawn_key[us as usize] ^= rook_key;
+            self.st_mut().major_piece_key ^= rook_key;
             captured = Piece::NoPiece;
         }
 
@@ -519,16 +1198,20 @@ impl Position {
                 };
                 self.st_mut().pawn_key ^= zobrist_psq[captured as usize][capsq as usize];
             } else {
+                let captured_type = captured.type_of();
                 self.st_mut().non_pawn_material[them as usize] -= PIECEVALUE[captured as usize];
                 self.st_mut().non_pawn_key[them as usize] ^=
                     zobrist_psq[captured as usize][capsq as usize];
-                if pc.type_of() == PieceType::Queen || pc.type_of() == PieceType::Rook {
+                if captured_type == PieceType::Queen || captured_type == PieceType::Rook {
                     self.st_mut().major_piece_key ^= zobrist_psq[captured as usize][capsq as usize];
                 } else {
                     self.st_mut().minor_piece_key ^= zobrist_psq[captured as usize][capsq as usize];
                 }
             }
             self.remove_piece(capsq);
+            self.st_mut()
+                .dirty_piece
+                .push(captured, capsq, Square::SqNone);
             k ^= zobrist_psq[captured as usize][capsq as usize];
             self.st_mut().material_key ^=
                 zobrist_psq[captured as usize][self.piece_count[captured as usize] as usize];
@@ -562,22 +1245,22 @@ impl Position {
         //Special Hnadling if the Moved. Handle Promotion and Some Enpassant Stuff.
         if pc.type_of() == PieceType::Pawn {
             if to as i32 ^ from as i32 == 16
-            // todo: I Don't like the cast to u64 when calling pawn_push here
-                && (pawn_attacks_bb((to - pawn_push(us)) as u64, us)
+                && (bb::get_pawn_attacks_bb(us, to - pawn_push(us))
                     & pieces_by_color_and_pt!(self, them, PieceType::Pawn)
                     != 0)
             {
                 self.st_mut().ep_square = to - pawn_push(us);
                 k ^= zobrist_enpassant[self.st().ep_square.file_of() as usize];
-            }
-
-            if m.type_of() == MoveType::Promotion {
+            } else if m.type_of() == MoveType::Promotion {
                 let promotion: Piece = make_piece(us, m.promotion_type());
                 let promotion_type: PieceType = promotion.type_of();
 
                 //Remove the Pawn and add the New Piece
                 self.remove_piece(to);
                 self.put_piece(promotion, to);
+                let dp = &mut self.st_mut().dirty_piece;
+                dp.to[0] = Square::SqNone;
+                dp.push(promotion, Square::SqNone, to);
 
                 //Update the Hash Keys
                 k ^= zobrist_psq[pc as usize][to as usize]
@@ -587,6 +1270,8 @@ impl Position {
                 self.st_mut().material_key ^= zobrist_psq[promotion as usize]
                     [self.piece_count[promotion as usize] as usize - 1]
                     ^ zobrist_psq[pc as usize][self.piece_count[pc as usize] as usize];
+                self.st_mut().non_pawn_key[us as usize] ^=
+                    zobrist_psq[promotion as usize][to as usize];
                 if promotion_type == PieceType::Queen || promotion_type == PieceType::Rook {
                     self.st_mut().major_piece_key ^= zobrist_psq[promotion as usize][to as usize];
                 } else {
@@ -621,24 +1306,33 @@ impl Position {
         self.st_mut().key = k;
 
         self.st_mut().checkers_bb = if gives_check {
-            self.attackers_to(
-                self.square(them, PieceType::King),
-                pieces_by_color_and_pt!(self, us, PieceType::AllPieces),
-            )
+            self.attackers_to(self.square(them, PieceType::King), all_pieces!(self))
+                & self.pieces_by_color(us)
         } else {
             0
         };
 
         self.side_to_move = !self.side_to_move;
-        //Todo:: Test this Function Fully.
         self.set_check_info();
-        self.st_mut().repition = 0;
-
-        //Three-Fold Repitition
-        let end = std::cmp::min(self.st().rule_50, self.st().plies_from_null);
 
-        if end >= 4 {
-            todo!()
+        //Three-Fold Repitition. Find the last state with the same key, if any, stepping back
+        //two plies at a time. A negative distance marks that the earlier state was itself a repetition
+        self.st_mut().repition = 0;
+        let end = std::cmp::min(self.st().rule_50, self.st().plies_from_null) as usize;
+
+        let mut i = 4;
+        while i <= end {
+            let stp = &self.state_stack.states[self.state_idx - i];
+            if stp.key == k {
+                let repition = if stp.repition != 0 {
+                    -(i as i32)
+                } else {
+                    i as i32
+                };
+                self.st_mut().repition = repition;
+                break;
+            }
+            i += 2;
         }
     }
 
@@ -647,20 +1341,22 @@ impl Position {
 
         self.side_to_move = !self.side_to_move;
         let us: Color = self.side_to_move;
-        let mut from: Square = mv.from_sq();
+        let from: Square = mv.from_sq();
         let mut to: Square = mv.to_sq();
 
         assert!(self.empty(from) || mv.type_of() == MoveType::Castling);
-        assert!(self.st().captured_piece.type_of() != PieceType::King);
+        assert!(
+            self.st().captured_piece == Piece::NoPiece
+                || self.st().captured_piece.type_of() != PieceType::King
+        );
 
         if mv.type_of() == MoveType::Promotion {
             self.remove_piece(to);
-            let pc = make_piece(us, PieceType::Pawn);
-            self.put_piece(pc, from);
-        } else if mv.type_of() == MoveType::Castling {
-            let mut rfrom: Square = Square::SqNone;
-            let mut rto: Square = Square::SqNone;
-            self.undo_castling(us, &mut from, &mut to, &mut rfrom, &mut rto);
+            self.put_piece(make_piece(us, PieceType::Pawn), to);
+        }
+
+        if mv.type_of() == MoveType::Castling {
+            self.do_castling::<false>(us, from, &mut to);
         } else {
             self.move_piece(to, from);
 
@@ -672,68 +1368,54 @@ impl Position {
                 self.put_piece(self.st().captured_piece, capsq);
             }
         }
+        self.state_stack.pop();
         self.state_idx -= 1;
         self.game_ply -= 1;
     }
 
-    fn do_castling(
+    //Moves the king and the rook for a castling move, or puts them back when undoing it.
+    //Sets `to` to the king destination and returns the rook origin and destination
+    fn do_castling<const DO: bool>(
         &mut self,
-        side: Color,
-        from: &mut Square,
+        us: Color,
+        from: Square,
         to: &mut Square,
-        rfrom: &mut Square,
-        rto: &mut Square,
-    ) {
-        let kingside: bool = to > from;
-        *rfrom = *to;
-        *rto = if kingside {
-            Square::SqF1.relative_square(side)
+    ) -> (Square, Square) {
+        let kingside: bool = *to > from;
+        let rfrom = *to;
+        let rto = if kingside {
+            Square::SqF1.relative_square(us)
         } else {
-            Square::SqD1.relative_square(side)
+            Square::SqD1.relative_square(us)
         };
 
         *to = if kingside {
-            Square::SqG1.relative_square(side)
+            Square::SqG1.relative_square(us)
         } else {
-            Square::SqC1.relative_square(side)
+            Square::SqC1.relative_square(us)
         };
 
-        self.remove_piece(*from);
-        self.remove_piece(*rfrom);
-        self.board[*from as usize] = Piece::NoPiece;
-        self.board[*rfrom as usize] = Piece::NoPiece;
-        self.put_piece(make_piece(side, PieceType::King), *to);
-        self.put_piece(make_piece(side, PieceType::King), *to);
+        //Remove both pieces first since the squares could overlap in chess960
+        self.remove_piece(if DO { from } else { *to });
+        self.remove_piece(if DO { rfrom } else { rto });
+        self.put_piece(make_piece(us, PieceType::King), if DO { *to } else { from });
+        self.put_piece(
+            make_piece(us, PieceType::Rook),
+            if DO { rto } else { rfrom },
+        );
+        (rfrom, rto)
     }
 
-    fn undo_castling(
-        &mut self,
-        side: Color,
-        from: &mut Square,
-        to: &mut Square,
-        rfrom: &mut Square,
-        rto: &mut Square,
-    ) {
-        let kingside: bool = to > from;
-        *rfrom = *to;
-        *rto = if kingside {
-            Square::SqF1.relative_square(side)
-        } else {
-            Square::SqD1.relative_square(side)
-        };
-
-        *to = if kingside {
-            Square::SqG1.relative_square(side)
-        } else {
-            Square::SqC1.relative_square(side)
-        };
+    //The states from the root of the game up to the current one, for incremental updates that
+    //need to look back at earlier moves
+    #[inline]
+    pub fn states(&self) -> &[StateInfo] {
+        &self.state_stack.states[..=self.state_idx]
+    }
 
-        self.remove_piece(*to);
-        self.remove_piece(*rto);
-        self.board[*to as usize] = Piece::NoPiece;
-        self.board[*rto as usize] = Piece::NoPiece;
-        self.put_piece(make_piece(side, PieceType::King), *from);
-        self.put_piece(make_piece(side, PieceType::King), *rfrom);
+    #[inline]
+    pub fn state(&self) -> &StateInfo {
+        self.st()
     }
 
     #[inline]
@@ -750,8 +1432,26 @@ impl Position {
         self.side_to_move
     }
 
+    pub fn is_chess960(&self) -> bool {
+        self.chess960
+    }
+
+    pub fn set_chess960(&mut self, chess960: bool) {
+        self.chess960 = chess960;
+    }
+
+    //Finds the legal move written in uci coordinate notation, castling is written as king
+    //captures rook in chess960 and as the king's two square move otherwise
+    pub fn parse_uci_move(&self, s: &str) -> Option<Move> {
+        //Some guis send the promotion piece in upper case
+        let s = s.to_ascii_lowercase();
+        generate::<LEGAL>(self)
+            .iter()
+            .find(|m| m.to_uci(self.chess960) == s)
+    }
+
     #[inline]
-    fn piece_on(&self, s: Square) -> Piece {
+    pub fn piece_on(&self, s: Square) -> Piece {
         self.board[s as usize]
     }
 
@@ -760,8 +1460,22 @@ impl Position {
         self.board[s as usize] == Piece::NoPiece
     }
 
+    //Castling is encoded as king captures rook, so it has to be excluded explicitly
+    #[inline]
+    pub fn capture(&self, m: Move) -> bool {
+        (!self.empty(m.to_sq()) && m.type_of() != MoveType::Castling)
+            || m.type_of() == MoveType::EnPassant
+    }
+
+    //Moves that are generated by GenType::Captures: captures and queen promotions
+    #[inline]
+    pub fn capture_stage(&self, m: Move) -> bool {
+        self.capture(m)
+            || (m.type_of() == MoveType::Promotion && m.promotion_type() == PieceType::Queen)
+    }
+
     #[inline]
-    fn moved_piece(&self, m: Move) -> Piece {
+    pub fn moved_piece(&self, m: Move) -> Piece {
         self.piece_on(m.from_sq())
     }
 
@@ -781,10 +1495,15 @@ impl Position {
     }
 
     #[inline]
-    fn can_castle(&self, cr: CastlingRights) -> bool {
+    pub fn can_castle(&self, cr: CastlingRights) -> bool {
         self.st().castling_rights as i32 & cr as i32 != 0
     }
 
+    #[inline]
+    pub fn castling_rook_square(&self, cr: CastlingRights) -> Square {
+        self.castling_rook_square[cr as usize]
+    }
+
     #[inline]
     pub fn checkers(&self) -> Bitboard {
         self.st().checkers_bb
@@ -801,27 +1520,52 @@ impl Position {
     }
 
     #[inline]
-    fn check_squares(&self, pt: PieceType) -> Bitboard {
+    pub fn castling_impeded(&self, cr: CastlingRights) -> bool {
+        all_pieces!(self) & self.castling_path[cr as usize] != 0
+    }
+
+    #[inline]
+    pub fn check_squares(&self, pt: PieceType) -> Bitboard {
         self.st().check_squares[pt as usize]
     }
 
     #[inline]
-    fn pawn_key(&self) -> Key {
+    pub fn key(&self) -> Key {
+        self.st().key
+    }
+
+    #[inline]
+    pub fn pawn_key(&self) -> Key {
         self.st().pawn_key
     }
 
+    #[inline]
+    pub fn minor_piece_key(&self) -> Key {
+        self.st().minor_piece_key
+    }
+
+    #[inline]
+    pub fn major_piece_key(&self) -> Key {
+        self.st().major_piece_key
+    }
+
+    #[inline]
+    pub fn non_pawn_key(&self, c: Color) -> Key {
+        self.st().non_pawn_key[c as usize]
+    }
+
     #[inline]
     fn material_key(&self) -> Key {
         self.st().material_key
     }
 
     #[inline]
-    fn non_pawn_material(&self, c: Color) -> Value {
+    pub fn non_pawn_material(&self, c: Color) -> Value {
         self.st().non_pawn_material[c as usize]
     }
 
     #[inline]
-    fn game_ply(&self) -> i32 {
+    pub fn game_ply(&self) -> i32 {
         self.game_ply
     }
 
@@ -835,32 +1579,38 @@ impl Position {
         self.st().captured_piece
     }
 
+    //The piece count of all pieces of a color lives in the slot of make_piece(c, AllPieces)
+    #[inline]
+    const fn all_pieces_count_idx(c: Color) -> usize {
+        (c as usize) << 3
+    }
+
     fn put_piece(&mut self, pc: Piece, s: Square) {
         let pt = pc.type_of();
         self.board[s as usize] = pc;
         self.by_type_bb[pt as usize] |= s;
-        self.by_type_bb[PieceType::AllPieces as usize] |= self.by_type_bb[pt as usize];
+        self.by_type_bb[PieceType::AllPieces as usize] |= s;
         self.by_color_bb[pc.color() as usize] |= s;
         self.piece_count[pc as usize] += 1;
-        self.piece_count[make_piece(pc.color(), pc.type_of()) as usize] += 1;
+        self.piece_count[Self::all_pieces_count_idx(pc.color())] += 1;
     }
 
     fn remove_piece(&mut self, s: Square) {
         let pc = self.board[s as usize];
-        self.by_type_bb[(PieceType::AllPieces as i32 + 1) as usize] ^= s;
+        self.by_type_bb[PieceType::AllPieces as usize] ^= s;
         self.by_type_bb[pc.type_of() as usize] ^= s;
         self.by_color_bb[pc.color() as usize] ^= s;
         self.board[s as usize] = Piece::NoPiece;
         self.piece_count[pc as usize] -= 1;
-        self.piece_count[make_piece(pc.color(), pc.type_of()) as usize] -= 1;
+        self.piece_count[Self::all_pieces_count_idx(pc.color())] -= 1;
     }
 
     fn move_piece(&mut self, f: Square, t: Square) {
         let pc = self.board[f as usize];
         let from_to: Bitboard = f.bb() | t;
-        self.by_type_bb[(PieceType::AllPieces as i32 + 1) as usize] ^= from_to;
+        self.by_type_bb[PieceType::AllPieces as usize] ^= from_to;
         self.by_type_bb[pc.type_of() as usize] ^= from_to;
-        self.by_type_bb[pc.color() as usize] ^= from_to;
+        self.by_color_bb[pc.color() as usize] ^= from_to;
         self.board[f as usize] = Piece::NoPiece;
         self.board[t as usize] = pc;
     }
@@ -892,6 +1642,10 @@ impl Position {
             panic!("Error Initializing zobrist Nopawns table");
         }
 
+        if CUCKOO.get().is_some() {
+            return;
+        }
+
         let mut cuckoo: [Key; 8192] = [0; 8192];
         let mut cuckoomove: [Move; 8192] = [Move::none(); 8192];
         let zpsq = zobrist::get_zobrist_psq();
@@ -902,8 +1656,7 @@ impl Position {
                 let s1 = Square::new_from_n(i as i32);
                 for j in i + 1..=SQH8 {
                     let s2 = Square::new_from_n(j as i32);
-                    if (pc.type_of() as usize != Piece::BPawn as usize
-                        && pc.type_of() as usize != Piece::BPawn as usize)
+                    if pc.type_of() != PieceType::Pawn
                         && (bb::attacks_bb(pc.type_of(), s1, 0) & s2) != 0
                     {
                         let mut mv = Move::new_from_to_sq(s1, s2);
@@ -923,6 +1676,8 @@ impl Position {
             }
         }
         assert!(count == 3668);
+        CUCKOO.get_or_init(|| cuckoo);
+        CUCKOO_MOVE.get_or_init(|| cuckoomove);
     }
 
     #[inline]
@@ -948,6 +1703,7 @@ impl fmt::Display for Position {
     }
 }
 
+#[cfg(test)]
 mod test {
     use super::*;
 
@@ -1047,6 +1803,15 @@ mod test {
 
         position.do_move(ef, newst, false);
         println!("3..EF\n{}", &position);
+        assert_eq!(position.piece_on(Square::SqF6), Piece::WPawn);
+        assert!(position.empty(Square::SqF5));
+
+        position.undo_move(ef);
+        assert_eq!(position.piece_on(Square::SqF5), Piece::BPawn);
+        position.undo_move(f7f5);
+        position.undo_move(e4e5);
+        position.undo_move(d7d5);
+        position.undo_move(e2e4);
     }
 
     fn test_normal_move_do_undo(
@@ -1077,8 +1842,14 @@ mod test {
         position.undo_move(nxd5);
         position.undo_move(d4);
         position.undo_move(nf6);
+        position.undo_move(ed);
+        position.undo_move(d5);
+        position.undo_move(e4);
 
         println!("{}", position);
+        assert_eq!(position.piece_on(Square::SqE2), Piece::WPawn);
+        assert_eq!(position.piece_on(Square::SqD7), Piece::BPawn);
+        assert_eq!(position.piece_on(Square::SqG8), Piece::BKnight);
     }
 
     fn test_promotion_moves(position: &mut Position, newst: &mut StateInfo, st: &mut StateStack) {
@@ -1145,13 +1916,607 @@ mod test {
         position.undo_move(promote_capture);
     }
 
+    const FENS: [&str; 6] = [
+        START_FEN,
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+        "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+        "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 b - - 13 10",
+    ];
+
+    fn init_tables() {
+        bb::init();
+        zobrist::init_zobrist();
+    }
+
+    #[test]
+    fn test_fen_round_trip() {
+        init_tables();
+        for fen in FENS {
+            let mut position = Position::default();
+            position.set(fen).unwrap();
+            assert_eq!(position.fen(), fen);
+        }
+    }
+
+    #[test]
+    fn test_fen_fills_position() {
+        init_tables();
+        let mut position = Position::default();
+        position.set(FENS[4]).unwrap();
+
+        assert_eq!(position.side_to_move(), Color::White);
+        assert_eq!(position.ep_square(), Square::SqF6);
+        assert_eq!(position.game_ply(), 4);
+        assert_eq!(position.piece_on(Square::SqE5), Piece::WPawn);
+        assert_eq!(position.piece_count[Piece::WPawn as usize], 8);
+        assert_eq!(position.piece_count[Piece::BPawn as usize], 8);
+        assert_eq!(all_pieces!(position).count_ones(), 32);
+        assert!(position.can_castle(CastlingRights::AnyCastling));
+        assert_eq!(
+            position.non_pawn_material(Color::White),
+            position.non_pawn_material(Color::Black)
+        );
+        assert_eq!(position.checkers(), 0);
+
+        //An en passant square nobody can capture on is dropped
+        position
+            .set("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1")
+            .unwrap();
+        assert_eq!(position.ep_square(), Square::SqNone);
+
+        //Optional move counters default to the start of the game
+        position.set("4k3/8/8/8/8/8/8/4K2R w K -").unwrap();
+        assert_eq!(position.fen(), "4k3/8/8/8/8/8/8/4K2R w K - 0 1");
+    }
+
+    #[test]
+    fn test_fen_keys() {
+        init_tables();
+        let mut a = Position::default();
+        let mut b = Position::default();
+        a.set(FENS[0]).unwrap();
+        b.set("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR b KQkq - 0 1")
+            .unwrap();
+        assert_ne!(a.st().key, b.st().key);
+        assert_eq!(a.st().key ^ b.st().key, zobrist::get_zobrist_side());
+        assert_eq!(a.pawn_key(), b.pawn_key());
+        assert_eq!(a.material_key(), b.material_key());
+
+        b.set("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w Kkq - 0 1")
+            .unwrap();
+        assert_ne!(a.st().key, b.st().key);
+        assert_eq!(a.st().non_pawn_key, b.st().non_pawn_key);
+
+        b.set(FENS[1]).unwrap();
+        assert_eq!(a.material_key(), b.material_key());
+        assert_ne!(a.pawn_key(), b.pawn_key());
+        assert_ne!(a.st().minor_piece_key, b.st().minor_piece_key);
+
+        b.set(FENS[2]).unwrap();
+        assert_ne!(a.material_key(), b.material_key());
+    }
+
+    //The keys updated in do_move match the ones computed from scratch
+    #[test]
+    fn test_incremental_keys() {
+        init_tables();
+        fn keys(pos: &Position) -> [Key; 6] {
+            [
+                pos.key(),
+                pos.pawn_key(),
+                pos.minor_piece_key(),
+                pos.major_piece_key(),
+                pos.non_pawn_key(Color::White),
+                pos.non_pawn_key(Color::Black),
+            ]
+        }
+        fn walk(pos: &mut Position, depth: i32) {
+            let mut fresh = Position::default();
+            fresh.set(&pos.fen()).unwrap();
+            assert_eq!(keys(pos), keys(&fresh), "{}", pos.fen());
+            if depth == 0 {
+                return;
+            }
+            for m in generate::<LEGAL>(pos).iter() {
+                let mut st = StateInfo::default();
+                let gives_check = pos.gives_check(m);
+                pos.do_move(m, &mut st, gives_check);
+                walk(pos, depth - 1);
+                pos.undo_move(m);
+            }
+        }
+
+        for fen in FENS {
+            let mut pos = Position::default();
+            pos.set(fen).unwrap();
+            walk(&mut pos, 2);
+        }
+    }
+
+    #[test]
+    fn test_is_draw() {
+        init_tables();
+        let mut position = Position::default();
+
+        //Fifty move rule, unless the last move mated
+        position.set("7k/6Q1/6K1/8/8/8/8/8 b - - 100 80").unwrap();
+        assert_eq!(position.is_draw(0), None);
+        position.set("7k/6Q1/8/6K1/8/8/8/8 b - - 100 80").unwrap();
+        assert_eq!(position.is_draw(0), Some(DrawReason::FiftyMoveRule));
+        position.set("7k/6Q1/8/6K1/8/8/8/8 b - - 99 80").unwrap();
+        assert_eq!(position.is_draw(0), None);
+
+        //Insufficient material
+        for (fen, draw) in [
+            ("8/8/4k3/8/8/4K3/8/8 w - - 0 1", true),
+            ("8/8/4k3/8/8/3NK3/8/8 w - - 0 1", true),
+            ("8/8/4k3/8/8/3BK3/8/5b2 w - - 0 1", true),
+            ("8/8/4k3/8/8/3BK3/5b2/8 w - - 0 1", false),
+            ("8/8/4k3/8/8/3NK3/5b2/8 w - - 0 1", false),
+            ("8/8/4k3/8/8/2NNK3/8/8 w - - 0 1", false),
+            ("8/8/4k3/8/8/4K3/4P3/8 w - - 0 1", false),
+        ] {
+            position.set(fen).unwrap();
+            let expected = draw.then_some(DrawReason::InsufficientMaterial);
+            assert_eq!(position.is_draw(0), expected, "{}", fen);
+        }
+
+        //Repetitions. A position seen once since the root is a draw, before the root it has to
+        //be the third occurrence
+        position.set(START_FEN).unwrap();
+        let mut states = Vec::new();
+        for (i, uci) in [
+            "g1f3", "g8f6", "f3g1", "f6g8", "g1f3", "g8f6", "f3g1", "f6g8",
+        ]
+        .iter()
+        .enumerate()
+        {
+            let m = generate::<LEGAL>(&position)
+                .iter()
+                .find(|m| m.to_string() == *uci)
+                .unwrap();
+            states.push(StateInfo::default());
+            let gives_check = position.gives_check(m);
+            position.do_move(m, states.last_mut().unwrap(), gives_check);
+            //Searched from the start position, the root itself does not count as the earlier
+            //occurrence but every later one does
+            let from_start = (i >= 4).then_some(DrawReason::Repetition);
+            assert_eq!(position.is_draw(i as i32 + 1), from_start);
+            //Searched from the position itself, only the third occurrence counts
+            let at_root = (i == 7).then_some(DrawReason::Repetition);
+            assert_eq!(position.is_draw(0), at_root);
+        }
+    }
+
+    #[test]
+    fn test_parse_uci_move() {
+        init_tables();
+        let mut position = Position::default();
+        position
+            .set("r3k2r/1P6/8/3pP3/8/8/8/R3K2R w KQkq d6 0 1")
+            .unwrap();
+
+        let m = position.parse_uci_move("e1g1").unwrap();
+        assert_eq!(m.type_of(), MoveType::Castling);
+        assert_eq!(m.to_sq(), Square::SqH1);
+        assert_eq!(
+            position.parse_uci_move("e1c1").unwrap().to_sq(),
+            Square::SqA1
+        );
+        //King captures rook is only understood in chess960
+        assert_eq!(position.parse_uci_move("e1h1"), None);
+
+        let m = position.parse_uci_move("e5d6").unwrap();
+        assert_eq!(m.type_of(), MoveType::EnPassant);
+
+        let m = position.parse_uci_move("b7a8N").unwrap();
+        assert_eq!(m.type_of(), MoveType::Promotion);
+        assert_eq!(m.promotion_type(), PieceType::Knight);
+        //A promotion needs its piece
+        assert_eq!(position.parse_uci_move("b7b8"), None);
+
+        //Illegal and malformed moves
+        for s in ["e1e3", "a8a7", "e5e6x", "e2e4", "", "0000", "z9a1"] {
+            assert_eq!(position.parse_uci_move(s), None, "{}", s);
+        }
+
+        position.set_chess960(true);
+        position
+            .set("r3k2r/1P6/8/3pP3/8/8/8/R3K2R w KQkq d6 0 1")
+            .unwrap();
+        assert!(position.is_chess960());
+        let m = position.parse_uci_move("e1h1").unwrap();
+        assert_eq!(m.type_of(), MoveType::Castling);
+        assert_eq!(m.to_uci(position.is_chess960()), "e1h1");
+        assert_eq!(position.parse_uci_move("e1g1"), None);
+    }
+
+    #[test]
+    fn test_chess960() {
+        init_tables();
+        assert_eq!(
+            chess960_fen(0).unwrap(),
+            "bbqnnrkr/pppppppp/8/8/8/8/PPPPPPPP/BBQNNRKR w KQkq - 0 1"
+        );
+        assert_eq!(chess960_fen(518).unwrap(), START_FEN);
+        assert_eq!(chess960_fen(960), None);
+
+        let mut position = Position::default();
+        let mut back_ranks = std::collections::HashSet::new();
+        for n in 0..960 {
+            let fen = chess960_fen(n).unwrap();
+            position.set(&fen).unwrap();
+            let bishops = position.pieces_by_piecetype(PieceType::Bishop);
+            assert!(bishops & bb::DARKSQUARES != 0 && bishops & !bb::DARKSQUARES != 0);
+            let ksq = position.square(Color::White, PieceType::King);
+            assert!(position.castling_rook_square(CastlingRights::WhiteOOO) < ksq);
+            assert!(position.castling_rook_square(CastlingRights::WhiteOO) > ksq);
+            back_ranks.insert(fen[..8].to_string());
+        }
+        assert_eq!(back_ranks.len(), 960);
+
+        //X-FEN names inner rooks by their file, Shredder-FEN names every rook that way
+        let xfen = "1r2k1r1/8/8/8/8/8/8/RR2K2R w KBk - 0 1";
+        position.set(xfen).unwrap();
+        assert_eq!(
+            position.castling_rook_square(CastlingRights::WhiteOOO),
+            Square::SqB1
+        );
+        assert_eq!(
+            position.castling_rook_square(CastlingRights::BlackOO),
+            Square::SqG8
+        );
+        assert_eq!(position.fen(), "1r2k1r1/8/8/8/8/8/8/RR2K2R w KQk - 0 1");
+        position.set_chess960(true);
+        position.set(xfen).unwrap();
+        let shredder = "1r2k1r1/8/8/8/8/8/8/RR2K2R w HBg - 0 1";
+        assert_eq!(position.fen(), shredder);
+        position.set(shredder).unwrap();
+        assert_eq!(position.fen(), shredder);
+        assert_eq!(
+            position.set("1r2k1r1/8/8/8/8/8/8/RR2K2R w C - 0 1"),
+            Err(FenError::InvalidCastling("C".to_string()))
+        );
+
+        //The castling rook shields the king from the queen, after castling it would not
+        position.set("4k3/8/8/8/8/8/8/qR1K4 w B - 0 1").unwrap();
+        assert_eq!(position.parse_uci_move("d1b1"), None);
+        position.set("4k3/8/8/8/8/8/8/1R1K4 w B - 0 1").unwrap();
+        assert!(position.parse_uci_move("d1b1").is_some());
+    }
+
+    #[test]
+    fn test_outcome() {
+        init_tables();
+        let mut position = Position::default();
+        for (fen, outcome) in [
+            (START_FEN, Outcome::Ongoing),
+            (
+                "7k/6Q1/6K1/8/8/8/8/8 b - - 0 80",
+                Outcome::Checkmate(Color::White),
+            ),
+            (
+                "7k/6Q1/6K1/8/8/8/8/8 b - - 100 80",
+                Outcome::Checkmate(Color::White),
+            ),
+            ("7k/8/6QK/8/8/8/8/8 b - - 0 80", Outcome::Stalemate),
+            ("7k/8/8/8/8/8/8/R6K w - - 100 80", Outcome::FiftyMove),
+            (
+                "8/8/4k3/8/8/3NK3/8/8 w - - 0 1",
+                Outcome::InsufficientMaterial,
+            ),
+        ] {
+            position.set(fen).unwrap();
+            assert_eq!(position.outcome(), outcome, "{}", fen);
+        }
+
+        //Shuffling the knights back and forth twice repeats the start position a third time
+        position.set(START_FEN).unwrap();
+        let mut states = Vec::new();
+        for (i, uci) in [
+            "g1f3", "g8f6", "f3g1", "f6g8", "g1f3", "g8f6", "f3g1", "f6g8",
+        ]
+        .iter()
+        .enumerate()
+        {
+            let m = generate::<LEGAL>(&position)
+                .iter()
+                .find(|m| m.to_string() == *uci)
+                .unwrap();
+            states.push(StateInfo::default());
+            let gives_check = position.gives_check(m);
+            position.do_move(m, states.last_mut().unwrap(), gives_check);
+            let expected = if i == 7 {
+                Outcome::ThreefoldRepetition
+            } else {
+                Outcome::Ongoing
+            };
+            assert_eq!(position.outcome(), expected);
+        }
+    }
+
+    #[test]
+    fn test_clone() {
+        init_tables();
+        let mut position = Position::default();
+        position.set(START_FEN).unwrap();
+        let e4 = position.parse_uci_move("e2e4").unwrap();
+        let mut st = StateInfo::default();
+        position.do_move(e4, &mut st, false);
+
+        //The clone carries the history, so it can take back the move made before
+        let mut clone = position.clone();
+        assert_eq!(clone.fen(), position.fen());
+        clone.undo_move(e4);
+        assert_eq!(clone.fen(), START_FEN);
+        assert_eq!(position.side_to_move(), Color::Black);
+        position.undo_move(e4);
+        assert_eq!(position.key(), clone.key());
+    }
+
+    #[test]
+    fn test_upcoming_repetition() {
+        init_tables();
+        Position::init();
+        assert_eq!(
+            CUCKOO.get().unwrap().iter().filter(|&&k| k != 0).count(),
+            3668
+        );
+
+        let mut position = Position::default();
+        position.set(START_FEN).unwrap();
+        let mut states = Vec::new();
+        let mut play = |position: &mut Position, uci: &str| {
+            let m = generate::<LEGAL>(position)
+                .iter()
+                .find(|m| m.to_string() == uci)
+                .unwrap();
+            states.push(StateInfo::default());
+            let gives_check = position.gives_check(m);
+            position.do_move(m, states.last_mut().unwrap(), gives_check);
+        };
+
+        for uci in ["g1f3", "g8f6"] {
+            play(&mut position, uci);
+            assert!(!position.upcoming_repetition(10));
+        }
+        //Nf6-g8 gets back to the start position, which only counts after the root
+        play(&mut position, "f3g1");
+        assert!(position.upcoming_repetition(4));
+        assert!(!position.upcoming_repetition(3));
+        assert!(!position.upcoming_repetition(0));
+
+        //Once the start position has repeated, getting back to it again is a third occurrence
+        for uci in ["f6g8", "g1f3", "g8f6", "f3g1"] {
+            play(&mut position, uci);
+        }
+        assert!(position.upcoming_repetition(0));
+
+        //Pawn moves can not be undone
+        play(&mut position, "e7e5");
+        play(&mut position, "g1f3");
+        play(&mut position, "b8c6");
+        assert!(!position.upcoming_repetition(10));
+    }
+
+    #[test]
+    fn test_fen_check_info() {
+        init_tables();
+        let mut position = Position::default();
+        position.set("4k3/8/8/8/1b6/8/3P4/4K2r w - - 0 1").unwrap();
+        assert_eq!(position.checkers(), Square::SqH1.bb());
+        assert_eq!(position.blockers_for_king(Color::White), Square::SqD2.bb());
+        assert_eq!(position.pinners(Color::Black), Square::SqB4.bb());
+    }
+
+    #[test]
+    fn test_fen_errors() {
+        init_tables();
+        let mut position = Position::default();
+        position.set(FENS[0]).unwrap();
+
+        let bad = [
+            ("", FenError::MissingField("piece placement")),
+            (
+                "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP w KQkq - 0 1",
+                FenError::InvalidPiecePlacement("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP".to_string()),
+            ),
+            (
+                "rnbqkbnr/pppppppp/9/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+                FenError::InvalidPiecePlacement(
+                    "rnbqkbnr/pppppppp/9/8/8/8/PPPPPPPP/RNBQKBNR".to_string(),
+                ),
+            ),
+            (
+                "rnbqkbnr/ppppxppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+                FenError::InvalidPiecePlacement(
+                    "rnbqkbnr/ppppxppp/8/8/8/8/PPPPPPPP/RNBQKBNR".to_string(),
+                ),
+            ),
+            ("8/8/8/8/8/8/8/4K3 w - - 0 1", FenError::InvalidKingCount),
+            (
+                "4k3/8/8/8/8/8/8/4K3 x - - 0 1",
+                FenError::InvalidSideToMove("x".to_string()),
+            ),
+            ("4k3/8/8/8/8/8/8/4K3 w", FenError::MissingField("castling")),
+            (
+                "4k3/8/8/8/8/8/8/4K3 w K - 0 1",
+                FenError::InvalidCastling("K".to_string()),
+            ),
+            (
+                "4k3/8/8/8/8/8/8/4K3 w - e4 0 1",
+                FenError::InvalidEnPassant("e4".to_string()),
+            ),
+            (
+                "4k3/8/8/8/8/8/8/4K3 w - e6 0 1",
+                FenError::InvalidEnPassant("e6".to_string()),
+            ),
+            (
+                "4k3/4p3/8/4pP2/8/8/8/4K3 w - e6 0 1",
+                FenError::InvalidEnPassant("e6".to_string()),
+            ),
+            (
+                "4k3/8/8/8/8/8/8/4K2p w - - 0 1",
+                FenError::InvalidPiecePlacement("4k3/8/8/8/8/8/8/4K2p".to_string()),
+            ),
+            (
+                "4kP2/8/8/8/8/8/8/4K3 b - - 0 1",
+                FenError::InvalidPiecePlacement("4kP2/8/8/8/8/8/8/4K3".to_string()),
+            ),
+            ("4k3/8/8/8/8/8/8/4R1K1 w - - 0 1", FenError::OpponentInCheck),
+            ("4k3/8/8/8/8/5n2/8/4K3 b - - 0 1", FenError::OpponentInCheck),
+            (
+                "4k3/8/8/8/8/8/8/4K3 w - - x 1",
+                FenError::InvalidHalfmoveClock("x".to_string()),
+            ),
+            (
+                "4k3/8/8/8/8/8/8/4K3 w - - 0 -3",
+                FenError::InvalidFullmoveNumber("-3".to_string()),
+            ),
+        ];
+        for (fen, err) in bad {
+            assert_eq!(position.set(fen), Err(err));
+        }
+
+        //A failed set leaves the previous position in place
+        assert_eq!(position.fen(), FENS[0]);
+    }
+
+    #[test]
+    fn test_pseudo_legal() {
+        init_tables();
+        let positions: Vec<Position> = FENS
+            .iter()
+            .chain(["4k3/8/8/b7/8/8/8/4K2R w K - 0 1"].iter())
+            .map(|fen| {
+                let mut pos = Position::default();
+                pos.set(fen).unwrap();
+                pos
+            })
+            .collect();
+
+        //A move from any position is pseudo legal elsewhere exactly when it gets generated there
+        for source in &positions {
+            for m in generate::<LEGAL>(source).iter() {
+                for pos in &positions {
+                    let expected = if pos.checkers() != 0 {
+                        generate::<EVASIONS>(pos).contains(m)
+                    } else {
+                        generate::<NON_EVASIONS>(pos).contains(m)
+                    };
+                    assert_eq!(pos.pseudo_legal(m), expected, "{} in {}", m, pos.fen());
+                    //The same move with other promotion bits is never generated
+                    if m.type_of() != MoveType::Promotion {
+                        for promotion in 1..4 {
+                            let alias = Move::new(m.raw() ^ promotion << 12);
+                            assert!(!pos.pseudo_legal(alias), "{} in {}", alias, pos.fen());
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_see() {
+        init_tables();
+        let see = |fen: &str, from: Square, to: Square| {
+            let mut pos = Position::default();
+            pos.set(fen).unwrap();
+            let m = Move::new_from_to_sq(from, to);
+            let value = pos.see(m);
+            assert!(pos.see_ge(m, value));
+            assert!(!pos.see_ge(m, value + 1));
+            value
+        };
+
+        //Undefended pawn
+        let fen = "1k1r4/1pp4p/p7/4p3/8/P5P1/1PP4P/2K1R3 w - - 0 1";
+        assert_eq!(see(fen, Square::SqE1, Square::SqE5), PawnValue);
+
+        //Knight takes a pawn that is defended once too often
+        let fen = "1k1r3q/1ppn3p/p4b2/4p3/8/P2N2P1/1PP1R1BP/2K1Q3 w - - 0 1";
+        assert_eq!(
+            see(fen, Square::SqD3, Square::SqE5),
+            PawnValue - KnightValue
+        );
+
+        //The knight can not recapture while pinned to its king
+        let fen = "6k1/5n2/8/4p3/8/8/B7/4R1K1 w - - 0 1";
+        assert_eq!(see(fen, Square::SqE1, Square::SqE5), PawnValue);
+        let fen = "6k1/5n2/8/4p3/8/8/8/4R1K1 w - - 0 1";
+        assert_eq!(see(fen, Square::SqE1, Square::SqE5), PawnValue - RookValue);
+
+        //X-ray attackers join in once the piece in front of them is gone
+        let fen = "4k3/4r3/8/4p3/8/8/4R3/4R1K1 w - - 0 1";
+        assert_eq!(see(fen, Square::SqE2, Square::SqE5), PawnValue);
+        let fen = "4k3/4r3/8/4p3/8/8/4R3/6K1 w - - 0 1";
+        assert_eq!(see(fen, Square::SqE2, Square::SqE5), PawnValue - RookValue);
+
+        //A quiet move to an attacked square hangs the piece
+        let fen = "4k3/8/3p4/8/3N4/8/8/4K3 w - - 0 1";
+        assert_eq!(see(fen, Square::SqD4, Square::SqE5), -KnightValue);
+        assert_eq!(see(fen, Square::SqD4, Square::SqB5), VALUE_ZERO);
+
+        //The king may only capture on an undefended square
+        let fen = "4k3/8/8/8/8/8/3q4/4K3 w - - 0 1";
+        assert_eq!(see(fen, Square::SqE1, Square::SqD2), QueenValue);
+        let fen = "4k3/8/8/8/8/3q4/8/3RK3 b - - 0 1";
+        assert_eq!(see(fen, Square::SqD3, Square::SqD1), RookValue - QueenValue);
+        let fen = "3rk3/8/8/8/8/3q4/8/3RK3 b - - 0 1";
+        assert_eq!(see(fen, Square::SqD3, Square::SqD1), RookValue);
+
+        //Special moves are not resolved
+        let mut pos = Position::default();
+        pos.set("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+        let castling = Move::make(
+            MoveType::Castling,
+            Square::SqE1,
+            Square::SqH1,
+            PieceType::Knight,
+        );
+        assert_eq!(pos.see(castling), VALUE_ZERO);
+    }
+
     #[test]
     fn test_set_check_info() {
-        todo!()
+        init_tables();
+        let mut position = Position::default();
+        position.set("4k3/8/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+
+        //Squares from which white would give check to the king on e8
+        assert_eq!(
+            position.check_squares(PieceType::Pawn),
+            Square::SqD7.bb() | Square::SqF7.bb()
+        );
+        assert_eq!(
+            position.check_squares(PieceType::Knight),
+            bb::get_pseudo_attacks(PieceType::Knight, Square::SqE8)
+        );
+        assert_eq!(
+            position.check_squares(PieceType::Queen),
+            position.check_squares(PieceType::Rook) | position.check_squares(PieceType::Bishop)
+        );
+        assert_ne!(position.check_squares(PieceType::Rook) & Square::SqE1, 0);
+        assert_eq!(position.check_squares(PieceType::King), 0);
     }
 
+    #[test]
     fn test_update_sliders_blockers() {
-        todo!()
+        init_tables();
+        let mut position = Position::default();
+
+        //The c2 bishop is pinned, the f1 rook and g1 knight together shield the king from the h1
+        //queen and the d7 knight is pinned to its own king
+        position
+            .set("3k4/3n4/8/8/q7/8/2BR4/3K1Rnq w - - 0 1")
+            .unwrap();
+        assert_eq!(position.blockers_for_king(Color::White), Square::SqC2.bb());
+        assert_eq!(position.pinners(Color::Black), Square::SqA4.bb());
+        assert_eq!(position.blockers_for_king(Color::Black), Square::SqD7.bb());
+        assert_eq!(position.pinners(Color::White), Square::SqD2.bb());
     }
 
     #[test]
@@ -1191,4 +2556,3 @@ mod test {
         assert_eq!(all_pieces, res);
     }
 }
-// rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1
@@ -0,0 +1,237 @@
+use crate::board::movegen::generate;
+use crate::board::position::{Position, StateInfo};
+use crate::types::*;
+
+const PIECE_LETTERS: &str = "  NBRQK";
+
+//Standard algebraic notation of a legal move, like Nbd7, exf8=Q+ or O-O-O#
+pub fn to_san(pos: &mut Position, m: Move) -> String {
+    if !m.is_ok() {
+        return "--".to_string();
+    }
+
+    let from = m.from_sq();
+    let to = m.to_sq();
+    let mut san = if m.type_of() == MoveType::Castling {
+        let san = if to > from { "O-O" } else { "O-O-O" };
+        san.to_string()
+    } else {
+        let pt = pos.moved_piece(m).type_of();
+        let mut san = String::new();
+        if pt == PieceType::Pawn {
+            if pos.capture(m) {
+                san.push(file_char(from));
+            }
+        } else {
+            san.push(PIECE_LETTERS.as_bytes()[pt as usize] as char);
+            san.push_str(&disambiguation(pos, m));
+        }
+        if pos.capture(m) {
+            san.push('x');
+        }
+        san.push_str(&to.to_string());
+        if m.type_of() == MoveType::Promotion {
+            san.push('=');
+            san.push(PIECE_LETTERS.as_bytes()[m.promotion_type() as usize] as char);
+        }
+        san
+    };
+
+    let gives_check = pos.gives_check(m);
+    if gives_check {
+        let mut st = StateInfo::default();
+        pos.do_move(m, &mut st, gives_check);
+        let mate = generate::<LEGAL>(pos).is_empty();
+        pos.undo_move(m);
+        san.push(if mate { '#' } else { '+' });
+    }
+    san
+}
+
+//Finds the legal move written in standard algebraic notation. Check and mate markers, move
+//annotations and the '=' of promotions are optional, castling may be written with zeros
+pub fn parse_san(pos: &Position, s: &str) -> Option<Move> {
+    let s = s.trim_end_matches(['+', '#', '!', '?']);
+    let s = s.strip_suffix("e.p.").unwrap_or(s).trim_end();
+    let legal = generate::<LEGAL>(pos);
+
+    let castling = match s {
+        "O-O" | "0-0" => Some(true),
+        "O-O-O" | "0-0-0" => Some(false),
+        _ => None,
+    };
+    if let Some(king_side) = castling {
+        return legal
+            .iter()
+            .find(|m| m.type_of() == MoveType::Castling && (m.to_sq() > m.from_sq()) == king_side);
+    }
+
+    let mut chars: Vec<char> = s.chars().filter(|&c| c != 'x' && c != '-').collect();
+
+    let mut promotion = PieceType::NoPieceType;
+    if let Some(&c) = chars.last().filter(|c| c.is_ascii_alphabetic()) {
+        promotion = piece_type(c.to_ascii_uppercase())?;
+        chars.pop();
+        if chars.last() == Some(&'=') {
+            chars.pop();
+        }
+    }
+
+    if chars.len() < 2 {
+        return None;
+    }
+    let to_chars: String = chars.split_off(chars.len() - 2).into_iter().collect();
+    let to = Square::from_algebraic(&to_chars)?;
+
+    let mut pt = PieceType::Pawn;
+    if let Some(&c) = chars.first().filter(|c| c.is_ascii_uppercase()) {
+        pt = piece_type(c)?;
+        chars.remove(0);
+    }
+
+    //Whatever is left disambiguates the origin square by file, rank or both
+    let (mut file, mut rank) = (None, None);
+    for c in chars {
+        match c {
+            'a'..='h' if file.is_none() => file = Some(c as i32 - 'a' as i32),
+            '1'..='8' if rank.is_none() => rank = Some(c as i32 - '1' as i32),
+            _ => return None,
+        }
+    }
+
+    let mut candidates = legal.iter().filter(|&m| {
+        let from = m.from_sq();
+        m.type_of() != MoveType::Castling
+            && m.to_sq() == to
+            && pos.moved_piece(m).type_of() == pt
+            && file.is_none_or(|f| from.file_of() as i32 == f)
+            && rank.is_none_or(|r| from.rank_of() as i32 == r)
+            && match m.type_of() {
+                MoveType::Promotion => m.promotion_type() == promotion,
+                _ => promotion == PieceType::NoPieceType,
+            }
+    });
+    let m = candidates.next()?;
+    candidates.next().is_none().then_some(m)
+}
+
+//The shortest origin hint that tells m apart from the other moves of the same piece type to
+//the same square: the file if that is unique, else the rank, else the whole square
+fn disambiguation(pos: &Position, m: Move) -> String {
+    let from = m.from_sq();
+    let pc = pos.moved_piece(m);
+    let others: Vec<Square> = generate::<LEGAL>(pos)
+        .iter()
+        .filter(|&o| o.to_sq() == m.to_sq() && o.from_sq() != from && pos.moved_piece(o) == pc)
+        .map(|o| o.from_sq())
+        .collect();
+
+    if others.is_empty() {
+        String::new()
+    } else if others.iter().all(|s| s.file_of() != from.file_of()) {
+        file_char(from).to_string()
+    } else if others.iter().all(|s| s.rank_of() != from.rank_of()) {
+        ((b'1' + from.rank_of() as u8) as char).to_string()
+    } else {
+        from.to_string()
+    }
+}
+
+fn file_char(s: Square) -> char {
+    (b'a' + s.file_of() as u8) as char
+}
+
+fn piece_type(c: char) -> Option<PieceType> {
+    match c {
+        'N' => Some(PieceType::Knight),
+        'B' => Some(PieceType::Bishop),
+        'R' => Some(PieceType::Rook),
+        'Q' => Some(PieceType::Queen),
+        'K' => Some(PieceType::King),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::board::position::START_FEN;
+
+    fn san_of(pos: &mut Position, uci: &str) -> String {
+        let m = pos.parse_uci_move(uci).unwrap();
+        to_san(pos, m)
+    }
+
+    #[test]
+    fn test_to_san() {
+        let mut pos = Position::from_fen(START_FEN).unwrap();
+        assert_eq!(san_of(&mut pos, "e2e4"), "e4");
+        assert_eq!(san_of(&mut pos, "g1f3"), "Nf3");
+        assert_eq!(to_san(&mut pos, Move::null()), "--");
+
+        //Disambiguation by file, by rank and by square
+        let mut pos = Position::from_fen("1k6/8/8/R6R/8/8/8/R3K1N1 w - - 0 1").unwrap();
+        assert_eq!(san_of(&mut pos, "h5d5"), "Rhd5");
+        assert_eq!(san_of(&mut pos, "a1a3"), "R1a3");
+        assert_eq!(san_of(&mut pos, "g1f3"), "Nf3");
+        let mut pos = Position::from_fen("k7/8/8/8/1Q1Q4/8/1Q6/7K w - - 0 1").unwrap();
+        assert_eq!(san_of(&mut pos, "b4c3"), "Qb4c3");
+        assert_eq!(san_of(&mut pos, "d4c3"), "Qdc3");
+
+        //Captures, en passant, promotions, castling, check and mate
+        let mut pos = Position::from_fen("r3k2r/1P6/8/3pP3/8/8/8/R3K2R w KQkq d6 0 1").unwrap();
+        assert_eq!(san_of(&mut pos, "e5d6"), "exd6");
+        assert_eq!(san_of(&mut pos, "b7a8q"), "bxa8=Q+");
+        assert_eq!(san_of(&mut pos, "b7b8n"), "b8=N");
+        assert_eq!(san_of(&mut pos, "e1g1"), "O-O");
+        assert_eq!(san_of(&mut pos, "e1c1"), "O-O-O");
+        assert_eq!(san_of(&mut pos, "a1a8"), "Rxa8+");
+        let mut pos = Position::from_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1").unwrap();
+        assert_eq!(san_of(&mut pos, "a1a8"), "Ra8#");
+    }
+
+    #[test]
+    fn test_parse_san() {
+        let pos = Position::from_fen("r3k2r/1P6/8/3pP3/8/8/8/R3K2R w KQkq d6 0 1").unwrap();
+        let uci = |san: &str| parse_san(&pos, san).map(|m| m.to_string());
+        for (san, expected) in [
+            ("exd6", "e5d6"),
+            ("exd6e.p.", "e5d6"),
+            ("ed6", "e5d6"),
+            ("bxa8=Q+", "b7a8q"),
+            ("bxa8Q", "b7a8q"),
+            ("b8=N", "b7b8n"),
+            ("b8n", "b7b8n"),
+            ("O-O", "e1g1"),
+            ("0-0-0", "e1c1"),
+            ("Rxa8", "a1a8"),
+            ("Rxa8+!?", "a1a8"),
+            ("Ra1-a8", "a1a8"),
+            ("Rhg1", "h1g1"),
+            ("Kd2", "e1d2"),
+        ] {
+            assert_eq!(uci(san).as_deref(), Some(expected), "{}", san);
+        }
+
+        //Ambiguous, illegal and malformed moves
+        for san in ["b8", "e4", "Kc1", "Nf3", "Ra9", "Zxa8", "O-O-O-O", "", "x"] {
+            assert_eq!(uci(san), None, "{}", san);
+        }
+        let pos = Position::from_fen("1k6/8/8/R6R/8/8/8/R3K1N1 w - - 0 1").unwrap();
+        assert_eq!(parse_san(&pos, "Rd5"), None);
+        assert_eq!(parse_san(&pos, "Ra3"), None);
+        assert_eq!(parse_san(&pos, "Rad5").unwrap().to_string(), "a5d5");
+        assert_eq!(parse_san(&pos, "R5a3").unwrap().to_string(), "a5a3");
+    }
+
+    #[test]
+    fn test_san_round_trip() {
+        let mut pos =
+            Position::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq -")
+                .unwrap();
+        for m in generate::<LEGAL>(&pos).iter() {
+            let san = to_san(&mut pos, m);
+            assert_eq!(parse_san(&pos, &san), Some(m), "{}", san);
+        }
+    }
+}
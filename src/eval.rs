@@ -0,0 +1,558 @@
+use crate::board::bitboard as bb;
+use crate::board::position::Position;
+use crate::types::*;
+use crate::{pieces_by_color_and_pt, pieces_of_types};
+use std::ops::{Add, AddAssign, Mul, Neg, Sub, SubAssign};
+
+//A middlegame and an endgame value, interpolated by the game phase at the end
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Score {
+    pub mg: Value,
+    pub eg: Value,
+}
+
+const fn s(mg: Value, eg: Value) -> Score {
+    Score { mg, eg }
+}
+
+impl Add for Score {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        s(self.mg + rhs.mg, self.eg + rhs.eg)
+    }
+}
+
+impl Sub for Score {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self {
+        s(self.mg - rhs.mg, self.eg - rhs.eg)
+    }
+}
+
+impl Neg for Score {
+    type Output = Self;
+    fn neg(self) -> Self {
+        s(-self.mg, -self.eg)
+    }
+}
+
+impl Mul<i32> for Score {
+    type Output = Self;
+    fn mul(self, rhs: i32) -> Self {
+        s(self.mg * rhs, self.eg * rhs)
+    }
+}
+
+impl AddAssign for Score {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+impl SubAssign for Score {
+    fn sub_assign(&mut self, rhs: Self) {
+        *self = *self - rhs;
+    }
+}
+
+//The phase goes from PHASE_MIDGAME with all non pawn material on the board to 0 once the
+//material drops to ENDGAME_LIMIT
+const PHASE_MIDGAME: Value = 128;
+const MIDGAME_LIMIT: Value = 15258;
+const ENDGAME_LIMIT: Value = 3915;
+
+const TEMPO: Value = 28;
+
+const PIECE_SCORE: [Score; 7] = [
+    s(0, 0),
+    s(126, 208),
+    s(781, 854),
+    s(825, 915),
+    s(1276, 1380),
+    s(2538, 2682),
+    s(0, 0),
+];
+
+//Piece square tables for knight to king. The board is mirrored around the middle so only
+//files a to d are given, ranks are from the point of view of the piece's owner
+#[rustfmt::skip]
+const PIECE_BONUS: [[[Score; 4]; RNB]; 5] = [
+    [ //Knight
+        [s(-175, -96), s(-92, -65), s(-74, -49), s(-73, -21)],
+        [s( -77, -67), s(-41, -54), s(-27, -18), s(-15,   8)],
+        [s( -61, -40), s(-17, -27), s(  6,  -8), s( 12,  29)],
+        [s( -35, -35), s(  8,  -2), s( 40,  13), s( 49,  28)],
+        [s( -34, -45), s( 13, -16), s( 44,   9), s( 51,  39)],
+        [s(  -9, -51), s( 22, -44), s( 58, -16), s( 53,  17)],
+        [s( -67, -69), s(-27, -50), s(  4, -51), s( 37,  12)],
+        [s(-201,-100), s(-83, -88), s(-56, -56), s(-26, -17)],
+    ],
+    [ //Bishop
+        [s(-37, -40), s( -4, -21), s( -6, -26), s(-16,  -8)],
+        [s(-11, -26), s(  6,  -9), s( 13, -12), s(  3,   1)],
+        [s( -5, -11), s( 15,  -1), s( -4,  -1), s( 12,   7)],
+        [s( -4, -14), s(  8,  -4), s( 18,   0), s( 27,  12)],
+        [s( -8, -12), s( 20,  -1), s( 15, -10), s( 22,  11)],
+        [s(-11, -21), s(  4,   4), s(  1,   3), s(  8,   4)],
+        [s(-12, -22), s(-10, -14), s(  4,  -1), s(  0,   1)],
+        [s(-34, -32), s(  1, -29), s(-10, -26), s(-16, -17)],
+    ],
+    [ //Rook
+        [s(-31,  -9), s(-20, -13), s(-14, -10), s( -5,  -9)],
+        [s(-21, -12), s(-13,  -9), s( -8,  -1), s(  6,  -2)],
+        [s(-25,   6), s(-11,  -8), s( -1,  -2), s(  3,  -6)],
+        [s(-13,  -6), s( -5,   1), s( -4,  -9), s( -6,   7)],
+        [s(-27,  -5), s(-15,   8), s( -4,   7), s(  3,  -6)],
+        [s(-22,   6), s( -2,   1), s(  6,  -7), s( 12,  10)],
+        [s( -2,   4), s( 12,   5), s( 16,  20), s( 18,  -5)],
+        [s(-17,  18), s(-19,   0), s( -1,  19), s(  9,  13)],
+    ],
+    [ //Queen
+        [s(  3, -69), s( -5, -57), s( -5, -47), s(  4, -26)],
+        [s( -3, -54), s(  5, -31), s(  8, -22), s( 12,  -4)],
+        [s( -3, -39), s(  6, -18), s( 13,  -9), s(  7,   3)],
+        [s(  4, -23), s(  5,  -3), s(  9,  13), s(  8,  24)],
+        [s(  0, -29), s( 14,  -6), s( 12,   9), s(  5,  21)],
+        [s( -4, -38), s( 10, -18), s(  6, -11), s(  8,   1)],
+        [s( -5, -50), s(  6, -27), s( 10, -24), s(  8,  -8)],
+        [s( -2, -74), s( -2, -52), s(  1, -43), s( -2, -34)],
+    ],
+    [ //King
+        [s(271,   1), s(327,  45), s(271,  85), s(198,  76)],
+        [s(278,  53), s(303, 100), s(234, 133), s(179, 135)],
+        [s(195,  88), s(258, 130), s(169, 169), s(120, 175)],
+        [s(164, 103), s(190, 156), s(138, 172), s( 98, 172)],
+        [s(154,  96), s(179, 166), s(105, 199), s( 70, 199)],
+        [s(123,  92), s(145, 172), s( 81, 184), s( 31, 191)],
+        [s( 88,  47), s(120, 121), s( 65, 116), s( 33, 131)],
+        [s( 59,  11), s( 89,  59), s( 45,  73), s( -1,  78)],
+    ],
+];
+
+//Pawns are not symmetric, the table covers the whole board
+#[rustfmt::skip]
+const PAWN_BONUS: [[Score; FNB]; RNB] = [
+    [s(0, 0); FNB],
+    [s(  3,-10), s(  3, -6), s( 10, 10), s( 19,  0), s( 16, 14), s( 19,  7), s(  7, -5), s( -5,-19)],
+    [s( -9,-10), s(-15,-10), s( 11,-10), s( 15,  4), s( 32,  4), s( 22,  3), s(  5, -6), s(-22, -4)],
+    [s( -4,  6), s(-23, -2), s(  6, -8), s( 20, -4), s( 40,-13), s( 17,-12), s(  4,-10), s( -8, -9)],
+    [s( 13, 10), s(  0,  5), s(-13,  4), s(  1, -5), s( 11, -5), s( -2, -5), s(-13, 14), s(  5,  9)],
+    [s(  5, 28), s(-12, 20), s( -7, 21), s( 22, 28), s( -8, 30), s( -5,  7), s(-15,  6), s( -8, 13)],
+    [s( -7,  0), s(  7,-11), s( -3, 12), s(-13, 21), s(  5, 25), s(-16, 19), s( 10,  4), s( -8,  7)],
+    [s(0, 0); FNB],
+];
+
+//Indexed by the number of safe squares a piece attacks
+#[rustfmt::skip]
+const KNIGHT_MOBILITY: [Score; 9] = [
+    s(-62,-79), s(-53,-57), s(-12,-31), s( -3,-17), s(  3,  7), s( 12, 13), s( 21, 16),
+    s( 28, 21), s( 37, 26),
+];
+#[rustfmt::skip]
+const BISHOP_MOBILITY: [Score; 14] = [
+    s(-47,-59), s(-20,-25), s( 14, -8), s( 29, 12), s( 39, 21), s( 53, 40), s( 53, 56),
+    s( 60, 58), s( 62, 65), s( 69, 72), s( 78, 78), s( 83, 87), s( 91, 88), s( 96, 98),
+];
+#[rustfmt::skip]
+const ROOK_MOBILITY: [Score; 15] = [
+    s(-60,-82), s(-24,-15), s(  0, 17), s(  3, 43), s(  4, 72), s( 14,100), s( 20,102),
+    s( 30,122), s( 41,133), s( 41,139), s( 41,153), s( 45,160), s( 57,165), s( 58,170),
+    s( 67,175),
+];
+#[rustfmt::skip]
+const QUEEN_MOBILITY: [Score; 28] = [
+    s(-29,-49), s(-16,-29), s( -8, -8), s( -8, 17), s( 18, 39), s( 25, 54), s( 23, 59),
+    s( 37, 73), s( 41, 76), s( 54, 95), s( 65, 95), s( 68,101), s( 69,124), s( 70,128),
+    s( 70,132), s( 70,133), s( 71,136), s( 72,140), s( 74,147), s( 76,149), s( 90,153),
+    s(104,169), s(105,171), s(106,171), s(112,178), s(114,185), s(114,187), s(119,221),
+];
+
+const ISOLATED: Score = s(5, 15);
+const BACKWARD: Score = s(9, 24);
+const DOUBLED: Score = s(11, 56);
+//Indexed by the relative rank of the passed pawn
+const PASSED_RANK: [Score; RNB] = [
+    s(0, 0),
+    s(10, 28),
+    s(17, 33),
+    s(15, 41),
+    s(62, 72),
+    s(168, 177),
+    s(276, 260),
+    s(0, 0),
+];
+
+const BISHOP_PAIR: Score = s(45, 60);
+//Rook on a file without own pawns, and on a file without any pawns
+const ROOK_ON_SEMI_OPEN_FILE: Score = s(19, 7);
+const ROOK_ON_OPEN_FILE: Score = s(48, 29);
+
+//Own pawns on the king file and its neighbours in front of the king
+const PAWN_SHELTER: Score = s(18, 0);
+//Weight of each piece type that attacks the area around the enemy king
+const KING_ATTACK_WEIGHTS: [i32; 7] = [0, 0, 81, 52, 44, 10, 0];
+
+//Evaluates the position from the point of view of the side to move
+pub fn evaluate(pos: &Position) -> Value {
+    let v = Evaluation::new(pos).value();
+    if pos.side_to_move() == Color::White {
+        v + TEMPO
+    } else {
+        -v + TEMPO
+    }
+}
+
+//Attack maps and king danger bookkeeping shared between the evaluation terms
+struct Evaluation<'a> {
+    pos: &'a Position,
+    mobility_area: [Bitboard; COLORNB],
+    attacked_by: [[Bitboard; PTNB]; COLORNB],
+    king_ring: [Bitboard; COLORNB],
+    //Indexed by the attacking color
+    king_attackers_count: [i32; COLORNB],
+    king_attackers_weight: [i32; COLORNB],
+    king_attacks_count: [i32; COLORNB],
+}
+
+impl<'a> Evaluation<'a> {
+    fn new(pos: &'a Position) -> Self {
+        let mut eval = Self {
+            pos,
+            mobility_area: [0; COLORNB],
+            attacked_by: [[0; PTNB]; COLORNB],
+            king_ring: [0; COLORNB],
+            king_attackers_count: [0; COLORNB],
+            king_attackers_weight: [0; COLORNB],
+            king_attacks_count: [0; COLORNB],
+        };
+        eval.initialize(Color::White);
+        eval.initialize(Color::Black);
+        eval
+    }
+
+    //Evaluation from white's point of view
+    fn value(&mut self) -> Value {
+        let mut score = Score::default();
+        for c in [Color::White, Color::Black] {
+            let sign = if c == Color::White { 1 } else { -1 };
+            let mut side = self.psq(c) + self.pawns(c);
+            for pt in [
+                PieceType::Knight,
+                PieceType::Bishop,
+                PieceType::Rook,
+                PieceType::Queen,
+            ] {
+                side += self.pieces(c, pt);
+            }
+            score += side * sign;
+        }
+        //King safety needs the attack maps of all the enemy pieces
+        score += self.king(Color::White) - self.king(Color::Black);
+
+        let npm =
+            self.pos.non_pawn_material(Color::White) + self.pos.non_pawn_material(Color::Black);
+        let phase = (npm.clamp(ENDGAME_LIMIT, MIDGAME_LIMIT) - ENDGAME_LIMIT) * PHASE_MIDGAME
+            / (MIDGAME_LIMIT - ENDGAME_LIMIT);
+        (score.mg * phase + score.eg * (PHASE_MIDGAME - phase)) / PHASE_MIDGAME
+    }
+
+    fn initialize(&mut self, us: Color) {
+        let them = !us;
+        let pos = self.pos;
+        let ksq = pos.square(us, PieceType::King);
+        let our_pawns = pieces_by_color_and_pt!(pos, us, PieceType::Pawn);
+        let low_ranks = if us == Color::White {
+            bb::RANK2BB | bb::RANK3BB
+        } else {
+            bb::RANK7BB | bb::RANK6BB
+        };
+        let down = pawn_push(them);
+
+        //Blocked pawns and pawns that have not moved much leave their squares to no one
+        let blocked = our_pawns & (bb::shift(pos.all_pieces(), down) | low_ranks);
+
+        self.attacked_by[us as usize][PieceType::King as usize] =
+            bb::get_pseudo_attacks(PieceType::King, ksq);
+        self.attacked_by[us as usize][PieceType::Pawn as usize] =
+            bb::pawn_attacks_bb(our_pawns, us);
+        self.attacked_by[us as usize][PieceType::AllPieces as usize] = self.attacked_by
+            [us as usize][PieceType::King as usize]
+            | self.attacked_by[us as usize][PieceType::Pawn as usize];
+
+        self.mobility_area[us as usize] = !(blocked
+            | pieces_by_color_and_pt!(pos, us, PieceType::King, PieceType::Queen)
+            | bb::pawn_attacks_bb(pieces_by_color_and_pt!(pos, them, PieceType::Pawn), them));
+
+        self.king_ring[us as usize] = bb::get_pseudo_attacks(PieceType::King, ksq) | ksq;
+    }
+
+    //Material and piece square tables
+    fn psq(&self, us: Color) -> Score {
+        let mut score = Score::default();
+        let mut b = self.pos.pieces_by_color(us);
+        while b != 0 {
+            let sq = bb::pop_lsb(&mut b);
+            let pt = self.pos.piece_on(sq).type_of();
+            let rank = sq.relative_rank(us) as usize;
+            let file = sq.file_of() as usize;
+            score += PIECE_SCORE[pt as usize];
+            score += if pt == PieceType::Pawn {
+                PAWN_BONUS[rank][file]
+            } else {
+                PIECE_BONUS[pt as usize - PieceType::Knight as usize][rank][file.min(7 - file)]
+            };
+        }
+        score
+    }
+
+    fn pawns(&self, us: Color) -> Score {
+        let them = !us;
+        let pos = self.pos;
+        let up = pawn_push(us);
+        let our_pawns = pieces_by_color_and_pt!(pos, us, PieceType::Pawn);
+        let their_pawns = pieces_by_color_and_pt!(pos, them, PieceType::Pawn);
+        let mut score = Score::default();
+
+        let mut b = our_pawns;
+        while b != 0 {
+            let sq = bb::pop_lsb(&mut b);
+            let stop = sq + up;
+            let neighbours = our_pawns & bb::adjacent_files_bb(sq);
+
+            if their_pawns & bb::passed_pawn_span(us, sq) == 0 {
+                score += PASSED_RANK[sq.relative_rank(us) as usize];
+            }
+
+            if our_pawns & (sq - up) != 0 {
+                score -= DOUBLED;
+            }
+
+            //A pawn that can not be supported by its neighbours and can not safely advance
+            if neighbours == 0 {
+                score -= ISOLATED;
+            } else if neighbours & bb::forward_ranks_bb(them, stop) == 0
+                && (their_pawns & stop != 0 || bb::pawn_attacks_bb(their_pawns, them) & stop != 0)
+            {
+                score -= BACKWARD;
+            }
+        }
+        score
+    }
+
+    fn pieces(&mut self, us: Color, pt: PieceType) -> Score {
+        let them = !us;
+        let pos = self.pos;
+        let mut score = Score::default();
+
+        let mut b = pieces_by_color_and_pt!(pos, us, pt);
+        while b != 0 {
+            let sq = bb::pop_lsb(&mut b);
+
+            //Sliders see through friendly sliders moving along the same lines
+            let occupied = match pt {
+                PieceType::Bishop => pos.all_pieces() ^ pieces_of_types!(pos, PieceType::Queen),
+                PieceType::Rook => {
+                    pos.all_pieces()
+                        ^ pieces_by_color_and_pt!(pos, us, PieceType::Rook, PieceType::Queen)
+                }
+                _ => pos.all_pieces(),
+            };
+            let attacks = bb::attacks_bb(pt, sq, occupied);
+
+            self.attacked_by[us as usize][pt as usize] |= attacks;
+            self.attacked_by[us as usize][PieceType::AllPieces as usize] |= attacks;
+
+            if attacks & self.king_ring[them as usize] != 0 {
+                self.king_attackers_count[us as usize] += 1;
+                self.king_attackers_weight[us as usize] += KING_ATTACK_WEIGHTS[pt as usize];
+                self.king_attacks_count[us as usize] += (attacks
+                    & self.attacked_by[them as usize][PieceType::King as usize])
+                    .count_ones() as i32;
+            }
+
+            let mob = (attacks & self.mobility_area[us as usize]).count_ones() as usize;
+            score += match pt {
+                PieceType::Knight => KNIGHT_MOBILITY[mob],
+                PieceType::Bishop => BISHOP_MOBILITY[mob],
+                PieceType::Rook => ROOK_MOBILITY[mob],
+                _ => QUEEN_MOBILITY[mob],
+            };
+
+            if pt == PieceType::Rook {
+                let file = bb::file_bb(sq.file_of());
+                if pieces_by_color_and_pt!(pos, us, PieceType::Pawn) & file == 0 {
+                    score += if pieces_of_types!(pos, PieceType::Pawn) & file == 0 {
+                        ROOK_ON_OPEN_FILE
+                    } else {
+                        ROOK_ON_SEMI_OPEN_FILE
+                    };
+                }
+            }
+        }
+
+        if pt == PieceType::Bishop && bb::more_than_one(pieces_by_color_and_pt!(pos, us, pt)) {
+            score += BISHOP_PAIR;
+        }
+        score
+    }
+
+    //Pawn shelter and the danger from enemy pieces attacking the squares around our king
+    fn king(&self, us: Color) -> Score {
+        let them = !us;
+        let pos = self.pos;
+        let ksq = pos.square(us, PieceType::King);
+        let mut score = Score::default();
+
+        let shelter_files = bb::file_bb(ksq.file_of()) | bb::adjacent_files_bb(ksq);
+        let king_rank = bb::rank_bb(ksq.rank_of());
+        let shelter_ranks = bb::shift(king_rank, pawn_push(us))
+            | bb::shift(bb::shift(king_rank, pawn_push(us)), pawn_push(us));
+        let shelter =
+            pieces_by_color_and_pt!(pos, us, PieceType::Pawn) & shelter_files & shelter_ranks;
+        score += PAWN_SHELTER * shelter.count_ones() as i32;
+
+        //A lone attacker is harmless unless it is backed up by the queen
+        let queenless = pieces_by_color_and_pt!(pos, them, PieceType::Queen) == 0;
+        if self.king_attackers_count[them as usize] > 1 - queenless as i32 {
+            let danger = self.king_attackers_count[them as usize]
+                * self.king_attackers_weight[them as usize]
+                + 69 * self.king_attacks_count[them as usize]
+                - 873 * queenless as i32;
+            if danger > 100 {
+                score -= s(danger * danger / 4096, danger / 16);
+            }
+        }
+        score
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::board::position::START_FEN;
+
+    //Same position with the colors swapped
+    fn mirror(fen: &str) -> String {
+        let mut fields = fen.split(' ');
+        let board: Vec<String> = fields
+            .next()
+            .unwrap()
+            .split('/')
+            .rev()
+            .map(|r| {
+                r.chars()
+                    .map(|c| {
+                        if c.is_uppercase() {
+                            c.to_ascii_lowercase()
+                        } else {
+                            c.to_ascii_uppercase()
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        let stm = if fields.next() == Some("w") { "b" } else { "w" };
+        format!("{} {} - - 0 1", board.join("/"), stm)
+    }
+
+    #[test]
+    fn test_symmetry() {
+        let fens = [
+            START_FEN,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w - - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 b - - 0 10",
+        ];
+        for fen in fens {
+            assert_eq!(
+                evaluate(&Position::from_fen(fen).unwrap()),
+                evaluate(&Position::from_fen(&mirror(fen)).unwrap()),
+                "{}",
+                fen
+            );
+        }
+        assert_eq!(evaluate(&Position::from_fen(START_FEN).unwrap()), TEMPO);
+    }
+
+    #[test]
+    fn test_material() {
+        //An extra queen is worth more than anything the other terms add up to
+        let pos =
+            Position::from_fen("rnb1kbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").unwrap();
+        assert!(evaluate(&pos) > QueenValue - 2 * PawnValue);
+        let pos =
+            Position::from_fen("rnb1kbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR b KQkq - 0 1").unwrap();
+        assert!(evaluate(&pos) < -QueenValue + 2 * PawnValue);
+    }
+
+    #[test]
+    fn test_phase() {
+        //Only kings and pawns left, the endgame values apply
+        let pos = Position::from_fen("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1").unwrap();
+        let mut eval = Evaluation::new(&pos);
+        let score = eval.psq(Color::White) - eval.psq(Color::Black) + eval.pawns(Color::White);
+        assert_eq!(eval.value(), score.eg);
+    }
+
+    #[test]
+    fn test_pawn_structure() {
+        let pawns = |fen: &str| {
+            let pos = Position::from_fen(fen).unwrap();
+            let eval = Evaluation::new(&pos);
+            eval.pawns(Color::White)
+        };
+        //Doubled and isolated c pawns against a healthy pair
+        assert_eq!(
+            pawns("4k3/8/8/8/8/2P5/2P5/4K3 w - - 0 1"),
+            -DOUBLED - ISOLATED * 2 + PASSED_RANK[1] + PASSED_RANK[2]
+        );
+        //The e pawn is passed, the d pawn can not advance past the c5 pawn without support
+        assert_eq!(
+            pawns("4k3/8/8/2p5/4P3/3P4/8/4K3 w - - 0 1"),
+            PASSED_RANK[3] - BACKWARD
+        );
+    }
+
+    #[test]
+    fn test_piece_terms() {
+        let white = |fen: &str, pt: PieceType| {
+            let pos = Position::from_fen(fen).unwrap();
+            let mut eval = Evaluation::new(&pos);
+            eval.pieces(Color::White, pt)
+        };
+        let pair = white("4k3/8/8/8/8/8/8/2B1KB2 w - - 0 1", PieceType::Bishop);
+        let single = white("4k3/8/8/8/8/8/8/2B1K3 w - - 0 1", PieceType::Bishop);
+        assert_eq!(pair - single - BISHOP_MOBILITY[7], BISHOP_PAIR);
+
+        let open = white("4k3/pp6/8/8/8/8/PP6/3RK3 w - - 0 1", PieceType::Rook);
+        assert_eq!(open, ROOK_MOBILITY[10] + ROOK_ON_OPEN_FILE);
+        let semi_open = white("4k3/pp1p4/8/8/8/8/PP6/3RK3 w - - 0 1", PieceType::Rook);
+        assert_eq!(semi_open, ROOK_MOBILITY[9] + ROOK_ON_SEMI_OPEN_FILE);
+        let closed = white("4k3/pp1p4/8/8/8/8/PP1P4/3RK3 w - - 0 1", PieceType::Rook);
+        assert_eq!(closed, ROOK_MOBILITY[3]);
+    }
+
+    #[test]
+    fn test_king_safety() {
+        let king = |fen: &str| {
+            let pos = Position::from_fen(fen).unwrap();
+            let mut eval = Evaluation::new(&pos);
+            for pt in [
+                PieceType::Knight,
+                PieceType::Bishop,
+                PieceType::Rook,
+                PieceType::Queen,
+            ] {
+                eval.pieces(Color::Black, pt);
+            }
+            eval.king(Color::White)
+        };
+        let sheltered = king("4k3/8/8/8/8/8/5PPP/6K1 w - - 0 1");
+        assert_eq!(sheltered, PAWN_SHELTER * 3);
+        let exposed = king("4k3/8/8/8/8/8/8/6K1 w - - 0 1");
+        assert_eq!(exposed, Score::default());
+
+        let attacked = king("4k3/8/8/8/8/5n1q/5PPP/6K1 w - - 0 1");
+        assert!(attacked.mg < sheltered.mg);
+    }
+}
@@ -0,0 +1,396 @@
+use crate::board::position::Position;
+use crate::types::*;
+
+//Move ordering statistics learned by the search. Every entry is moved towards the bonus it is
+//given and saturates at the table's bound, so recent results keep having an effect
+
+//Plies back whose (piece, to) pair index the continuation history
+pub const CONTINUATION_PLIES: [usize; 3] = [1, 2, 4];
+
+const BUTTERFLY_BOUND: i32 = 7183;
+const CAPTURE_BOUND: i32 = 10692;
+const CONTINUATION_BOUND: i32 = 29952;
+
+const PIECE_TO: usize = PNB * SQNB;
+
+fn gravity(entry: &mut i16, bonus: i32, bound: i32) {
+    let bonus = bonus.clamp(-bound, bound);
+    let v = *entry as i32;
+    *entry = (v + bonus - v * bonus.abs() / bound) as i16;
+}
+
+fn piece_to(pc: Piece, to: Square) -> usize {
+    pc as usize * SQNB + to as usize
+}
+
+//En passant captures a pawn that is not on the destination square
+pub fn captured_type(pos: &Position, m: Move) -> PieceType {
+    if m.type_of() == MoveType::EnPassant {
+        PieceType::Pawn
+    } else {
+        pos.piece_on(m.to_sq()).type_of()
+    }
+}
+
+pub fn stat_bonus(depth: Depth) -> i32 {
+    (291 * depth - 350).clamp(0, 1200)
+}
+
+pub fn stat_malus(depth: Depth) -> i32 {
+    (361 * depth - 361).clamp(0, 1182)
+}
+
+//Quiet moves by side to move and from/to squares
+pub struct ButterflyHistory {
+    table: Vec<i16>,
+}
+
+impl ButterflyHistory {
+    pub fn new() -> Self {
+        Self {
+            table: vec![0; COLORNB * 64 * 64],
+        }
+    }
+
+    fn index(c: Color, m: Move) -> usize {
+        c as usize * 64 * 64 + m.from_to() as usize
+    }
+
+    pub fn get(&self, c: Color, m: Move) -> i32 {
+        self.table[Self::index(c, m)] as i32
+    }
+
+    pub fn update(&mut self, c: Color, m: Move, bonus: i32) {
+        gravity(&mut self.table[Self::index(c, m)], bonus, BUTTERFLY_BOUND);
+    }
+
+    pub fn clear(&mut self) {
+        self.table.fill(0);
+    }
+}
+
+impl Default for ButterflyHistory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+//Captures by moved piece, destination and captured piece type
+pub struct CaptureHistory {
+    table: Vec<i16>,
+}
+
+impl CaptureHistory {
+    pub fn new() -> Self {
+        Self {
+            table: vec![0; PIECE_TO * PTNB],
+        }
+    }
+
+    //Queen promotions without a capture use the slot of AllPieces
+    fn index(pc: Piece, to: Square, captured: PieceType) -> usize {
+        piece_to(pc, to) * PTNB + (captured as i32).max(0) as usize
+    }
+
+    pub fn get(&self, pc: Piece, to: Square, captured: PieceType) -> i32 {
+        self.table[Self::index(pc, to, captured)] as i32
+    }
+
+    pub fn update(&mut self, pc: Piece, to: Square, captured: PieceType, bonus: i32) {
+        gravity(
+            &mut self.table[Self::index(pc, to, captured)],
+            bonus,
+            CAPTURE_BOUND,
+        );
+    }
+
+    pub fn clear(&mut self) {
+        self.table.fill(0);
+    }
+}
+
+impl Default for CaptureHistory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+//Quiet moves by (piece, to) of an earlier move and (piece, to) of the move itself
+pub struct ContinuationHistory {
+    table: Vec<i16>,
+}
+
+impl ContinuationHistory {
+    pub fn new() -> Self {
+        Self {
+            table: vec![0; PIECE_TO * PIECE_TO],
+        }
+    }
+
+    fn index(prev: (Piece, Square), pc: Piece, to: Square) -> usize {
+        piece_to(prev.0, prev.1) * PIECE_TO + piece_to(pc, to)
+    }
+
+    pub fn get(&self, prev: (Piece, Square), pc: Piece, to: Square) -> i32 {
+        self.table[Self::index(prev, pc, to)] as i32
+    }
+
+    pub fn update(&mut self, prev: (Piece, Square), pc: Piece, to: Square, bonus: i32) {
+        gravity(
+            &mut self.table[Self::index(prev, pc, to)],
+            bonus,
+            CONTINUATION_BOUND,
+        );
+    }
+
+    pub fn clear(&mut self) {
+        self.table.fill(0);
+    }
+}
+
+impl Default for ContinuationHistory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+//The quiet move that refuted the previous move, by its (piece, to)
+pub struct CounterMoveHistory {
+    table: Vec<Move>,
+}
+
+impl CounterMoveHistory {
+    pub fn new() -> Self {
+        Self {
+            table: vec![Move::none(); PIECE_TO],
+        }
+    }
+
+    pub fn get(&self, prev: (Piece, Square)) -> Move {
+        self.table[piece_to(prev.0, prev.1)]
+    }
+
+    pub fn set(&mut self, prev: (Piece, Square), m: Move) {
+        self.table[piece_to(prev.0, prev.1)] = m;
+    }
+
+    pub fn clear(&mut self) {
+        self.table.fill(Move::none());
+    }
+}
+
+impl Default for CounterMoveHistory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+//How far the static evaluation was from the search result in positions sharing a pawn, minor
+//piece, major piece or non pawn structure, by side to move. Used to correct the static eval
+pub struct CorrectionHistory {
+    pawn: Vec<i16>,
+    minor: Vec<i16>,
+    major: Vec<i16>,
+    //Indexed by the side to move, then the side whose pieces the key describes
+    non_pawn: Vec<i16>,
+}
+
+pub const CORRECTION_HISTORY_LIMIT: i32 = 1024;
+const CORRECTION_HISTORY_SIZE: usize = 16384;
+
+impl CorrectionHistory {
+    pub fn new() -> Self {
+        Self {
+            pawn: vec![0; COLORNB * CORRECTION_HISTORY_SIZE],
+            minor: vec![0; COLORNB * CORRECTION_HISTORY_SIZE],
+            major: vec![0; COLORNB * CORRECTION_HISTORY_SIZE],
+            non_pawn: vec![0; COLORNB * COLORNB * CORRECTION_HISTORY_SIZE],
+        }
+    }
+
+    fn index(c: Color, key: Key) -> usize {
+        c as usize * CORRECTION_HISTORY_SIZE + (key as usize & (CORRECTION_HISTORY_SIZE - 1))
+    }
+
+    //Indices into pawn, minor, major and the two non pawn entries
+    fn indices(pos: &Position) -> [usize; 5] {
+        let us = pos.side_to_move();
+        [
+            Self::index(us, pos.pawn_key()),
+            Self::index(us, pos.minor_piece_key()),
+            Self::index(us, pos.major_piece_key()),
+            us as usize * COLORNB * CORRECTION_HISTORY_SIZE
+                + Self::index(Color::White, pos.non_pawn_key(Color::White)),
+            us as usize * COLORNB * CORRECTION_HISTORY_SIZE
+                + Self::index(Color::Black, pos.non_pawn_key(Color::Black)),
+        ]
+    }
+
+    //The static evaluation adjusted by what was learned, kept away from the mate range
+    pub fn correct(&self, pos: &Position, eval: Value) -> Value {
+        let [p, mi, ma, wnp, bnp] = Self::indices(pos);
+        let correction = 6995 * self.pawn[p] as i32
+            + 6593 * self.minor[mi] as i32
+            + 3040 * self.major[ma] as i32
+            + 7840 * (self.non_pawn[wnp] as i32 + self.non_pawn[bnp] as i32);
+        (eval + correction / 131072).clamp(VALUE_MATED_IN_MAX_PLY + 1, VALUE_MATE_IN_MAX_PLY - 1)
+    }
+
+    pub fn update(&mut self, pos: &Position, bonus: i32) {
+        let [p, mi, ma, wnp, bnp] = Self::indices(pos);
+        gravity(&mut self.pawn[p], bonus, CORRECTION_HISTORY_LIMIT);
+        gravity(&mut self.minor[mi], bonus, CORRECTION_HISTORY_LIMIT);
+        gravity(&mut self.major[ma], bonus, CORRECTION_HISTORY_LIMIT);
+        gravity(&mut self.non_pawn[wnp], bonus, CORRECTION_HISTORY_LIMIT);
+        gravity(&mut self.non_pawn[bnp], bonus, CORRECTION_HISTORY_LIMIT);
+    }
+
+    pub fn clear(&mut self) {
+        self.pawn.fill(0);
+        self.minor.fill(0);
+        self.major.fill(0);
+        self.non_pawn.fill(0);
+    }
+}
+
+impl Default for CorrectionHistory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+//Bonus for a search at depth that returned best_value where static_eval was expected
+pub fn correction_bonus(best_value: Value, static_eval: Value, depth: Depth) -> i32 {
+    ((best_value - static_eval) * depth / 8)
+        .clamp(-CORRECTION_HISTORY_LIMIT / 4, CORRECTION_HISTORY_LIMIT / 4)
+}
+
+//All the tables, kept between searches of the same game
+#[derive(Default)]
+pub struct Histories {
+    pub main: ButterflyHistory,
+    pub capture: CaptureHistory,
+    pub continuation: ContinuationHistory,
+    pub counter_moves: CounterMoveHistory,
+    pub correction: CorrectionHistory,
+}
+
+impl Histories {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.main.clear();
+        self.capture.clear();
+        self.continuation.clear();
+        self.counter_moves.clear();
+        self.correction.clear();
+    }
+
+    //Sum of the continuation histories of a quiet move, the previous move counted twice
+    pub fn continuation_score(&self, prev: &[(Piece, Square); 3], pc: Piece, to: Square) -> i32 {
+        2 * self.continuation.get(prev[0], pc, to)
+            + self.continuation.get(prev[1], pc, to)
+            + self.continuation.get(prev[2], pc, to)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_gravity() {
+        let mut h = ButterflyHistory::new();
+        let m = Move::new_from_to_sq(Square::SqE2, Square::SqE4);
+        h.update(Color::White, m, stat_bonus(4));
+        assert_eq!(h.get(Color::White, m), stat_bonus(4));
+        assert_eq!(h.get(Color::Black, m), 0);
+
+        //Repeated bonuses approach the bound without crossing it
+        for _ in 0..1000 {
+            h.update(Color::White, m, 100_000);
+        }
+        assert_eq!(h.get(Color::White, m), BUTTERFLY_BOUND);
+        h.update(Color::White, m, -stat_malus(10));
+        assert!(h.get(Color::White, m) < BUTTERFLY_BOUND - stat_malus(10));
+        for _ in 0..1000 {
+            h.update(Color::White, m, -stat_malus(20));
+        }
+        assert!(h.get(Color::White, m) >= -BUTTERFLY_BOUND);
+        h.clear();
+        assert_eq!(h.get(Color::White, m), 0);
+    }
+
+    #[test]
+    fn test_correction() {
+        let pos = Position::from_fen("4k3/pp6/8/8/8/8/PP6/2B1K3 w - - 0 1").unwrap();
+        //Same pawns and bishop, the other side to move
+        let black = Position::from_fen("4k3/pp6/8/8/8/8/PP6/2B1K3 b - - 0 1").unwrap();
+        //Same pawns, different pieces
+        let other = Position::from_fen("4k3/pp6/8/8/8/8/PP6/3RK3 w - - 0 1").unwrap();
+
+        let mut ch = CorrectionHistory::new();
+        assert_eq!(ch.correct(&pos, 100), 100);
+        assert_eq!(correction_bonus(300, 100, 16), CORRECTION_HISTORY_LIMIT / 4);
+        assert_eq!(correction_bonus(100, 116, 4), -8);
+        for _ in 0..100 {
+            ch.update(&pos, correction_bonus(400, 100, 10));
+        }
+        let corrected = ch.correct(&pos, 100);
+        //Roughly a pawn at most, even with every table saturated
+        assert!(corrected > 100 && corrected < 100 + 256);
+        assert_eq!(ch.correct(&black, 100), 100);
+        let partial = ch.correct(&other, 100);
+        assert!(partial > 100 && partial < corrected);
+
+        assert_eq!(ch.correct(&pos, VALUE_MATE), VALUE_MATE_IN_MAX_PLY - 1);
+        ch.clear();
+        assert_eq!(ch.correct(&pos, 100), 100);
+    }
+
+    #[test]
+    fn test_tables() {
+        let mut hs = Histories::new();
+        let prev = (Piece::BPawn, Square::SqE5);
+        let none = (Piece::NoPiece, Square::SqA1);
+        hs.capture
+            .update(Piece::WKnight, Square::SqE5, PieceType::Pawn, 500);
+        assert_eq!(
+            hs.capture
+                .get(Piece::WKnight, Square::SqE5, PieceType::Pawn),
+            500
+        );
+        assert_eq!(
+            hs.capture
+                .get(Piece::WKnight, Square::SqE5, PieceType::Knight),
+            0
+        );
+        hs.capture
+            .update(Piece::WPawn, Square::SqE8, PieceType::NoPieceType, 300);
+        assert_eq!(
+            hs.capture
+                .get(Piece::WPawn, Square::SqE8, PieceType::NoPieceType),
+            300
+        );
+
+        hs.continuation
+            .update(prev, Piece::WKnight, Square::SqF3, 400);
+        assert_eq!(
+            hs.continuation_score(&[prev, none, none], Piece::WKnight, Square::SqF3),
+            800
+        );
+        assert_eq!(
+            hs.continuation_score(&[none, prev, prev], Piece::WKnight, Square::SqF3),
+            800
+        );
+
+        let m = Move::new_from_to_sq(Square::SqG1, Square::SqF3);
+        hs.counter_moves.set(prev, m);
+        assert_eq!(hs.counter_moves.get(prev), m);
+        hs.clear();
+        assert_eq!(hs.counter_moves.get(prev), Move::none());
+        assert_eq!(hs.continuation.get(prev, Piece::WKnight, Square::SqF3), 0);
+    }
+}
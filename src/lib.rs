@@ -0,0 +1,13 @@
+pub mod board;
+pub mod eval;
+pub mod history;
+pub mod misc;
+pub mod movepick;
+pub mod nnue;
+pub mod pgn;
+pub mod search;
+pub mod thread;
+pub mod timeman;
+pub mod tt;
+pub mod types;
+pub mod uci;
@@ -1,4 +1,9 @@
-mod board;
-mod misc;
-mod types;
-fn main() {}
+use rusty_screbby::board::bitboard;
+use rusty_screbby::board::position::Position;
+use rusty_screbby::uci;
+
+fn main() {
+    bitboard::init();
+    Position::init();
+    uci::Uci::new().run();
+}
@@ -0,0 +1,586 @@
+use crate::board::movegen::{generate, ExtMove};
+use crate::board::position::Position;
+use crate::history::{captured_type, Histories};
+use crate::types::*;
+
+//The move picker hands out the pseudo legal moves of a position one at a time, generating and
+//scoring them lazily so that a cutoff on an early move saves the work for the rest
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Stage {
+    //Main search
+    MainTT,
+    CaptureInit,
+    GoodCapture,
+    Refutation,
+    QuietInit,
+    Quiet,
+    BadCapture,
+
+    //Main search and quiescence search when in check
+    EvasionTT,
+    EvasionInit,
+    Evasion,
+
+    //ProbCut, only captures that win at least the threshold
+    ProbCutTT,
+    ProbCutInit,
+    ProbCut,
+
+    //Quiescence search
+    QSearchTT,
+    QCaptureInit,
+    QCapture,
+    QCheckInit,
+    QCheck,
+}
+
+pub struct MovePicker {
+    stage: Stage,
+    tt_move: Move,
+    //Killers followed by the counter move
+    refutations: [Move; 3],
+    refutation_idx: usize,
+    //(piece, to) of the moves 1, 2 and 4 plies back for the continuation history
+    continuation: [(Piece, Square); 3],
+    moves: Vec<ExtMove>,
+    cur: usize,
+    bad_captures: Vec<ExtMove>,
+    bad_capture_idx: usize,
+    depth: Depth,
+    threshold: Value,
+}
+
+impl MovePicker {
+    fn with_stage(stage: Stage, tt_move: Move, depth: Depth) -> Self {
+        Self {
+            stage,
+            tt_move,
+            refutations: [Move::none(); 3],
+            refutation_idx: 0,
+            continuation: [(Piece::NoPiece, Square::SqA1); 3],
+            moves: Vec::new(),
+            cur: 0,
+            bad_captures: Vec::new(),
+            bad_capture_idx: 0,
+            depth,
+            threshold: VALUE_ZERO,
+        }
+    }
+
+    //Picker for the main search
+    pub fn new(
+        pos: &Position,
+        tt_move: Move,
+        depth: Depth,
+        killers: [Move; 2],
+        counter_move: Move,
+        continuation: [(Piece, Square); 3],
+    ) -> Self {
+        assert!(depth > 0);
+        let stage = if pos.checkers() != 0 {
+            Stage::EvasionTT
+        } else {
+            Stage::MainTT
+        };
+        let mut mp = Self::with_stage(stage, tt_move, depth);
+        mp.refutations = [killers[0], killers[1], counter_move];
+        mp.continuation = continuation;
+        if !mp.tt_move_valid(pos) {
+            mp.tt_move = Move::none();
+            mp.stage = mp.next_stage();
+        }
+        mp
+    }
+
+    //Picker for the quiescence search, quiet checks are only generated for depth >= DEPTH_QS_CHECKS
+    pub fn new_qsearch(
+        pos: &Position,
+        tt_move: Move,
+        depth: Depth,
+        continuation: [(Piece, Square); 3],
+    ) -> Self {
+        assert!(depth <= 0);
+        let stage = if pos.checkers() != 0 {
+            Stage::EvasionTT
+        } else {
+            Stage::QSearchTT
+        };
+        let mut mp = Self::with_stage(stage, tt_move, depth);
+        mp.continuation = continuation;
+        //Out of check the tt move is only searched if it is a move this picker would generate,
+        //a capture or promotion, or a check when checks are generated
+        if !mp.tt_move_valid(pos)
+            || (pos.checkers() == 0
+                && !pos.capture(tt_move)
+                && tt_move.type_of() != MoveType::Promotion
+                && (depth < DEPTH_QS_CHECKS || !pos.gives_check(tt_move)))
+        {
+            mp.tt_move = Move::none();
+            mp.stage = mp.next_stage();
+        }
+        mp
+    }
+
+    //Picker for ProbCut, captures whose static exchange evaluation is at least threshold
+    pub fn new_probcut(pos: &Position, tt_move: Move, threshold: Value) -> Self {
+        assert!(pos.checkers() == 0);
+        let mut mp = Self::with_stage(Stage::ProbCutTT, tt_move, 0);
+        mp.threshold = threshold;
+        if !mp.tt_move_valid(pos) || !pos.capture_stage(tt_move) || !pos.see_ge(tt_move, threshold)
+        {
+            mp.tt_move = Move::none();
+            mp.stage = mp.next_stage();
+        }
+        mp
+    }
+
+    //An empty table slot holds the null move, which pseudo_legal does not accept
+    fn tt_move_valid(&self, pos: &Position) -> bool {
+        self.tt_move.is_ok() && pos.pseudo_legal(self.tt_move)
+    }
+
+    fn next_stage(&self) -> Stage {
+        match self.stage {
+            Stage::MainTT => Stage::CaptureInit,
+            Stage::CaptureInit => Stage::GoodCapture,
+            Stage::GoodCapture => Stage::Refutation,
+            Stage::Refutation => Stage::QuietInit,
+            Stage::QuietInit => Stage::Quiet,
+            Stage::Quiet => Stage::BadCapture,
+            Stage::EvasionTT => Stage::EvasionInit,
+            Stage::EvasionInit => Stage::Evasion,
+            Stage::ProbCutTT => Stage::ProbCutInit,
+            Stage::ProbCutInit => Stage::ProbCut,
+            Stage::QSearchTT => Stage::QCaptureInit,
+            Stage::QCaptureInit => Stage::QCapture,
+            Stage::QCapture => Stage::QCheckInit,
+            Stage::QCheckInit => Stage::QCheck,
+            Stage::BadCapture | Stage::Evasion | Stage::ProbCut | Stage::QCheck => unreachable!(),
+        }
+    }
+
+    //Returns the next pseudo legal move, or Move::none() once all of them have been returned.
+    //pos must be the position the picker was created for
+    pub fn next_move(&mut self, pos: &Position, history: &Histories) -> Move {
+        loop {
+            match self.stage {
+                Stage::MainTT | Stage::EvasionTT | Stage::ProbCutTT | Stage::QSearchTT => {
+                    self.stage = self.next_stage();
+                    return self.tt_move;
+                }
+
+                Stage::CaptureInit | Stage::ProbCutInit | Stage::QCaptureInit => {
+                    self.moves = generate::<CAPTURES>(pos).into_ext_moves();
+                    self.score_captures(pos, history);
+                    partial_insertion_sort(&mut self.moves, i32::MIN);
+                    self.cur = 0;
+                    self.stage = self.next_stage();
+                }
+
+                Stage::GoodCapture => {
+                    while let Some(em) = self.select(|_| true) {
+                        //Losing captures are tried after the quiet moves
+                        if pos.see_ge(em.base, VALUE_ZERO) {
+                            return em.base;
+                        }
+                        self.bad_captures.push(em);
+                    }
+
+                    //A killer or counter move may be a capture in this position, and the
+                    //counter move may be one of the killers
+                    let [k0, k1, counter] = self.refutations;
+                    if counter == k0 || counter == k1 {
+                        self.refutations[2] = Move::none();
+                    }
+                    self.stage = self.next_stage();
+                }
+
+                Stage::Refutation => {
+                    while self.refutation_idx < self.refutations.len() {
+                        let m = self.refutations[self.refutation_idx];
+                        self.refutation_idx += 1;
+                        if m != Move::none()
+                            && m != self.tt_move
+                            && !pos.capture_stage(m)
+                            && pos.pseudo_legal(m)
+                        {
+                            return m;
+                        }
+                    }
+                    self.stage = self.next_stage();
+                }
+
+                Stage::QuietInit => {
+                    self.moves = generate::<QUIETS>(pos).into_ext_moves();
+                    self.score_quiets(pos, history);
+                    partial_insertion_sort(&mut self.moves, -3000 * self.depth);
+                    self.cur = 0;
+                    self.stage = self.next_stage();
+                }
+
+                Stage::Quiet => {
+                    let refutations = self.refutations;
+                    if let Some(em) = self.select(|m| !refutations.contains(&m)) {
+                        return em.base;
+                    }
+                    self.stage = self.next_stage();
+                }
+
+                Stage::BadCapture => {
+                    while let Some(em) = self.bad_captures.get(self.bad_capture_idx) {
+                        self.bad_capture_idx += 1;
+                        if em.base != self.tt_move {
+                            return em.base;
+                        }
+                    }
+                    return Move::none();
+                }
+
+                Stage::EvasionInit => {
+                    self.moves = generate::<EVASIONS>(pos).into_ext_moves();
+                    self.score_evasions(pos, history);
+                    partial_insertion_sort(&mut self.moves, i32::MIN);
+                    self.cur = 0;
+                    self.stage = self.next_stage();
+                }
+
+                Stage::Evasion | Stage::QCheck => {
+                    return self.select(|_| true).map_or(Move::none(), |em| em.base);
+                }
+
+                Stage::ProbCut => {
+                    let threshold = self.threshold;
+                    return self
+                        .select(|m| pos.see_ge(m, threshold))
+                        .map_or(Move::none(), |em| em.base);
+                }
+
+                Stage::QCapture => {
+                    if let Some(em) = self.select(|_| true) {
+                        return em.base;
+                    }
+                    if self.depth < DEPTH_QS_CHECKS {
+                        return Move::none();
+                    }
+                    self.stage = self.next_stage();
+                }
+
+                Stage::QCheckInit => {
+                    self.moves = generate::<QUIET_CHECKS>(pos).into_ext_moves();
+                    self.cur = 0;
+                    self.stage = self.next_stage();
+                }
+            }
+        }
+    }
+
+    //The next move of the current stage that is not the tt move and passes the filter
+    fn select(&mut self, mut filter: impl FnMut(Move) -> bool) -> Option<ExtMove> {
+        while let Some(&em) = self.moves.get(self.cur) {
+            self.cur += 1;
+            if em.base != self.tt_move && filter(em.base) {
+                return Some(em);
+            }
+        }
+        None
+    }
+
+    //Most valuable victim first, ties broken by the least valuable attacker and the capture
+    //history
+    fn score_captures(&mut self, pos: &Position, history: &Histories) {
+        for em in self.moves.iter_mut() {
+            let m = em.base;
+            let pc = pos.moved_piece(m);
+            em.value = captured_value(pos, m) * 8 - pc.type_of() as i32
+                + history.capture.get(pc, m.to_sq(), captured_type(pos, m)) / 16;
+        }
+    }
+
+    fn score_quiets(&mut self, pos: &Position, history: &Histories) {
+        let us = pos.side_to_move();
+        for em in self.moves.iter_mut() {
+            let m = em.base;
+            em.value = 2 * history.main.get(us, m)
+                + history.continuation_score(&self.continuation, pos.moved_piece(m), m.to_sq());
+        }
+    }
+
+    //Captures first by MVV-LVA, then the king moves and interpositions by history
+    fn score_evasions(&mut self, pos: &Position, history: &Histories) {
+        let us = pos.side_to_move();
+        for em in self.moves.iter_mut() {
+            let m = em.base;
+            let pc = pos.moved_piece(m);
+            em.value = if pos.capture_stage(m) {
+                captured_value(pos, m) - pc.type_of() as i32 + (1 << 28)
+            } else {
+                history.main.get(us, m)
+                    + history
+                        .continuation
+                        .get(self.continuation[0], pc, m.to_sq())
+            };
+        }
+    }
+}
+
+fn captured_value(pos: &Position, m: Move) -> Value {
+    if m.type_of() == MoveType::EnPassant {
+        PawnValue
+    } else {
+        PIECEVALUE[pos.piece_on(m.to_sq()) as usize]
+    }
+}
+
+//Sorts the moves with a value of at least limit in descending order in front of the others,
+//the order of the rest is unspecified
+fn partial_insertion_sort(moves: &mut [ExtMove], limit: i32) {
+    let mut sorted_end = 0;
+    for p in 1..moves.len() {
+        if moves[p].value >= limit {
+            let tmp = moves[p];
+            sorted_end += 1;
+            moves[p] = moves[sorted_end];
+            let mut q = sorted_end;
+            while q > 0 && moves[q - 1].value < tmp.value {
+                moves[q] = moves[q - 1];
+                q -= 1;
+            }
+            moves[q] = tmp;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::board::position::START_FEN;
+
+    const KIWIPETE: &str = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+
+    const NO_CONTINUATION: [(Piece, Square); 3] = [(Piece::NoPiece, Square::SqA1); 3];
+
+    fn collect(mp: &mut MovePicker, pos: &Position) -> Vec<Move> {
+        collect_with(mp, pos, &Histories::new())
+    }
+
+    fn collect_with(mp: &mut MovePicker, pos: &Position, history: &Histories) -> Vec<Move> {
+        let mut moves = Vec::new();
+        loop {
+            let m = mp.next_move(pos, history);
+            if m == Move::none() {
+                return moves;
+            }
+            moves.push(m);
+        }
+    }
+
+    fn uci_move(pos: &Position, s: &str) -> Move {
+        generate::<LEGAL>(pos)
+            .iter()
+            .find(|m| m.to_string() == s)
+            .unwrap()
+    }
+
+    //Every pseudo legal move exactly once
+    fn assert_complete(moves: &[Move], pos: &Position) {
+        let all = if pos.checkers() != 0 {
+            generate::<EVASIONS>(pos)
+        } else {
+            generate::<NON_EVASIONS>(pos)
+        };
+        assert_eq!(moves.len(), all.len(), "{}", pos.fen());
+        assert!(all.iter().all(|m| moves.contains(&m)));
+    }
+
+    #[test]
+    fn test_main_stages() {
+        let pos = Position::from_fen(KIWIPETE).unwrap();
+        let tt_move = uci_move(&pos, "e1g1");
+        let killer = uci_move(&pos, "a2a3");
+        //A capture can not be a killer, it is picked with the captures instead
+        let capture = uci_move(&pos, "e5f7");
+        let mut mp = MovePicker::new(&pos, tt_move, 5, [killer, capture], killer, NO_CONTINUATION);
+        let moves = collect(&mut mp, &pos);
+        assert_complete(&moves, &pos);
+        assert_eq!(moves[0], tt_move);
+
+        //Winning captures, then the killer, quiets and losing captures
+        let killer_idx = moves.iter().position(|&m| m == killer).unwrap();
+        let (good, rest) = moves[1..].split_at(killer_idx - 1);
+        assert!(good
+            .iter()
+            .all(|&m| pos.capture_stage(m) && pos.see_ge(m, VALUE_ZERO)));
+        assert!(good.contains(&uci_move(&pos, "d5e6")));
+        assert!(rest.contains(&uci_move(&pos, "f3f6")));
+        assert_eq!(good[0], uci_move(&pos, "e2a6"));
+        let first_bad = rest.iter().position(|&m| pos.capture_stage(m)).unwrap();
+        assert!(rest[first_bad..]
+            .iter()
+            .all(|&m| !pos.see_ge(m, VALUE_ZERO)));
+        assert!(rest[first_bad..].contains(&capture));
+    }
+
+    #[test]
+    fn test_invalid_tt_move_and_killers() {
+        let pos = Position::from_fen(START_FEN).unwrap();
+        //Moves of another position
+        let other = Position::from_fen(KIWIPETE).unwrap();
+        let mut mp = MovePicker::new(
+            &pos,
+            uci_move(&other, "e2a6"),
+            3,
+            [uci_move(&other, "e1g1"), Move::none()],
+            uci_move(&other, "d5d6"),
+            NO_CONTINUATION,
+        );
+        let moves = collect(&mut mp, &pos);
+        assert_complete(&moves, &pos);
+
+        //A legal move with promotion bits is not the tt move, or it would be picked twice
+        let alias = Move::new(uci_move(&pos, "e2e4").raw() | 3 << 12);
+        let mut mp = MovePicker::new(
+            &pos,
+            alias,
+            3,
+            [Move::none(); 2],
+            Move::none(),
+            NO_CONTINUATION,
+        );
+        let moves = collect(&mut mp, &pos);
+        assert!(!moves.contains(&alias));
+        assert_complete(&moves, &pos);
+
+        //The move of an empty table slot is the null move
+        let mut mp = MovePicker::new_qsearch(&pos, Move::null(), 0, NO_CONTINUATION);
+        assert!(collect(&mut mp, &pos).is_empty());
+    }
+
+    #[test]
+    fn test_history_ordering() {
+        let pos = Position::from_fen(START_FEN).unwrap();
+        let mut history = Histories::new();
+        let prev = (Piece::BPawn, Square::SqE5);
+        let continuation = [
+            prev,
+            (Piece::NoPiece, Square::SqA1),
+            (Piece::NoPiece, Square::SqA1),
+        ];
+        let main = uci_move(&pos, "b1c3");
+        let cont = uci_move(&pos, "g1f3");
+        let bad = uci_move(&pos, "e2e4");
+        history.main.update(Color::White, main, 2000);
+        history
+            .continuation
+            .update(prev, Piece::WKnight, Square::SqF3, 3000);
+        history.main.update(Color::White, bad, -2000);
+
+        let mut mp = MovePicker::new(
+            &pos,
+            Move::none(),
+            3,
+            [Move::none(); 2],
+            Move::none(),
+            continuation,
+        );
+        let moves = collect_with(&mut mp, &pos, &history);
+        assert_complete(&moves, &pos);
+        assert_eq!(&moves[..2], &[cont, main]);
+        assert_eq!(*moves.last().unwrap(), bad);
+    }
+
+    #[test]
+    fn test_evasions() {
+        let pos = Position::from_fen("4k3/8/8/3Q4/8/8/8/4K2r w - - 0 1").unwrap();
+        let mut mp = MovePicker::new(
+            &pos,
+            Move::none(),
+            1,
+            [Move::none(); 2],
+            Move::none(),
+            NO_CONTINUATION,
+        );
+        let moves = collect(&mut mp, &pos);
+        assert_complete(&moves, &pos);
+        assert_eq!(moves[0], uci_move(&pos, "d5h1"));
+
+        let mut mp =
+            MovePicker::new_qsearch(&pos, Move::none(), DEPTH_QS_NO_CHECKS, NO_CONTINUATION);
+        assert_complete(&collect(&mut mp, &pos), &pos);
+    }
+
+    #[test]
+    fn test_qsearch() {
+        let pos = Position::from_fen(KIWIPETE).unwrap();
+        let captures = generate::<CAPTURES>(&pos);
+        let checks = generate::<QUIET_CHECKS>(&pos);
+
+        let mut mp =
+            MovePicker::new_qsearch(&pos, Move::none(), DEPTH_QS_NO_CHECKS, NO_CONTINUATION);
+        let moves = collect(&mut mp, &pos);
+        assert_eq!(moves.len(), captures.len());
+        assert_eq!(moves[0], uci_move(&pos, "e2a6"));
+
+        let tt_move = uci_move(&pos, "d5e6");
+        let mut mp = MovePicker::new_qsearch(&pos, tt_move, DEPTH_QS_CHECKS, NO_CONTINUATION);
+        let moves = collect(&mut mp, &pos);
+        assert_eq!(moves.len(), captures.len() + checks.len());
+        assert_eq!(moves[0], tt_move);
+        assert!(moves[captures.len()..]
+            .iter()
+            .all(|&m| !pos.capture_stage(m)));
+    }
+
+    #[test]
+    fn test_qsearch_quiet_tt_move() {
+        //A quiet tt move that does not give check is never searched
+        let pos = Position::from_fen(KIWIPETE).unwrap();
+        let tt_move = uci_move(&pos, "a2a3");
+        for depth in [DEPTH_QS_CHECKS, DEPTH_QS_NO_CHECKS] {
+            let mut mp = MovePicker::new_qsearch(&pos, tt_move, depth, NO_CONTINUATION);
+            assert!(!collect(&mut mp, &pos).contains(&tt_move));
+        }
+
+        //A quiet check only when checks are generated
+        let pos = Position::from_fen("4k3/8/8/8/8/8/8/R3K3 w - - 0 1").unwrap();
+        let tt_move = uci_move(&pos, "a1a8");
+        let mut mp = MovePicker::new_qsearch(&pos, tt_move, DEPTH_QS_NO_CHECKS, NO_CONTINUATION);
+        assert!(collect(&mut mp, &pos).is_empty());
+        let mut mp = MovePicker::new_qsearch(&pos, tt_move, DEPTH_QS_CHECKS, NO_CONTINUATION);
+        assert_eq!(collect(&mut mp, &pos), [tt_move]);
+    }
+
+    #[test]
+    fn test_probcut() {
+        let pos = Position::from_fen(KIWIPETE).unwrap();
+        let mut mp = MovePicker::new_probcut(&pos, Move::none(), KnightValue);
+        let moves = collect(&mut mp, &pos);
+        assert!(!moves.is_empty());
+        assert!(moves.iter().all(|&m| pos.see_ge(m, KnightValue)));
+        let expected = generate::<CAPTURES>(&pos)
+            .iter()
+            .filter(|&m| pos.see_ge(m, KnightValue))
+            .count();
+        assert_eq!(moves.len(), expected);
+
+        //A tt move that does not win enough is not returned
+        let mut mp = MovePicker::new_probcut(&pos, uci_move(&pos, "a1b1"), KnightValue);
+        assert_eq!(collect(&mut mp, &pos), moves);
+    }
+
+    #[test]
+    fn test_partial_insertion_sort() {
+        let mut moves: Vec<ExtMove> = [5, -10, 30, 0, 7, -3]
+            .iter()
+            .map(|&v| ExtMove {
+                base: Move::none(),
+                value: v,
+            })
+            .collect();
+        partial_insertion_sort(&mut moves, 0);
+        let values: Vec<i32> = moves.iter().map(|em| em.value).collect();
+        assert_eq!(&values[..4], &[30, 7, 5, 0]);
+        partial_insertion_sort(&mut moves, i32::MIN);
+        let values: Vec<i32> = moves.iter().map(|em| em.value).collect();
+        assert_eq!(values, [30, 7, 5, 0, -3, -10]);
+    }
+}
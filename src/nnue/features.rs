@@ -0,0 +1,89 @@
+use crate::types::*;
+
+//Input feature sets. Both are indexed by (king square, piece, square) from the point of view
+//of one side, with the board rotated for black
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FeatureSet {
+    //Kings only select the bucket, 10 piece kinds plus one unused slot per king square
+    HalfKP,
+    //Kings are features as well, 12 piece kinds per king square
+    HalfKA,
+}
+
+const HALFKP_PIECES: usize = 10 * SQNB_BOARD + 1;
+const HALFKA_PIECES: usize = 12 * SQNB_BOARD;
+const SQNB_BOARD: usize = 64;
+
+impl FeatureSet {
+    pub const fn dimensions(&self) -> usize {
+        match self {
+            FeatureSet::HalfKP => SQNB_BOARD * HALFKP_PIECES,
+            FeatureSet::HalfKA => SQNB_BOARD * HALFKA_PIECES,
+        }
+    }
+
+    //Whether a move of this piece changes any feature other than through a king bucket change
+    pub fn is_feature(&self, pc: Piece) -> bool {
+        *self == FeatureSet::HalfKA || pc.type_of() != PieceType::King
+    }
+
+    //Index of piece pc on square s, seen by perspective whose king stands on ksq
+    pub fn index(&self, perspective: Color, s: Square, pc: Piece, ksq: Square) -> usize {
+        let s = orient(perspective, s);
+        let ksq = orient(perspective, ksq);
+        //Own pieces first, then the opponent's
+        let pt = pc.type_of() as usize - PieceType::Pawn as usize;
+        let theirs = (pc.color() != perspective) as usize;
+        match self {
+            FeatureSet::HalfKP => 1 + (2 * pt + theirs) * SQNB_BOARD + s + HALFKP_PIECES * ksq,
+            FeatureSet::HalfKA => (6 * theirs + pt) * SQNB_BOARD + s + HALFKA_PIECES * ksq,
+        }
+    }
+}
+
+//Rotate the board by 180 degrees for black
+fn orient(perspective: Color, s: Square) -> usize {
+    match perspective {
+        Color::White => s as usize,
+        _ => s as usize ^ 63,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_indices() {
+        assert_eq!(FeatureSet::HalfKP.dimensions(), 41024);
+        assert_eq!(FeatureSet::HalfKA.dimensions(), 49152);
+
+        let ksq = Square::SqE1;
+        let kp = FeatureSet::HalfKP;
+        assert_eq!(
+            kp.index(Color::White, Square::SqA1, Piece::WPawn, Square::SqA1),
+            1
+        );
+        assert_eq!(
+            kp.index(Color::White, Square::SqA1, Piece::BPawn, Square::SqA1),
+            65
+        );
+        assert_eq!(
+            kp.index(Color::White, Square::SqH8, Piece::BQueen, Square::SqH8),
+            HALFKP_PIECES * 64 - 1
+        );
+
+        //The same piece seen from the other side with the colors swapped
+        for fs in [FeatureSet::HalfKP, FeatureSet::HalfKA] {
+            assert_eq!(
+                fs.index(Color::White, Square::SqC3, Piece::WKnight, ksq),
+                fs.index(Color::Black, Square::SqF6, Piece::BKnight, Square::SqD8)
+            );
+            assert!(
+                fs.index(Color::Black, Square::SqH8, Piece::WQueen, Square::SqA1) < fs.dimensions()
+            );
+        }
+        assert!(!kp.is_feature(Piece::BKing));
+        assert!(FeatureSet::HalfKA.is_feature(Piece::BKing));
+    }
+}
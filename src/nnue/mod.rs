@@ -0,0 +1,209 @@
+//Efficiently updatable neural network evaluation. The first layer is kept per position in an
+//accumulator that is updated from the pieces moved by each move instead of being recomputed
+pub mod features;
+pub mod network;
+pub mod simd;
+
+use crate::board::bitboard as bb;
+use crate::board::position::Position;
+use crate::types::*;
+
+pub use network::{Network, NnueError};
+
+pub const HALF_DIMENSIONS: usize = 256;
+
+//Output of the feature transformer for both perspectives
+#[derive(Debug, Clone, Copy)]
+pub struct Accumulator {
+    pub values: [[i16; HALF_DIMENSIONS]; COLORNB],
+    pub computed: [bool; COLORNB],
+}
+
+impl Default for Accumulator {
+    fn default() -> Self {
+        Self {
+            values: [[0; HALF_DIMENSIONS]; COLORNB],
+            computed: [false; COLORNB],
+        }
+    }
+}
+
+//Evaluation from the side to move's point of view. accumulators has an entry for each position
+//of the line that led to pos, indexed by ply with pos last, and is updated from the last entry
+//that is computed. The caller marks an entry as not computed when it makes a move to its ply
+pub fn evaluate(net: &Network, pos: &Position, accumulators: &mut [Accumulator]) -> Value {
+    update_accumulator(net, pos, accumulators, Color::White);
+    update_accumulator(net, pos, accumulators, Color::Black);
+    net.propagate(accumulators.last().unwrap(), pos.side_to_move())
+}
+
+fn update_accumulator(
+    net: &Network,
+    pos: &Position,
+    accumulators: &mut [Accumulator],
+    perspective: Color,
+) {
+    let p = perspective as usize;
+    let fs = net.feature_set();
+    let ksq = pos.square(perspective, PieceType::King);
+    let king = make_piece(perspective, PieceType::King);
+    let last = accumulators.len() - 1;
+    //The state of the position at ply i of the line
+    let states = pos.states();
+    let state = |i: usize| &states[states.len() - 1 - (last - i)];
+
+    //Walk back to the last computed entry. A move of our king changes every feature
+    let mut i = last;
+    while !accumulators[i].computed[p] {
+        if i == 0 || state(i).dirty_piece().piece[0] == king {
+            refresh_accumulator(net, pos, &mut accumulators[last], perspective);
+            return;
+        }
+        i -= 1;
+    }
+
+    for j in i + 1..=last {
+        let (prev, cur) = accumulators.split_at_mut(j);
+        let dp = *state(j).dirty_piece();
+        let acc = &mut cur[0];
+        acc.values[p] = prev[j - 1].values[p];
+        for k in 0..dp.dirty_num {
+            let pc = dp.piece[k];
+            if !fs.is_feature(pc) {
+                continue;
+            }
+            if dp.from[k] != Square::SqNone {
+                let w = net.feature_weights(fs.index(perspective, dp.from[k], pc, ksq));
+                simd::sub_assign(&mut acc.values[p], w);
+            }
+            if dp.to[k] != Square::SqNone {
+                let w = net.feature_weights(fs.index(perspective, dp.to[k], pc, ksq));
+                simd::add_assign(&mut acc.values[p], w);
+            }
+        }
+        acc.computed[p] = true;
+    }
+}
+
+//Recompute the accumulator of pos from scratch
+fn refresh_accumulator(net: &Network, pos: &Position, acc: &mut Accumulator, perspective: Color) {
+    let p = perspective as usize;
+    let fs = net.feature_set();
+    let ksq = pos.square(perspective, PieceType::King);
+    let mut values = [0i16; HALF_DIMENSIONS];
+    values.copy_from_slice(net.biases());
+
+    let mut pieces = pos.all_pieces();
+    while pieces != 0 {
+        let s = bb::pop_lsb(&mut pieces);
+        let pc = pos.piece_on(s);
+        if fs.is_feature(pc) {
+            simd::add_assign(
+                &mut values,
+                net.feature_weights(fs.index(perspective, s, pc, ksq)),
+            );
+        }
+    }
+
+    acc.values[p] = values;
+    acc.computed[p] = true;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::board::movegen::generate;
+    use crate::board::position::{StateInfo, START_FEN};
+    use crate::nnue::features::FeatureSet;
+    use crate::nnue::network::random_network_bytes;
+    use std::sync::OnceLock;
+
+    fn network(fs: FeatureSet) -> &'static Network {
+        static HALFKP: OnceLock<Network> = OnceLock::new();
+        static HALFKA: OnceLock<Network> = OnceLock::new();
+        let cell = match fs {
+            FeatureSet::HalfKP => &HALFKP,
+            FeatureSet::HalfKA => &HALFKA,
+        };
+        cell.get_or_init(|| Network::from_bytes(&random_network_bytes(fs, 0x5eed)).unwrap())
+    }
+
+    fn fresh_eval(net: &Network, fen: &str) -> Value {
+        let pos = Position::from_fen(fen).unwrap();
+        evaluate(net, &pos, &mut [Accumulator::default()])
+    }
+
+    //Walks the tree and compares every incremental evaluation against a refresh from the fen.
+    //accumulators ends with the entry of pos
+    fn check_tree(
+        net: &Network,
+        pos: &mut Position,
+        accumulators: &mut Vec<Accumulator>,
+        depth: i32,
+    ) {
+        assert_eq!(
+            evaluate(net, pos, accumulators),
+            fresh_eval(net, &pos.fen()),
+            "{}",
+            pos.fen()
+        );
+        if depth == 0 {
+            return;
+        }
+        for m in generate::<LEGAL>(pos).iter() {
+            let mut st = StateInfo::default();
+            let gives_check = pos.gives_check(m);
+            pos.do_move(m, &mut st, gives_check);
+            accumulators.push(Accumulator::default());
+            check_tree(net, pos, accumulators, depth - 1);
+            accumulators.pop();
+            pos.undo_move(m);
+        }
+        assert_eq!(
+            evaluate(net, pos, accumulators),
+            fresh_eval(net, &pos.fen())
+        );
+    }
+
+    #[test]
+    fn test_incremental_matches_refresh() {
+        let fens = [
+            //Castling both sides
+            (
+                "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+                1,
+            ),
+            //Promotions with and without capture
+            ("n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1", 2),
+            //En passant and king moves
+            ("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", 2),
+        ];
+        for fs in [FeatureSet::HalfKP, FeatureSet::HalfKA] {
+            let net = network(fs);
+            for (fen, depth) in fens {
+                let mut pos = Position::from_fen(fen).unwrap();
+                check_tree(net, &mut pos, &mut vec![Accumulator::default()], depth);
+            }
+        }
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let net = network(FeatureSet::HalfKP);
+        let v = fresh_eval(net, START_FEN);
+        assert!(v > VALUE_MATED_IN_MAX_PLY && v < VALUE_MATE_IN_MAX_PLY);
+
+        //Only the previous entry needs an update after a move
+        let mut pos = Position::from_fen(START_FEN).unwrap();
+        let mut accumulators = [Accumulator::default(); 2];
+        evaluate(net, &pos, &mut accumulators[..1]);
+        let m = generate::<LEGAL>(&pos).iter().next().unwrap();
+        let mut st = StateInfo::default();
+        pos.do_move(m, &mut st, false);
+        evaluate(net, &pos, &mut accumulators);
+        assert_eq!(accumulators[1].computed, [true; COLORNB]);
+        let mut refreshed = [Accumulator::default()];
+        evaluate(net, &pos, &mut refreshed);
+        assert_eq!(accumulators[1].values, refreshed[0].values);
+    }
+}
@@ -0,0 +1,256 @@
+use crate::nnue::features::FeatureSet;
+use crate::nnue::simd;
+use crate::nnue::{Accumulator, HALF_DIMENSIONS};
+use crate::types::*;
+use std::fmt;
+use std::path::Path;
+
+//Stockfish 12 style network: (HalfKP|HalfKA) -> 256x2 -> 32 -> 32 -> 1
+pub const VERSION: u32 = 0x7AF32F16;
+const L1: usize = 32;
+const L2: usize = 32;
+const FV_SCALE: i32 = 16;
+const WEIGHT_SCALE_BITS: i32 = 6;
+//Hash plus the three affine layers
+const NETWORK_BYTES: usize =
+    4 + (L1 * 4 + L1 * 2 * HALF_DIMENSIONS) + (L2 * 4 + L2 * L1) + (4 + L2);
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum NnueError {
+    Io(String),
+    InvalidVersion(u32),
+    UnknownArchitecture(usize),
+    Truncated,
+}
+
+impl fmt::Display for NnueError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NnueError::Io(s) => write!(f, "could not read network: {}", s),
+            NnueError::InvalidVersion(v) => write!(f, "unsupported network version: {:#x}", v),
+            NnueError::UnknownArchitecture(n) => {
+                write!(f, "unknown network architecture with {} input features", n)
+            }
+            NnueError::Truncated => write!(f, "network file is truncated"),
+        }
+    }
+}
+
+impl std::error::Error for NnueError {}
+
+struct Affine<const IN: usize, const OUT: usize> {
+    biases: Vec<i32>,
+    weights: Vec<i8>,
+}
+
+impl<const IN: usize, const OUT: usize> Affine<IN, OUT> {
+    fn read(r: &mut Reader) -> Result<Self, NnueError> {
+        let biases = (0..OUT).map(|_| r.i32()).collect::<Result<_, _>>()?;
+        let weights = r.bytes(IN * OUT)?.iter().map(|&b| b as i8).collect();
+        Ok(Self { biases, weights })
+    }
+
+    fn propagate(&self, input: &[u8], output: &mut [i32; OUT]) {
+        for (i, out) in output.iter_mut().enumerate() {
+            *out = self.biases[i] + simd::dot(input, &self.weights[i * IN..(i + 1) * IN]);
+        }
+    }
+}
+
+pub struct Network {
+    description: String,
+    feature_set: FeatureSet,
+    ft_biases: Vec<i16>,
+    ft_weights: Vec<i16>,
+    l1: Affine<{ 2 * HALF_DIMENSIONS }, L1>,
+    l2: Affine<L1, L2>,
+    output: Affine<L2, 1>,
+}
+
+impl Network {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, NnueError> {
+        let data = std::fs::read(path).map_err(|e| NnueError::Io(e.to_string()))?;
+        Self::from_bytes(&data)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, NnueError> {
+        let mut r = Reader { data, pos: 0 };
+        let version = r.u32()?;
+        if version != VERSION {
+            return Err(NnueError::InvalidVersion(version));
+        }
+        let _hash = r.u32()?;
+        let len = r.u32()? as usize;
+        let description = String::from_utf8_lossy(r.bytes(len)?).into_owned();
+
+        //The feature set is not part of the header, so it is inferred from the size of the
+        //feature transformer
+        let ft_bytes = data
+            .len()
+            .checked_sub(r.pos + 4 + HALF_DIMENSIONS * 2 + NETWORK_BYTES)
+            .ok_or(NnueError::Truncated)?;
+        let inputs = ft_bytes / (HALF_DIMENSIONS * 2);
+        let feature_set = [FeatureSet::HalfKP, FeatureSet::HalfKA]
+            .into_iter()
+            .find(|fs| fs.dimensions() == inputs && ft_bytes % (HALF_DIMENSIONS * 2) == 0)
+            .ok_or(NnueError::UnknownArchitecture(inputs))?;
+
+        let _ft_hash = r.u32()?;
+        let ft_biases = r.i16s(HALF_DIMENSIONS)?;
+        let ft_weights = r.i16s(inputs * HALF_DIMENSIONS)?;
+        let _net_hash = r.u32()?;
+        let l1 = Affine::read(&mut r)?;
+        let l2 = Affine::read(&mut r)?;
+        let output = Affine::read(&mut r)?;
+
+        Ok(Self {
+            description,
+            feature_set,
+            ft_biases,
+            ft_weights,
+            l1,
+            l2,
+            output,
+        })
+    }
+
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    pub fn feature_set(&self) -> FeatureSet {
+        self.feature_set
+    }
+
+    pub fn biases(&self) -> &[i16] {
+        &self.ft_biases
+    }
+
+    //Column of the feature transformer for one active feature
+    pub fn feature_weights(&self, index: usize) -> &[i16] {
+        &self.ft_weights[index * HALF_DIMENSIONS..(index + 1) * HALF_DIMENSIONS]
+    }
+
+    //Forward pass from an up to date accumulator, from the point of view of stm
+    pub fn propagate(&self, acc: &Accumulator, stm: Color) -> Value {
+        let mut transformed = [0u8; 2 * HALF_DIMENSIONS];
+        for (half, perspective) in [stm, !stm].into_iter().enumerate() {
+            let values = &acc.values[perspective as usize];
+            for (out, &v) in transformed[half * HALF_DIMENSIONS..].iter_mut().zip(values) {
+                *out = v.clamp(0, 127) as u8;
+            }
+        }
+
+        let mut l1_out = [0i32; L1];
+        self.l1.propagate(&transformed, &mut l1_out);
+        let l1_act = clipped_relu(&l1_out);
+        let mut l2_out = [0i32; L2];
+        self.l2.propagate(&l1_act, &mut l2_out);
+        let l2_act = clipped_relu(&l2_out);
+        let mut output = [0i32; 1];
+        self.output.propagate(&l2_act, &mut output);
+
+        (output[0] / FV_SCALE).clamp(VALUE_MATED_IN_MAX_PLY + 1, VALUE_MATE_IN_MAX_PLY - 1)
+    }
+}
+
+fn clipped_relu<const N: usize>(input: &[i32; N]) -> [u8; N] {
+    input.map(|x| (x >> WEIGHT_SCALE_BITS).clamp(0, 127) as u8)
+}
+
+//Little endian cursor over the network file
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], NnueError> {
+        let end = self.pos.checked_add(n).ok_or(NnueError::Truncated)?;
+        let b = self.data.get(self.pos..end).ok_or(NnueError::Truncated)?;
+        self.pos = end;
+        Ok(b)
+    }
+
+    fn u32(&mut self) -> Result<u32, NnueError> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn i32(&mut self) -> Result<i32, NnueError> {
+        Ok(self.u32()? as i32)
+    }
+
+    fn i16s(&mut self, n: usize) -> Result<Vec<i16>, NnueError> {
+        Ok(self
+            .bytes(n * 2)?
+            .chunks_exact(2)
+            .map(|c| i16::from_le_bytes([c[0], c[1]]))
+            .collect())
+    }
+}
+
+//Writes a network file with small random weights, for tests
+#[cfg(test)]
+pub(crate) fn random_network_bytes(feature_set: FeatureSet, seed: u64) -> Vec<u8> {
+    use crate::misc::Prng;
+
+    let mut rng = Prng::new(seed);
+    let mut small = |range: i64| (rng.rand64() % (2 * range as u64 + 1)) as i64 - range;
+    let description = b"random test network";
+    let mut out = Vec::new();
+    out.extend(VERSION.to_le_bytes());
+    out.extend(0u32.to_le_bytes());
+    out.extend((description.len() as u32).to_le_bytes());
+    out.extend(description);
+    out.extend(0u32.to_le_bytes());
+    for _ in 0..HALF_DIMENSIONS {
+        out.extend((small(32) as i16 + 32).to_le_bytes());
+    }
+    for _ in 0..feature_set.dimensions() * HALF_DIMENSIONS {
+        out.extend((small(16) as i16).to_le_bytes());
+    }
+    out.extend(0u32.to_le_bytes());
+    for (inputs, outputs) in [(2 * HALF_DIMENSIONS, L1), (L1, L2), (L2, 1)] {
+        for _ in 0..outputs {
+            out.extend((small(1000) as i32).to_le_bytes());
+        }
+        for _ in 0..inputs * outputs {
+            out.push(small(16) as i8 as u8);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_loader() {
+        let bytes = random_network_bytes(FeatureSet::HalfKA, 7);
+        let net = Network::from_bytes(&bytes).unwrap();
+        assert_eq!(net.feature_set(), FeatureSet::HalfKA);
+        assert_eq!(net.description(), "random test network");
+
+        let mut bad_version = bytes.clone();
+        bad_version[0] ^= 1;
+        assert_eq!(
+            Network::from_bytes(&bad_version).err(),
+            Some(NnueError::InvalidVersion(VERSION ^ 1))
+        );
+        assert_eq!(
+            Network::from_bytes(&bytes[..100]).err(),
+            Some(NnueError::Truncated)
+        );
+        assert_eq!(
+            Network::from_bytes(&bytes[..bytes.len() - HALF_DIMENSIONS * 2]).err(),
+            Some(NnueError::UnknownArchitecture(
+                FeatureSet::HalfKA.dimensions() - 1
+            ))
+        );
+        assert!(matches!(
+            Network::from_file("/nonexistent/net.nnue"),
+            Err(NnueError::Io(_))
+        ));
+    }
+}
@@ -0,0 +1,152 @@
+//Vector kernels used by the accumulator and the affine layers. The AVX2 versions are picked
+//at runtime when the cpu supports them, everything else uses the scalar fallback
+
+#[cfg(target_arch = "x86_64")]
+use std::sync::OnceLock;
+
+#[cfg(target_arch = "x86_64")]
+pub fn has_avx2() -> bool {
+    static AVX2: OnceLock<bool> = OnceLock::new();
+    *AVX2.get_or_init(|| is_x86_feature_detected!("avx2"))
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+pub fn has_avx2() -> bool {
+    false
+}
+
+//acc += w
+pub fn add_assign(acc: &mut [i16], w: &[i16]) {
+    #[cfg(target_arch = "x86_64")]
+    if has_avx2() {
+        unsafe { avx2::add_assign(acc, w) };
+        return;
+    }
+    scalar::add_assign(acc, w);
+}
+
+//acc -= w
+pub fn sub_assign(acc: &mut [i16], w: &[i16]) {
+    #[cfg(target_arch = "x86_64")]
+    if has_avx2() {
+        unsafe { avx2::sub_assign(acc, w) };
+        return;
+    }
+    scalar::sub_assign(acc, w);
+}
+
+//Dot product of clipped activations (0..=127) with the weights of one output neuron
+pub fn dot(input: &[u8], weights: &[i8]) -> i32 {
+    #[cfg(target_arch = "x86_64")]
+    if has_avx2() {
+        return unsafe { avx2::dot(input, weights) };
+    }
+    scalar::dot(input, weights)
+}
+
+pub mod scalar {
+    //Wrapping arithmetic, the same as the vector instructions
+    pub fn add_assign(acc: &mut [i16], w: &[i16]) {
+        for (a, &b) in acc.iter_mut().zip(w) {
+            *a = a.wrapping_add(b);
+        }
+    }
+
+    pub fn sub_assign(acc: &mut [i16], w: &[i16]) {
+        for (a, &b) in acc.iter_mut().zip(w) {
+            *a = a.wrapping_sub(b);
+        }
+    }
+
+    pub fn dot(input: &[u8], weights: &[i8]) -> i32 {
+        input
+            .iter()
+            .zip(weights)
+            .map(|(&x, &w)| x as i32 * w as i32)
+            .sum()
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod avx2 {
+    use std::arch::x86_64::*;
+
+    //Lengths must be a multiple of 16
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn add_assign(acc: &mut [i16], w: &[i16]) {
+        assert!(acc.len() == w.len() && acc.len().is_multiple_of(16));
+        for i in (0..acc.len()).step_by(16) {
+            let a = _mm256_loadu_si256(acc.as_ptr().add(i) as *const __m256i);
+            let b = _mm256_loadu_si256(w.as_ptr().add(i) as *const __m256i);
+            _mm256_storeu_si256(
+                acc.as_mut_ptr().add(i) as *mut __m256i,
+                _mm256_add_epi16(a, b),
+            );
+        }
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn sub_assign(acc: &mut [i16], w: &[i16]) {
+        assert!(acc.len() == w.len() && acc.len().is_multiple_of(16));
+        for i in (0..acc.len()).step_by(16) {
+            let a = _mm256_loadu_si256(acc.as_ptr().add(i) as *const __m256i);
+            let b = _mm256_loadu_si256(w.as_ptr().add(i) as *const __m256i);
+            _mm256_storeu_si256(
+                acc.as_mut_ptr().add(i) as *mut __m256i,
+                _mm256_sub_epi16(a, b),
+            );
+        }
+    }
+
+    //Lengths must be a multiple of 32. The activations are at most 127 so the pairwise sums
+    //of maddubs can not saturate
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn dot(input: &[u8], weights: &[i8]) -> i32 {
+        assert!(input.len() == weights.len() && input.len().is_multiple_of(32));
+        let ones = _mm256_set1_epi16(1);
+        let mut sum = _mm256_setzero_si256();
+        for i in (0..input.len()).step_by(32) {
+            let x = _mm256_loadu_si256(input.as_ptr().add(i) as *const __m256i);
+            let w = _mm256_loadu_si256(weights.as_ptr().add(i) as *const __m256i);
+            let products = _mm256_madd_epi16(_mm256_maddubs_epi16(x, w), ones);
+            sum = _mm256_add_epi32(sum, products);
+        }
+        let sum128 = _mm_add_epi32(
+            _mm256_castsi256_si128(sum),
+            _mm256_extracti128_si256(sum, 1),
+        );
+        let sum64 = _mm_add_epi32(sum128, _mm_unpackhi_epi64(sum128, sum128));
+        let sum32 = _mm_add_epi32(sum64, _mm_shuffle_epi32(sum64, 1));
+        _mm_cvtsi128_si32(sum32)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::misc::Prng;
+
+    #[test]
+    fn test_kernels_agree() {
+        let mut rng = Prng::new(1070372);
+        let acc: Vec<i16> = (0..256).map(|_| rng.rand64() as i16).collect();
+        let w: Vec<i16> = (0..256).map(|_| rng.rand64() as i16).collect();
+        let input: Vec<u8> = (0..512).map(|_| (rng.rand64() % 128) as u8).collect();
+        let weights: Vec<i8> = (0..512).map(|_| rng.rand64() as i8).collect();
+
+        let mut expected = acc.clone();
+        scalar::add_assign(&mut expected, &w);
+        let mut actual = acc.clone();
+        add_assign(&mut actual, &w);
+        assert_eq!(actual, expected);
+
+        scalar::sub_assign(&mut expected, &w);
+        sub_assign(&mut actual, &w);
+        assert_eq!(actual, acc);
+        assert_eq!(expected, acc);
+
+        assert_eq!(dot(&input, &weights), scalar::dot(&input, &weights));
+        let max = vec![127u8; 32];
+        assert_eq!(dot(&max, &[-128; 32]), 127 * -128 * 32);
+    }
+}
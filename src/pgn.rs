@@ -0,0 +1,635 @@
+use crate::board::movegen::generate;
+use crate::board::position::{FenError, Position, StateInfo, START_FEN};
+use crate::board::san::{parse_san, to_san};
+use crate::types::*;
+use std::fmt;
+use std::path::Path;
+
+//Export format lines stay below 80 characters
+const LINE_WIDTH: usize = 79;
+const RESULTS: [&str; 4] = ["1-0", "0-1", "1/2-1/2", "*"];
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum PgnError {
+    Io(String),
+    InvalidTag(String),
+    UnterminatedTag,
+    UnterminatedComment,
+    UnterminatedVariation,
+    UnexpectedToken(String),
+    Fen(FenError),
+    IllegalMove { ply: usize, san: String },
+}
+
+impl fmt::Display for PgnError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PgnError::Io(s) => write!(f, "could not read pgn: {}", s),
+            PgnError::InvalidTag(s) => write!(f, "invalid tag pair: {}", s),
+            PgnError::UnterminatedTag => write!(f, "unterminated tag pair"),
+            PgnError::UnterminatedComment => write!(f, "unterminated comment"),
+            PgnError::UnterminatedVariation => write!(f, "unterminated variation"),
+            PgnError::UnexpectedToken(s) => write!(f, "unexpected token: {}", s),
+            PgnError::Fen(e) => write!(f, "invalid FEN tag: {}", e),
+            PgnError::IllegalMove { ply, san } => write!(f, "illegal move at ply {}: {}", ply, san),
+        }
+    }
+}
+
+impl std::error::Error for PgnError {}
+
+//A move with the annotations that follow it. Every variation is an alternative to the move,
+//played from the position before it
+#[derive(Debug, PartialEq, Clone)]
+pub struct PgnMove {
+    pub m: Move,
+    pub nags: Vec<u8>,
+    pub comment: Option<String>,
+    pub variations: Vec<Vec<PgnMove>>,
+}
+
+//comment is the one in front of the first move. A comment in front of the first move of a
+//variation has no move to go with and is dropped when reading
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Game {
+    pub tags: Vec<(String, String)>,
+    pub comment: Option<String>,
+    pub moves: Vec<PgnMove>,
+    pub result: String,
+}
+
+impl Game {
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn start_fen(&self) -> &str {
+        self.tag("FEN").unwrap_or(START_FEN)
+    }
+
+    //Marked by the Variant tag, or by the rook files of a Shredder-FEN castling field
+    pub fn is_chess960(&self) -> bool {
+        let shredder = self
+            .start_fen()
+            .split_whitespace()
+            .nth(2)
+            .is_some_and(|castling| {
+                castling
+                    .chars()
+                    .any(|c| c.is_ascii_alphabetic() && !"KQkq".contains(c))
+            });
+        self.tag("Variant")
+            .is_some_and(|v| v.eq_ignore_ascii_case("chess960"))
+            || shredder
+    }
+
+    //The position after all moves of the game
+    pub fn position(&self) -> Result<Position, PgnError> {
+        let mut pos = start_position(self)?;
+        for (ply, pm) in self.moves.iter().enumerate() {
+            legal(&pos, pm.m, ply)?;
+            play(&mut pos, pm.m);
+        }
+        Ok(pos)
+    }
+
+    //The game in export format, tag pairs followed by the movetext wrapped below 80 columns
+    pub fn to_pgn(&self) -> Result<String, PgnError> {
+        let mut out = String::new();
+        for (name, value) in &self.tags {
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+            out.push_str(&format!("[{} \"{}\"]\n", name, value));
+        }
+        if !self.tags.is_empty() {
+            out.push('\n');
+        }
+
+        let mut tokens = Vec::new();
+        if let Some(comment) = &self.comment {
+            push_comment(&mut tokens, comment);
+        }
+        push_moves(&mut tokens, &mut start_position(self)?, &self.moves, 0)?;
+        tokens.push(if self.result.is_empty() {
+            "*".to_string()
+        } else {
+            self.result.clone()
+        });
+
+        let mut line = String::new();
+        for token in tokens {
+            if !line.is_empty() && line.len() + 1 + token.len() > LINE_WIDTH {
+                out.push_str(&line);
+                out.push('\n');
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&token);
+        }
+        out.push_str(&line);
+        out.push('\n');
+        Ok(out)
+    }
+}
+
+//Reads all games of a PGN database. Moves are replayed and checked for legality
+pub fn parse(text: &str) -> Result<Vec<Game>, PgnError> {
+    let mut parser = Parser {
+        chars: text.chars().collect(),
+        idx: 0,
+    };
+    let mut games = Vec::new();
+    while let Some(game) = parser.game()? {
+        games.push(game);
+    }
+    Ok(games)
+}
+
+pub fn read_file<P: AsRef<Path>>(path: P) -> Result<Vec<Game>, PgnError> {
+    let text = std::fs::read_to_string(path).map_err(|e| PgnError::Io(e.to_string()))?;
+    parse(&text)
+}
+
+//Writes games in export format separated by empty lines
+pub fn write(games: &[Game]) -> Result<String, PgnError> {
+    let games: Vec<String> = games.iter().map(Game::to_pgn).collect::<Result<_, _>>()?;
+    Ok(games.join("\n"))
+}
+
+fn start_position(game: &Game) -> Result<Position, PgnError> {
+    let mut pos = Position::default();
+    pos.set_chess960(game.is_chess960());
+    pos.set(game.start_fen()).map_err(PgnError::Fen)?;
+    Ok(pos)
+}
+
+//Moves of games built by hand are not checked by the parser
+fn legal(pos: &Position, m: Move, ply: usize) -> Result<(), PgnError> {
+    if generate::<LEGAL>(pos).contains(m) {
+        Ok(())
+    } else {
+        Err(PgnError::IllegalMove {
+            ply,
+            san: m.to_string(),
+        })
+    }
+}
+
+fn play(pos: &mut Position, m: Move) {
+    let mut st = StateInfo::default();
+    let gives_check = pos.gives_check(m);
+    pos.do_move(m, &mut st, gives_check);
+}
+
+//Movetext of a line starting at ply of the game, variations go in parentheses after the move
+//they replace
+fn push_moves(
+    tokens: &mut Vec<String>,
+    pos: &mut Position,
+    moves: &[PgnMove],
+    ply: usize,
+) -> Result<(), PgnError> {
+    //Black moves get their number at the start of a line and after a variation
+    let mut numbered = false;
+    for (i, pm) in moves.iter().enumerate() {
+        let fullmove = pos.game_ply() / 2 + 1;
+        if pos.side_to_move() == Color::White {
+            tokens.push(format!("{}.", fullmove));
+        } else if !numbered {
+            tokens.push(format!("{}...", fullmove));
+        }
+        numbered = pm.variations.is_empty();
+        legal(pos, pm.m, ply + i)?;
+        tokens.push(to_san(pos, pm.m));
+        tokens.extend(pm.nags.iter().map(|nag| format!("${}", nag)));
+        if let Some(comment) = &pm.comment {
+            push_comment(tokens, comment);
+        }
+        for variation in pm.variations.iter().filter(|v| !v.is_empty()) {
+            let mut line = Vec::new();
+            push_moves(&mut line, &mut pos.clone(), variation, ply + i)?;
+            line[0].insert(0, '(');
+            line.last_mut().unwrap().push(')');
+            tokens.extend(line);
+        }
+        play(pos, pm.m);
+    }
+    Ok(())
+}
+
+//Comments are split into words so that they can be wrapped like the moves
+fn push_comment(tokens: &mut Vec<String>, comment: &str) {
+    let words: Vec<&str> = comment.split_whitespace().collect();
+    if words.is_empty() {
+        tokens.push("{}".to_string());
+        return;
+    }
+    let last = words.len() - 1;
+    for (i, word) in words.iter().enumerate() {
+        let open = if i == 0 { "{" } else { "" };
+        let close = if i == last { "}" } else { "" };
+        tokens.push(format!("{}{}{}", open, word, close));
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    idx: usize,
+}
+
+//The main line or a variation being read
+struct Line {
+    moves: Vec<PgnMove>,
+    //The position after the moves read so far
+    pos: Position,
+    //Ply of the first move, counted from the start of the game
+    ply: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.idx).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        self.idx += 1;
+        c
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.idx += 1;
+        }
+    }
+
+    fn at_line_start(&self) -> bool {
+        self.idx == 0 || self.chars[self.idx - 1] == '\n'
+    }
+
+    fn rest_of_line(&mut self) -> String {
+        let mut s = String::new();
+        while let Some(c) = self.next() {
+            if c == '\n' {
+                break;
+            }
+            s.push(c);
+        }
+        s
+    }
+
+    //Reads the next game, None once only whitespace is left
+    fn game(&mut self) -> Result<Option<Game>, PgnError> {
+        let mut game = Game::default();
+        //The main line once its first move is read, followed by the variations that are open
+        let mut lines: Vec<Line> = Vec::new();
+        let mut empty = true;
+
+        loop {
+            self.skip_whitespace();
+            let Some(c) = self.peek() else {
+                break;
+            };
+            //A tag pair after the movetext starts the next game when the result is missing
+            if c == '[' && !lines.is_empty() {
+                break;
+            }
+            empty = false;
+
+            match c {
+                '%' if self.at_line_start() => {
+                    self.rest_of_line();
+                }
+                '[' => {
+                    self.idx += 1;
+                    let tag = self.tag()?;
+                    game.tags.push(tag);
+                }
+                '{' | ';' => {
+                    self.idx += 1;
+                    let comment = if c == '{' {
+                        self.braced_comment()?
+                    } else {
+                        self.rest_of_line()
+                    };
+                    //Line breaks inside are only wrapping, the writer wraps comments again
+                    let comment = comment.split_whitespace().collect::<Vec<_>>().join(" ");
+                    let in_variation = lines.len() > 1;
+                    match lines.last_mut().and_then(|l| l.moves.last_mut()) {
+                        Some(pm) => pm.comment = Some(comment),
+                        None if !in_variation => game.comment = Some(comment),
+                        None => (),
+                    }
+                }
+                //A variation replaces the last move read
+                '(' => {
+                    self.idx += 1;
+                    let line = lines
+                        .last()
+                        .filter(|l| !l.moves.is_empty())
+                        .ok_or_else(|| PgnError::UnexpectedToken(c.to_string()))?;
+                    let mut pos = line.pos.clone();
+                    pos.undo_move(line.moves.last().unwrap().m);
+                    let ply = line.ply + line.moves.len() - 1;
+                    lines.push(Line {
+                        moves: Vec::new(),
+                        pos,
+                        ply,
+                    });
+                }
+                ')' if lines.len() > 1 => {
+                    self.idx += 1;
+                    let variation = lines.pop().unwrap().moves;
+                    let pm = lines.last_mut().unwrap().moves.last_mut().unwrap();
+                    if !variation.is_empty() {
+                        pm.variations.push(variation);
+                    }
+                }
+                '$' => {
+                    self.idx += 1;
+                    let token = self.token();
+                    let nag = token
+                        .parse()
+                        .map_err(|_| PgnError::UnexpectedToken(format!("${}", token)))?;
+                    if let Some(pm) = lines.last_mut().and_then(|l| l.moves.last_mut()) {
+                        pm.nags.push(nag);
+                    }
+                }
+                ')' | ']' | '}' => return Err(PgnError::UnexpectedToken(c.to_string())),
+                _ => {
+                    let token = self.token();
+                    if RESULTS.contains(&token.as_str()) {
+                        if lines.len() > 1 {
+                            return Err(PgnError::UnterminatedVariation);
+                        }
+                        game.result = token;
+                        break;
+                    }
+                    //Move numbers may be glued to the move, like 12.e4 or 12...e5
+                    let digits = token.trim_start_matches(|c: char| c.is_ascii_digit());
+                    let san = if digits.len() < token.len() && digits.starts_with('.') {
+                        digits.trim_start_matches('.')
+                    } else {
+                        token.as_str()
+                    };
+                    if san.is_empty() {
+                        continue;
+                    }
+
+                    if lines.is_empty() {
+                        lines.push(Line {
+                            moves: Vec::new(),
+                            pos: start_position(&game)?,
+                            ply: 0,
+                        });
+                    }
+                    let line = lines.last_mut().unwrap();
+                    let ply = line.ply + line.moves.len();
+                    let m = parse_san(&line.pos, san).ok_or_else(|| PgnError::IllegalMove {
+                        ply,
+                        san: san.to_string(),
+                    })?;
+                    play(&mut line.pos, m);
+                    line.moves.push(PgnMove {
+                        m,
+                        nags: Vec::new(),
+                        comment: None,
+                        variations: Vec::new(),
+                    });
+                }
+            }
+        }
+
+        if empty {
+            return Ok(None);
+        }
+        if lines.len() > 1 {
+            return Err(PgnError::UnterminatedVariation);
+        }
+        //Validates the FEN tag of games without moves
+        start_position(&game)?;
+        if let Some(main) = lines.pop() {
+            game.moves = main.moves;
+        }
+        if game.result.is_empty() {
+            game.result = game.tag("Result").unwrap_or("*").to_string();
+        }
+        Ok(Some(game))
+    }
+
+    //A symbol up to the next whitespace or delimiter
+    fn token(&mut self) -> String {
+        let mut s = String::new();
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() || "[]{}();$".contains(c) {
+                break;
+            }
+            s.push(c);
+            self.idx += 1;
+        }
+        s
+    }
+
+    //[Name "Value"] with the opening bracket already consumed
+    fn tag(&mut self) -> Result<(String, String), PgnError> {
+        self.skip_whitespace();
+        let mut name = String::new();
+        while let Some(c) = self.peek().filter(|c| c.is_alphanumeric() || *c == '_') {
+            name.push(c);
+            self.idx += 1;
+        }
+        self.skip_whitespace();
+        if name.is_empty() || self.next() != Some('"') {
+            return Err(PgnError::InvalidTag(name));
+        }
+
+        let mut value = String::new();
+        loop {
+            match self.next() {
+                Some('"') => break,
+                Some('\\') => value.push(self.next().ok_or(PgnError::UnterminatedTag)?),
+                Some(c) => value.push(c),
+                None => return Err(PgnError::UnterminatedTag),
+            }
+        }
+        self.skip_whitespace();
+        match self.next() {
+            Some(']') => Ok((name, value)),
+            Some(_) => Err(PgnError::InvalidTag(name)),
+            None => Err(PgnError::UnterminatedTag),
+        }
+    }
+
+    fn braced_comment(&mut self) -> Result<String, PgnError> {
+        let mut s = String::new();
+        loop {
+            match self.next() {
+                Some('}') => return Ok(s),
+                Some(c) => s.push(c),
+                None => return Err(PgnError::UnterminatedComment),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::board::bitboard as bb;
+    use crate::board::position::Outcome;
+
+    const GAMES: &str = r#"[Event "Casual \"blitz\""]
+[Site "?"]
+[White "Morphy, Paul"]
+[Black "Duke Karl / Count Isouard"]
+[Result "1-0"]
+
+{Opera game} 1. e4 e5 2. Nf3 d6 3. d4 Bg4 $6 4. dxe5 Bxf3 5. Qxf3 dxe5 6. Bc4 Nf6
+7. Qb3 Qe7 8. Nc3 c6 9. Bg5 b5 $2 (9... Qb4 10. Qxb4 Bxb4 {is (much) better}) 10. Nxb5
+cxb5 11. Bxb5+ Nbd7 12. O-O-O Rd8 13. Rxd7 Rxd7 14. Rd1 Qe6 15. Bxd7+ Nxd7
+16. Qb8+ $1 Nxb8 17.Rd8# ; mate
+1-0
+
+[Event "From a position"]
+[SetUp "1"]
+[FEN "4k3/8/8/8/8/8/4P3/4K3 b - - 0 40"]
+
+40... Kd7 (40... Ke7 (40... Kf7)) 41. e4 Kc6
+%this line is escaped 42. e5
+42. e5 *
+[Event "No result"]
+
+1. d4 d5
+"#;
+
+    fn init() {
+        bb::init();
+        Position::init();
+    }
+
+    #[test]
+    fn test_parse() {
+        init();
+        let games = parse(GAMES).unwrap();
+        assert_eq!(games.len(), 3);
+
+        let opera = &games[0];
+        assert_eq!(opera.tag("Event"), Some("Casual \"blitz\""));
+        assert_eq!(opera.tag("White"), Some("Morphy, Paul"));
+        assert_eq!(opera.tag("Round"), None);
+        assert_eq!(opera.result, "1-0");
+        assert_eq!(opera.comment.as_deref(), Some("Opera game"));
+        assert_eq!(opera.moves.len(), 33);
+        assert_eq!(opera.moves[5].nags, vec![6]);
+        assert_eq!(opera.moves[17].nags, vec![2]);
+        let variation = &opera.moves[17].variations[0];
+        assert_eq!(variation.len(), 3);
+        assert_eq!(variation[0].m.to_string(), "e7b4");
+        assert_eq!(variation[2].comment.as_deref(), Some("is (much) better"));
+        assert_eq!(opera.moves[32].comment.as_deref(), Some("mate"));
+        assert_eq!(opera.moves[32].m.to_string(), "d1d8");
+        assert_eq!(
+            opera.position().unwrap().outcome(),
+            Outcome::Checkmate(Color::White)
+        );
+
+        let endgame = &games[1];
+        assert_eq!(endgame.result, "*");
+        let moves: Vec<String> = endgame.moves.iter().map(|pm| pm.m.to_string()).collect();
+        assert_eq!(moves, ["e8d7", "e2e4", "d7c6", "e4e5"]);
+        //Variations nest, each one replaces the move it follows
+        let variation = &endgame.moves[0].variations[0];
+        assert_eq!(variation[0].m.to_string(), "e8e7");
+        assert_eq!(variation[0].variations[0][0].m.to_string(), "e8f7");
+        assert_eq!(
+            endgame.position().unwrap().fen(),
+            "8/8/2k5/4P3/8/8/8/4K3 b - - 0 42"
+        );
+
+        let unfinished = &games[2];
+        assert_eq!(unfinished.moves.len(), 2);
+        assert_eq!(unfinished.result, "*");
+
+        assert_eq!(parse("").unwrap(), Vec::new());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        init();
+        for (text, error) in [
+            ("[Event \"x\"", PgnError::UnterminatedTag),
+            ("[Event x]", PgnError::InvalidTag("Event".to_string())),
+            ("1. e4 {", PgnError::UnterminatedComment),
+            ("1. e4 (1. d4", PgnError::UnterminatedVariation),
+            ("1. e4 )", PgnError::UnexpectedToken(")".to_string())),
+            ("(1. e4) 1. d4", PgnError::UnexpectedToken("(".to_string())),
+            ("1. e4 (1. d4 *)", PgnError::UnterminatedVariation),
+            (
+                "1. e4 e5 (1... c5 2. Ke3) 2. Nf3",
+                PgnError::IllegalMove {
+                    ply: 2,
+                    san: "Ke3".to_string(),
+                },
+            ),
+            (
+                "1. e4 e5 2. Ke3",
+                PgnError::IllegalMove {
+                    ply: 2,
+                    san: "Ke3".to_string(),
+                },
+            ),
+            (
+                "[FEN \"8/8 w\"]",
+                PgnError::Fen(FenError::InvalidPiecePlacement("8/8".to_string())),
+            ),
+        ] {
+            assert_eq!(parse(text), Err(error), "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_write() {
+        init();
+        let games = parse(GAMES).unwrap();
+        let text = write(&games).unwrap();
+        for line in text.lines() {
+            assert!(line.len() < 80, "{}", line);
+        }
+        assert!(text.starts_with("[Event \"Casual \\\"blitz\\\"\"]\n[Site \"?\"]\n"));
+        assert!(text.contains("\n\n{Opera game} 1. e4 e5 2. Nf3 d6 3. d4 Bg4 $6 4. dxe5"));
+        assert!(text.contains("17. Rd8# {mate} 1-0\n"));
+        assert!(text.contains("b5 $2 (9... Qb4 10. Qxb4 Bxb4 {is (much)\nbetter}) 10. Nxb5"));
+        assert!(text.contains("\n\n40... Kd7 (40... Ke7 (40... Kf7)) 41. e4 Kc6 42. e5 *\n"));
+
+        //Writing and reading again gives back the same games
+        assert_eq!(parse(&text).unwrap(), games);
+
+        let game = Game {
+            moves: vec![PgnMove {
+                m: Move::new_from_to_sq(Square::SqE2, Square::SqE5),
+                nags: Vec::new(),
+                comment: None,
+                variations: Vec::new(),
+            }],
+            ..Game::default()
+        };
+        assert!(matches!(
+            game.to_pgn(),
+            Err(PgnError::IllegalMove { ply: 0, .. })
+        ));
+
+        //Chess960 games are replayed as such, castling rights are written as rook files
+        let text = "[Variant \"Chess960\"]\n[FEN \"rk5r/pppppppp/8/8/8/8/PPPPPPPP/RK5R w KQkq - 0 1\"]\n\n1. O-O-O *\n";
+        let games = parse(text).unwrap();
+        assert_eq!(write(&games).unwrap(), text);
+        let pos = games[0].position().unwrap();
+        assert!(pos.is_chess960());
+        assert_eq!(pos.fen(), "rk5r/pppppppp/8/8/8/8/PPPPPPPP/2KR3R b ha - 1 1");
+        //With a Shredder-FEN the rook shielding the king from a1 makes O-O-O illegal
+        assert!(parse("[FEN \"4k3/8/8/8/8/8/8/rR1K4 w B - 0 1\"]\n\n1. O-O-O *").is_err());
+    }
+}
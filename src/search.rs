@@ -0,0 +1,1133 @@
+use crate::board::movegen::generate;
+use crate::board::position::{Position, StateInfo};
+use crate::eval;
+use crate::history::{
+    captured_type, correction_bonus, stat_bonus, stat_malus, Histories, CONTINUATION_PLIES,
+};
+use crate::movepick::MovePicker;
+use crate::nnue::{self, Accumulator, Network};
+use crate::timeman::{TimeManager, DEFAULT_MOVE_OVERHEAD};
+use crate::tt::TranspositionTable;
+use crate::types::*;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+//Continuation history key for the plies before the root
+const NO_MOVED_PIECE: (Piece, Square) = (Piece::NoPiece, Square::SqA1);
+//How often (in nodes) the clock is polled
+const TIME_CHECK_INTERVAL: u64 = 1024;
+//Helper threads skip iterations in a pattern of their own, so that they spread over
+//neighbouring depths instead of all searching the same one
+const SKIP_SIZE: [i32; 20] = [1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 3, 3, 4, 4, 4, 4, 4, 4, 4, 4];
+const SKIP_PHASE: [i32; 20] = [0, 1, 0, 1, 2, 3, 0, 1, 2, 3, 4, 5, 0, 1, 2, 3, 4, 5, 6, 7];
+//Iterations from this depth on search every line with a window around its previous score
+const ASPIRATION_DEPTH: Depth = 4;
+const ASPIRATION_DELTA: Value = 16;
+
+//What ends a search besides the stop signal: go depth, nodes, mate, movetime, the clock,
+//or nothing at all for go infinite
+#[derive(Debug, Clone)]
+pub struct SearchLimits {
+    pub depth: Depth,
+    //0 means no node limit
+    pub nodes: u64,
+    //Stop once a mate in this many moves is found, 0 means none
+    pub mate: i32,
+    //Search until stopped, even after a mate is found or the maximum depth is reached
+    pub infinite: bool,
+    //Only these root moves are searched, all legal moves when empty
+    pub searchmoves: Vec<Move>,
+    //Think on the opponent's time until the ponderhit signal, the clock only starts then
+    pub ponder: bool,
+    //Number of best root moves that get an exact score and a pv of their own
+    pub multipv: usize,
+    //Clocks and increments of both sides in milliseconds, a clock of 0 means untimed
+    pub time: [u64; COLORNB],
+    pub inc: [u64; COLORNB],
+    //Moves until the next time control, 0 for sudden death
+    pub movestogo: i32,
+    //Fixed time for this move in milliseconds, 0 means none
+    pub movetime: u64,
+    //Time lost per move to communication with the gui
+    pub move_overhead: u64,
+    //When the go command arrived, the clock runs from here
+    pub start: Instant,
+}
+
+impl Default for SearchLimits {
+    fn default() -> Self {
+        Self {
+            depth: MAX_PLY - 1,
+            nodes: 0,
+            mate: 0,
+            infinite: false,
+            searchmoves: Vec::new(),
+            ponder: false,
+            multipv: 1,
+            time: [0; COLORNB],
+            inc: [0; COLORNB],
+            movestogo: 0,
+            movetime: 0,
+            move_overhead: DEFAULT_MOVE_OVERHEAD,
+            start: Instant::now(),
+        }
+    }
+}
+
+//A legal move at the root, pv[0], with the score and pv it got in the last iteration. Moves
+//that were not the best of their line only have an upper bound of -VALUE_INFINITE
+#[derive(Debug, Clone)]
+pub struct RootMove {
+    pub score: Value,
+    pub previous_score: Value,
+    pub pv: Vec<Move>,
+}
+
+impl RootMove {
+    fn new(m: Move) -> Self {
+        Self {
+            score: -VALUE_INFINITE,
+            previous_score: -VALUE_INFINITE,
+            pv: vec![m],
+        }
+    }
+}
+
+//Result of the last completed iteration, also reported after every iteration
+#[derive(Debug, Clone)]
+pub struct SearchResult {
+    pub best_move: Move,
+    pub score: Value,
+    pub depth: Depth,
+    pub nodes: u64,
+    pub time: Duration,
+    pub hashfull: usize,
+    pub pv: Vec<Move>,
+    //The multipv best root moves, best first. The first line is the one above
+    pub lines: Vec<RootMove>,
+}
+
+pub struct Search<'a> {
+    pos: &'a mut Position,
+    tt: &'a TranspositionTable,
+    history: &'a mut Histories,
+    //The classical evaluation is used without a network
+    network: Option<&'a Network>,
+    limits: SearchLimits,
+    time: TimeManager,
+    //Set from outside, for example by the uci stop command
+    stop: Arc<AtomicBool>,
+    //Raised once the opponent played the move that is pondered on
+    ponderhit: Arc<AtomicBool>,
+    pondering: bool,
+    //0 for the main thread, helpers of a lazy smp search count up from 1
+    thread_id: usize,
+    nodes: u64,
+    //The nodes of all threads searching together, which is what the node limit applies to. Only
+    //counted under a node limit, the threads would contend for it otherwise
+    total_nodes: Arc<AtomicU64>,
+    stopped: bool,
+    //Sorted by score after every iteration, the lines before pv_idx are already searched
+    root_moves: Vec<RootMove>,
+    pv_idx: usize,
+    //Quiet moves that caused a cutoff at the same ply in a sibling node
+    killers: Vec<[Move; 2]>,
+    //(piece, to) of the move made at every ply, for the continuation history
+    moved: Vec<(Piece, Square)>,
+    //Nnue accumulators of the positions along the current line, by ply
+    accumulators: Vec<Accumulator>,
+}
+
+impl<'a> Search<'a> {
+    pub fn new(
+        pos: &'a mut Position,
+        tt: &'a TranspositionTable,
+        history: &'a mut Histories,
+        network: Option<&'a Network>,
+        limits: SearchLimits,
+    ) -> Self {
+        let time = TimeManager::new(&limits, pos.side_to_move(), pos.game_ply());
+        Self {
+            pos,
+            tt,
+            history,
+            network,
+            limits,
+            time,
+            stop: Arc::new(AtomicBool::new(false)),
+            ponderhit: Arc::new(AtomicBool::new(false)),
+            pondering: false,
+            thread_id: 0,
+            nodes: 0,
+            total_nodes: Arc::new(AtomicU64::new(0)),
+            stopped: false,
+            root_moves: Vec::new(),
+            pv_idx: 0,
+            killers: Vec::new(),
+            moved: Vec::new(),
+            accumulators: Vec::new(),
+        }
+    }
+
+    //Shares the flag that stops the search with another thread, the owner clears it before
+    //the next search
+    pub fn set_stop_signal(&mut self, stop: Arc<AtomicBool>) {
+        self.stop = stop;
+    }
+
+    //Like the stop signal, cleared by the owner before the next search
+    pub fn set_ponderhit_signal(&mut self, ponderhit: Arc<AtomicBool>) {
+        self.ponderhit = ponderhit;
+    }
+
+    //Shares the node count that the node limit applies to with the other threads of a search
+    pub fn set_node_counter(&mut self, total_nodes: Arc<AtomicU64>) {
+        self.total_nodes = total_nodes;
+    }
+
+    pub fn set_thread_id(&mut self, thread_id: usize) {
+        self.thread_id = thread_id;
+    }
+
+    //Iterative deepening loop. on_iteration is called with the result of every completed depth
+    pub fn think(&mut self, mut on_iteration: impl FnMut(&SearchResult)) -> SearchResult {
+        self.nodes = 0;
+        self.stopped = false;
+        self.pondering = self.limits.ponder;
+        self.check_ponderhit();
+        let mut root_moves: Vec<Move> = generate::<LEGAL>(self.pos).iter().collect();
+        //Moves that are not legal here are ignored, and so is a list without any legal one
+        let searchmoves = &self.limits.searchmoves;
+        if root_moves.iter().any(|m| searchmoves.contains(m)) {
+            root_moves.retain(|m| searchmoves.contains(m));
+        }
+        self.root_moves = root_moves.into_iter().map(RootMove::new).collect();
+        let multipv = self.limits.multipv.clamp(1, self.root_moves.len().max(1));
+        self.killers = vec![[Move::none(); 2]; MAX_PLY as usize + 1];
+        self.moved = vec![NO_MOVED_PIECE; MAX_PLY as usize + 1];
+        self.accumulators = vec![Accumulator::default(); MAX_PLY as usize + 1];
+
+        let mut result = SearchResult {
+            best_move: self.root_moves.first().map_or(Move::none(), |rm| rm.pv[0]),
+            score: VALUE_ZERO,
+            depth: 0,
+            nodes: 0,
+            time: Duration::ZERO,
+            hashfull: 0,
+            pv: Vec::new(),
+            lines: Vec::new(),
+        };
+
+        if self.root_moves.is_empty() {
+            result.score = if self.pos.checkers() != 0 {
+                mated_in(0)
+            } else {
+                VALUE_DRAW
+            };
+            self.wait_for_stop();
+            return result;
+        }
+
+        for depth in 1..=self.limits.depth.min(MAX_PLY - 1) {
+            if self.skip_depth(depth) {
+                continue;
+            }
+            for rm in &mut self.root_moves {
+                rm.previous_score = rm.score;
+            }
+            for pv_idx in 0..multipv {
+                self.pv_idx = pv_idx;
+                self.search_line(depth);
+                if self.stopped {
+                    break;
+                }
+                //The lines found so far stay in order of their exact scores
+                self.root_moves[..=pv_idx].sort_by_key(|rm| -rm.score);
+            }
+
+            //An interrupted iteration can not be trusted, keep the previous one
+            if self.stopped {
+                break;
+            }
+
+            let best = self.root_moves[0].pv[0];
+            let score = self.root_moves[0].score;
+            result = SearchResult {
+                best_move: best,
+                score,
+                depth,
+                nodes: self.nodes,
+                time: self.time.elapsed(),
+                hashfull: self.tt.hashfull(),
+                pv: self.root_moves[0].pv.clone(),
+                lines: self.root_moves[..multipv].to_vec(),
+            };
+            on_iteration(&result);
+
+            //go mate n is done once a mate in at most n moves is found
+            let mate = self.limits.mate;
+            if mate > 0 && score >= VALUE_MATE_IN_MAX_PLY && VALUE_MATE - score < 2 * mate {
+                break;
+            }
+            if self.limits.infinite {
+                continue;
+            }
+
+            //No point in searching deeper once a forced mate has been found
+            let mate_found =
+                score.abs() >= VALUE_MATE_IN_MAX_PLY && VALUE_MATE - score.abs() <= depth;
+            let single_move = self.root_moves.len() == 1;
+            let out_of_time = self
+                .time
+                .stop_after_iteration(depth, best, score, single_move);
+            //A pondering search goes on, it may only stop on the clock after ponderhit
+            self.check_ponderhit();
+            if !self.pondering && (mate_found || out_of_time) {
+                break;
+            }
+        }
+
+        self.wait_for_stop();
+        result.nodes = self.nodes;
+        result.time = self.time.elapsed();
+        result.hashfull = self.tt.hashfull();
+        result
+    }
+
+    //Searches the root moves from pv_idx on for the best one left. The window around its
+    //previous score, if it had one, is widened until the score falls inside
+    fn search_line(&mut self, depth: Depth) {
+        let previous = self.root_moves[self.pv_idx].previous_score;
+        let mut delta = ASPIRATION_DELTA;
+        let (mut alpha, mut beta) = if depth >= ASPIRATION_DEPTH && previous > -VALUE_INFINITE {
+            (
+                (previous - delta).max(-VALUE_INFINITE),
+                (previous + delta).min(VALUE_INFINITE),
+            )
+        } else {
+            (-VALUE_INFINITE, VALUE_INFINITE)
+        };
+
+        loop {
+            let mut pv = Vec::new();
+            let value = self.search(depth, 0, alpha, beta, &mut pv);
+            //Stable, so that moves without an exact score keep their order
+            self.root_moves[self.pv_idx..].sort_by_key(|rm| -rm.score);
+            if self.stopped {
+                return;
+            }
+
+            if value <= alpha {
+                beta = (alpha + beta) / 2;
+                alpha = (value - delta).max(-VALUE_INFINITE);
+            } else if value >= beta {
+                beta = (value + delta).min(VALUE_INFINITE);
+            } else {
+                return;
+            }
+            delta += delta / 3;
+        }
+    }
+
+    fn skip_depth(&self, depth: Depth) -> bool {
+        if self.thread_id == 0 {
+            return false;
+        }
+        let i = (self.thread_id - 1) % SKIP_SIZE.len();
+        (depth + self.pos.game_ply() + SKIP_PHASE[i]) / SKIP_SIZE[i] % 2 != 0
+    }
+
+    //An infinite search only ends on the stop signal and a pondering one also on ponderhit,
+    //even when they have nothing left to do
+    fn wait_for_stop(&mut self) {
+        loop {
+            self.check_ponderhit();
+            if !(self.limits.infinite || self.pondering) || self.stop.load(Ordering::Relaxed) {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    //On ponderhit the search turns into a normal one, timed from now on
+    fn check_ponderhit(&mut self) {
+        if self.pondering && self.ponderhit.load(Ordering::Relaxed) {
+            self.pondering = false;
+            self.time.ponderhit();
+        }
+    }
+
+    //Negamax alpha-beta search. pv is filled with the principal variation of the node
+    fn search(
+        &mut self,
+        depth: Depth,
+        ply: i32,
+        mut alpha: Value,
+        mut beta: Value,
+        pv: &mut Vec<Move>,
+    ) -> Value {
+        let root_node = ply == 0;
+        let pv_node = beta - alpha > 1;
+        self.count_node();
+
+        if self.should_stop() {
+            return VALUE_ZERO;
+        }
+
+        //The side to move can force a draw by repetition, so it is at least a draw
+        if !root_node && alpha < VALUE_DRAW && self.pos.upcoming_repetition(ply) {
+            alpha = VALUE_DRAW;
+            if alpha >= beta {
+                return alpha;
+            }
+        }
+
+        if depth <= 0 {
+            return self.qsearch(DEPTH_QS_CHECKS, ply, alpha, beta);
+        }
+
+        if !root_node && self.pos.is_draw(ply).is_some() {
+            return VALUE_DRAW;
+        }
+
+        if ply >= MAX_PLY {
+            return self.evaluate(ply);
+        }
+
+        //Mate distance pruning, a shorter mate was already found higher up in the tree
+        if !root_node {
+            alpha = alpha.max(mated_in(ply));
+            beta = beta.min(mate_in(ply + 1));
+            if alpha >= beta {
+                return alpha;
+            }
+        }
+
+        let key = self.pos.key();
+        let tt_data = self.tt.probe(key);
+        let mut tt_move = Move::none();
+        if let Some(tte) = tt_data {
+            let tt_value = value_from_tt(tte.value, ply);
+            if !pv_node
+                && tte.depth >= depth
+                && match tte.bound {
+                    Bound::BoundExact => true,
+                    Bound::BoundLower => tt_value >= beta,
+                    Bound::BoundUpper => tt_value <= alpha,
+                    Bound::BoundNone => false,
+                }
+            {
+                return tt_value;
+            }
+
+            //The move picker checks that the move is pseudo legal, the entry may belong to
+            //another position with the same key fragment
+            tt_move = tte.mv;
+        }
+
+        //The transposition table keeps the raw evaluation, the search works with the one adjusted
+        //by the correction history
+        let in_check = self.pos.checkers() != 0;
+        let mut raw_eval = VALUE_NONE;
+        let mut static_eval = VALUE_NONE;
+        if !in_check {
+            raw_eval = match tt_data {
+                Some(tte) if tte.eval != VALUE_NONE => tte.eval,
+                _ => self.evaluate(ply),
+            };
+            static_eval = self.history.correction.correct(self.pos, raw_eval);
+        }
+
+        //The best move of the previous iteration is searched first at the root
+        if root_node {
+            tt_move = self.root_moves[self.pv_idx].pv[0];
+        }
+
+        let original_alpha = alpha;
+        let mut best_value = -VALUE_INFINITE;
+        let mut best_move = Move::none();
+        let mut child_pv = Vec::new();
+        let mut move_count = 0;
+        let mut quiets_searched = Vec::new();
+        let mut captures_searched = Vec::new();
+        let continuation = self.continuation(ply);
+        let counter_move = self.history.counter_moves.get(continuation[0]);
+        let killers = self.killers[ply as usize];
+        let mut mp = MovePicker::new(
+            self.pos,
+            tt_move,
+            depth,
+            killers,
+            counter_move,
+            continuation,
+        );
+        loop {
+            let m = mp.next_move(self.pos, self.history);
+            if m == Move::none() {
+                break;
+            }
+            if (root_node
+                && !self.root_moves[self.pv_idx..]
+                    .iter()
+                    .any(|rm| rm.pv[0] == m))
+                || !self.pos.legal(m)
+            {
+                continue;
+            }
+            move_count += 1;
+            let capture = self.pos.capture_stage(m);
+
+            let mut st = StateInfo::default();
+            let gives_check = self.pos.gives_check(m);
+            self.moved[ply as usize] = (self.pos.moved_piece(m), m.to_sq());
+            self.pos.do_move(m, &mut st, gives_check);
+            self.accumulators[ply as usize + 1].computed = [false; COLORNB];
+            child_pv.clear();
+
+            //Principal variation search, only the first move gets the full window. The others
+            //are searched with a null window and searched again if they turn out to be better
+            let mut value = -VALUE_INFINITE;
+            if !pv_node || move_count > 1 {
+                value = -self.search(depth - 1, ply + 1, -alpha - 1, -alpha, &mut child_pv);
+            }
+            if pv_node && (move_count == 1 || (value > alpha && value < beta)) {
+                child_pv.clear();
+                value = -self.search(depth - 1, ply + 1, -beta, -alpha, &mut child_pv);
+            }
+            self.pos.undo_move(m);
+
+            if self.stopped {
+                return VALUE_ZERO;
+            }
+
+            if root_node {
+                let rm = self.root_moves.iter_mut().find(|rm| rm.pv[0] == m).unwrap();
+                if move_count == 1 || value > alpha {
+                    rm.score = value;
+                    rm.pv.truncate(1);
+                    rm.pv.extend_from_slice(&child_pv);
+                } else {
+                    rm.score = -VALUE_INFINITE;
+                }
+            }
+
+            if value > best_value {
+                best_value = value;
+                if value > alpha {
+                    alpha = value;
+                    best_move = m;
+                    pv.clear();
+                    pv.push(m);
+                    pv.extend_from_slice(&child_pv);
+                    if alpha >= beta {
+                        break;
+                    }
+                }
+            }
+
+            if m != best_move && move_count <= 32 {
+                if capture {
+                    captures_searched.push(m);
+                } else {
+                    quiets_searched.push(m);
+                }
+            }
+        }
+
+        if move_count == 0 {
+            return if in_check { mated_in(ply) } else { VALUE_DRAW };
+        }
+
+        if best_move != Move::none() {
+            self.update_stats(ply, depth, best_move, &quiets_searched, &captures_searched);
+        }
+
+        //Learn how far off the static evaluation was. Fail highs below it and fail lows above it
+        //only bound the error, and captures say little about the quiet position
+        let quiet_best = best_move == Move::none() || !self.pos.capture(best_move);
+        if !in_check
+            && quiet_best
+            && ((best_value < static_eval && best_value < beta)
+                || (best_value > static_eval && best_move != Move::none()))
+        {
+            let bonus = correction_bonus(best_value, static_eval, depth);
+            self.history.correction.update(self.pos, bonus);
+        }
+
+        let bound = if best_value >= beta {
+            Bound::BoundLower
+        } else if best_value > original_alpha {
+            Bound::BoundExact
+        } else {
+            Bound::BoundUpper
+        };
+        self.tt.store(
+            key,
+            value_to_tt(best_value, ply),
+            pv_node,
+            bound,
+            depth,
+            best_move,
+            raw_eval,
+        );
+
+        best_value
+    }
+
+    //Quiescence search, only captures, queen promotions and (at the first ply) quiet checks are
+    //searched so that the static evaluation is never taken in the middle of an exchange
+    fn qsearch(&mut self, depth: Depth, ply: i32, mut alpha: Value, beta: Value) -> Value {
+        let pv_node = beta - alpha > 1;
+        let in_check = self.pos.checkers() != 0;
+        self.count_node();
+
+        if self.should_stop() {
+            return VALUE_ZERO;
+        }
+
+        if self.pos.is_draw(ply).is_some() {
+            return VALUE_DRAW;
+        }
+
+        if alpha < VALUE_DRAW && self.pos.upcoming_repetition(ply) {
+            alpha = VALUE_DRAW;
+            if alpha >= beta {
+                return alpha;
+            }
+        }
+
+        if ply >= MAX_PLY {
+            return if in_check {
+                VALUE_DRAW
+            } else {
+                self.evaluate(ply)
+            };
+        }
+
+        //Entries stored with quiet checks are good enough for nodes without them, not vice versa
+        let tt_depth = if in_check || depth >= DEPTH_QS_CHECKS {
+            DEPTH_QS_CHECKS
+        } else {
+            DEPTH_QS_NO_CHECKS
+        };
+        let key = self.pos.key();
+        let tt_data = self.tt.probe(key);
+        let mut tt_move = Move::none();
+        if let Some(tte) = tt_data {
+            tt_move = tte.mv;
+            let tt_value = value_from_tt(tte.value, ply);
+            if !pv_node
+                && tte.depth >= tt_depth
+                && match tte.bound {
+                    Bound::BoundExact => true,
+                    Bound::BoundLower => tt_value >= beta,
+                    Bound::BoundUpper => tt_value <= alpha,
+                    Bound::BoundNone => false,
+                }
+            {
+                return tt_value;
+            }
+        }
+
+        //Stand pat, the side to move is assumed to have at least one move that keeps the
+        //evaluation. This does not hold when in check, so all evasions are searched instead
+        let original_alpha = alpha;
+        let mut best_value = -VALUE_INFINITE;
+        let mut raw_eval = VALUE_NONE;
+        if !in_check {
+            raw_eval = match tt_data {
+                Some(tte) if tte.eval != VALUE_NONE => tte.eval,
+                _ => self.evaluate(ply),
+            };
+            best_value = self.history.correction.correct(self.pos, raw_eval);
+            if best_value >= beta {
+                return best_value;
+            }
+            alpha = alpha.max(best_value);
+        }
+
+        let mut best_move = Move::none();
+        let mut mp = MovePicker::new_qsearch(self.pos, tt_move, depth, self.continuation(ply));
+        loop {
+            let m = mp.next_move(self.pos, self.history);
+            if m == Move::none() {
+                break;
+            }
+            if !self.pos.legal(m) {
+                continue;
+            }
+
+            //Captures that lose material can not raise the stand pat score
+            if !in_check && !self.pos.see_ge(m, VALUE_ZERO) {
+                continue;
+            }
+
+            let mut st = StateInfo::default();
+            let gives_check = self.pos.gives_check(m);
+            self.moved[ply as usize] = (self.pos.moved_piece(m), m.to_sq());
+            self.pos.do_move(m, &mut st, gives_check);
+            self.accumulators[ply as usize + 1].computed = [false; COLORNB];
+            let value = -self.qsearch(depth - 1, ply + 1, -beta, -alpha);
+            self.pos.undo_move(m);
+
+            if self.stopped {
+                return VALUE_ZERO;
+            }
+
+            if value > best_value {
+                best_value = value;
+                if value > alpha {
+                    alpha = value;
+                    best_move = m;
+                    if alpha >= beta {
+                        break;
+                    }
+                }
+            }
+        }
+
+        //No evasion, the side to move is checkmated
+        if in_check && best_value == -VALUE_INFINITE {
+            return mated_in(ply);
+        }
+
+        let bound = if best_value >= beta {
+            Bound::BoundLower
+        } else if pv_node && best_value > original_alpha {
+            Bound::BoundExact
+        } else {
+            Bound::BoundUpper
+        };
+        self.tt.store(
+            key,
+            value_to_tt(best_value, ply),
+            pv_node,
+            bound,
+            tt_depth,
+            best_move,
+            raw_eval,
+        );
+
+        best_value
+    }
+
+    fn count_node(&mut self) {
+        self.nodes += 1;
+        if self.limits.nodes != 0 {
+            self.total_nodes.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn should_stop(&mut self) -> bool {
+        if self.stopped {
+            return true;
+        }
+        let node_limit =
+            self.limits.nodes != 0 && self.total_nodes.load(Ordering::Relaxed) >= self.limits.nodes;
+        if node_limit || self.stop.load(Ordering::Relaxed) {
+            self.stopped = true;
+        } else if self.nodes.is_multiple_of(TIME_CHECK_INTERVAL) {
+            self.check_ponderhit();
+            self.stopped = !self.pondering && self.time.out_of_time();
+        }
+        self.stopped
+    }
+
+    fn continuation(&self, ply: i32) -> [(Piece, Square); 3] {
+        CONTINUATION_PLIES.map(|i| {
+            (ply as usize)
+                .checked_sub(i)
+                .map_or(NO_MOVED_PIECE, |p| self.moved[p])
+        })
+    }
+
+    //Rewards the best move and punishes the other moves searched before it
+    fn update_stats(
+        &mut self,
+        ply: i32,
+        depth: Depth,
+        best_move: Move,
+        quiets_searched: &[Move],
+        captures_searched: &[Move],
+    ) {
+        let bonus = stat_bonus(depth);
+        let malus = stat_malus(depth);
+        if self.pos.capture_stage(best_move) {
+            self.update_capture_stats(best_move, bonus);
+        } else {
+            self.update_quiet_stats(ply, best_move, bonus);
+            let killers = &mut self.killers[ply as usize];
+            if killers[0] != best_move {
+                killers[1] = killers[0];
+                killers[0] = best_move;
+            }
+            let prev = self.continuation(ply)[0];
+            if prev != NO_MOVED_PIECE {
+                self.history.counter_moves.set(prev, best_move);
+            }
+            for &m in quiets_searched {
+                self.update_quiet_stats(ply, m, -malus);
+            }
+        }
+        for &m in captures_searched {
+            self.update_capture_stats(m, -malus);
+        }
+    }
+
+    fn update_quiet_stats(&mut self, ply: i32, m: Move, bonus: i32) {
+        let pc = self.pos.moved_piece(m);
+        self.history.main.update(self.pos.side_to_move(), m, bonus);
+        for prev in self.continuation(ply) {
+            if prev != NO_MOVED_PIECE {
+                self.history.continuation.update(prev, pc, m.to_sq(), bonus);
+            }
+        }
+    }
+
+    fn update_capture_stats(&mut self, m: Move, bonus: i32) {
+        let pc = self.pos.moved_piece(m);
+        let captured = captured_type(self.pos, m);
+        self.history.capture.update(pc, m.to_sq(), captured, bonus);
+    }
+
+    fn evaluate(&mut self, ply: i32) -> Value {
+        match self.network {
+            Some(net) => nnue::evaluate(net, self.pos, &mut self.accumulators[..=ply as usize]),
+            None => eval::evaluate(self.pos),
+        }
+    }
+}
+
+//Mate scores are stored relative to the node instead of the root, so that they stay valid
+//when the position is reached again at a different ply
+fn value_to_tt(v: Value, ply: i32) -> Value {
+    if v >= VALUE_MATE_IN_MAX_PLY {
+        v + ply
+    } else if v <= VALUE_MATED_IN_MAX_PLY {
+        v - ply
+    } else {
+        v
+    }
+}
+
+fn value_from_tt(v: Value, ply: i32) -> Value {
+    if v >= VALUE_MATE_IN_MAX_PLY {
+        v - ply
+    } else if v <= VALUE_MATED_IN_MAX_PLY {
+        v + ply
+    } else {
+        v
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::board::position::START_FEN;
+
+    fn search(fen: &str, limits: SearchLimits) -> SearchResult {
+        let mut pos = Position::from_fen(fen).unwrap();
+        let tt = TranspositionTable::new(1);
+        let mut history = Histories::new();
+        let result = Search::new(&mut pos, &tt, &mut history, None, limits).think(|_| ());
+        assert_eq!(pos.fen(), fen);
+        result
+    }
+
+    #[test]
+    fn test_finds_mate_in_one() {
+        let limits = SearchLimits {
+            depth: 3,
+            ..SearchLimits::default()
+        };
+        let result = search("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", limits);
+        assert_eq!(result.best_move.to_string(), "a1a8");
+        assert_eq!(result.score, mate_in(1));
+        assert_eq!(result.pv.len(), 1);
+    }
+
+    #[test]
+    fn test_finds_mate_in_two() {
+        let limits = SearchLimits {
+            depth: 5,
+            ..SearchLimits::default()
+        };
+        //1. Kb6 Kb8 2. Rh8#
+        let result = search("k7/8/2K5/8/8/8/8/7R w - - 0 1", limits);
+        assert_eq!(result.score, mate_in(3));
+        assert_eq!(result.pv.len(), 3);
+    }
+
+    #[test]
+    fn test_mated_and_stalemated() {
+        let limits = SearchLimits {
+            depth: 2,
+            ..SearchLimits::default()
+        };
+        let result = search("R5k1/5ppp/8/8/8/8/8/6K1 b - - 0 1", limits.clone());
+        assert_eq!(result.best_move, Move::none());
+        assert_eq!(result.score, mated_in(0));
+
+        let result = search("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1", limits);
+        assert_eq!(result.best_move, Move::none());
+        assert_eq!(result.score, VALUE_DRAW);
+    }
+
+    #[test]
+    fn test_wins_material() {
+        let limits = SearchLimits {
+            depth: 2,
+            ..SearchLimits::default()
+        };
+        let result = search("4k3/8/8/3q4/8/8/3R4/4K3 w - - 0 1", limits);
+        assert_eq!(result.best_move.to_string(), "d2d5");
+        assert_eq!(result.pv[0], result.best_move);
+    }
+
+    #[test]
+    fn test_quiescence() {
+        let limits = SearchLimits {
+            depth: 1,
+            ..SearchLimits::default()
+        };
+        //Qxd5 wins a pawn for the horizon, but loses the queen to cxd5
+        let result = search("4k3/8/2p5/3p4/8/8/3Q4/4K3 w - - 0 1", limits.clone());
+        assert_ne!(result.best_move.to_string(), "d2d5");
+        assert!(result.score > QueenValue - 3 * PawnValue);
+
+        //Rxd5 is safe, the recapture is not available
+        let result = search("4k3/8/8/3p4/8/8/3R4/4K3 w - - 0 1", limits.clone());
+        assert_eq!(result.best_move.to_string(), "d2d5");
+        assert!(result.score > RookValue);
+
+        //Checkmate right behind the horizon is seen through the evasions
+        let result = search("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", limits);
+        assert_eq!(result.score, mate_in(1));
+    }
+
+    #[test]
+    fn test_node_limit() {
+        let limits = SearchLimits {
+            nodes: 5000,
+            ..SearchLimits::default()
+        };
+        let mut iterations = 0;
+        let mut pos = Position::from_fen(START_FEN).unwrap();
+        let tt = TranspositionTable::new(1);
+        let mut history = Histories::new();
+        let result =
+            Search::new(&mut pos, &tt, &mut history, None, limits).think(|_| iterations += 1);
+        assert!(result.nodes <= 5000);
+        assert!(result.depth >= 1);
+        assert_eq!(result.depth, iterations);
+        assert_ne!(result.best_move, Move::none());
+    }
+
+    #[test]
+    fn test_tt_is_reused() {
+        let limits = SearchLimits {
+            depth: 3,
+            ..SearchLimits::default()
+        };
+        let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        let mut pos = Position::from_fen(fen).unwrap();
+        let tt = TranspositionTable::new(1);
+        let mut history = Histories::new();
+        let first = Search::new(&mut pos, &tt, &mut history, None, limits.clone()).think(|_| ());
+        assert!(first.hashfull > 0);
+        let second = Search::new(&mut pos, &tt, &mut history, None, limits).think(|_| ());
+        assert_eq!(second.score, first.score);
+        assert!(second.nodes < first.nodes);
+        assert_eq!(pos.fen(), fen);
+    }
+
+    #[test]
+    fn test_mate_values_in_tt() {
+        for ply in [0, 1, 10] {
+            for v in [mate_in(5), mated_in(8), 100, -VALUE_MATE_IN_MAX_PLY + 1] {
+                assert_eq!(value_from_tt(value_to_tt(v, ply), ply), v);
+            }
+        }
+        //A mate found 3 plies below the root is a mate in 2 from the node itself
+        assert_eq!(value_to_tt(mate_in(5), 3), mate_in(2));
+    }
+
+    #[test]
+    fn test_time_limits() {
+        let limits = SearchLimits {
+            movetime: 50,
+            ..SearchLimits::default()
+        };
+        let result = search(START_FEN, limits);
+        assert!(result.time < Duration::from_secs(2));
+        assert_ne!(result.best_move, Move::none());
+
+        //A second on the clock is spent on more than one move
+        let limits = SearchLimits {
+            time: [1000, 0],
+            ..SearchLimits::default()
+        };
+        let time = TimeManager::new(&limits, Color::White, 0);
+        assert!(time.maximum().unwrap() < Duration::from_millis(300));
+        let result = search(START_FEN, limits);
+        assert!(result.time < Duration::from_secs(2));
+        assert_ne!(result.best_move, Move::none());
+    }
+
+    #[test]
+    fn test_search_limits() {
+        //Only the given root moves are searched, illegal ones are ignored
+        let pos = Position::from_fen(START_FEN).unwrap();
+        let a3 = pos.parse_uci_move("a2a3").unwrap();
+        let limits = SearchLimits {
+            depth: 3,
+            searchmoves: vec![a3, Move::new_from_to_sq(Square::SqE2, Square::SqE5)],
+            ..SearchLimits::default()
+        };
+        let result = search(START_FEN, limits);
+        assert_eq!(result.best_move, a3);
+        assert_eq!(result.pv[0], a3);
+
+        //A mate in two ends go mate 2 before the depth limit
+        let limits = SearchLimits {
+            mate: 2,
+            ..SearchLimits::default()
+        };
+        let result = search("k7/8/2K5/8/8/8/8/7R w - - 0 1", limits);
+        assert_eq!(result.score, mate_in(3));
+        assert!(result.depth < 10, "{}", result.depth);
+
+        //An infinite search goes on past the mate and only ends on the stop signal
+        let mut pos = Position::from_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1").unwrap();
+        let tt = TranspositionTable::new(1);
+        let mut history = Histories::new();
+        let limits = SearchLimits {
+            depth: 2,
+            infinite: true,
+            ..SearchLimits::default()
+        };
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopper = {
+            let stop = stop.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(50));
+                stop.store(true, Ordering::Relaxed);
+            })
+        };
+        let mut search = Search::new(&mut pos, &tt, &mut history, None, limits);
+        search.set_stop_signal(stop);
+        let result = search.think(|_| ());
+        stopper.join().unwrap();
+        assert!(result.time >= Duration::from_millis(50));
+        assert_eq!(result.best_move.to_string(), "a1a8");
+        assert_eq!(result.depth, 2);
+    }
+
+    #[test]
+    fn test_multipv() {
+        //Rxd5 wins the queen, every other line loses the rook or gets nothing
+        let fen = "4k3/8/8/3q4/8/8/3R4/4K3 w - - 0 1";
+        let limits = SearchLimits {
+            depth: 5,
+            multipv: 3,
+            ..SearchLimits::default()
+        };
+        let result = search(fen, limits);
+        assert_eq!(result.lines.len(), 3);
+        assert_eq!(result.lines[0].pv, result.pv);
+        assert_eq!(result.pv[0].to_string(), "d2d5");
+        assert!(result.lines.windows(2).all(|w| w[0].score >= w[1].score));
+        assert!(result.lines[2].score > -VALUE_INFINITE);
+        let pos = Position::from_fen(fen).unwrap();
+        let legal = generate::<LEGAL>(&pos);
+        assert!(result.lines.iter().all(|rm| legal.contains(rm.pv[0])));
+        assert_ne!(result.lines[1].pv[0], result.lines[2].pv[0]);
+
+        //Never more lines than legal moves, and a single line by default
+        let fen = "k7/8/2K5/8/8/8/8/7R b - - 0 1";
+        let limits = SearchLimits {
+            depth: 3,
+            multipv: 50,
+            ..SearchLimits::default()
+        };
+        let result = search(fen, limits);
+        let legal = generate::<LEGAL>(&Position::from_fen(fen).unwrap());
+        assert_eq!(result.lines.len(), legal.len());
+        let limits = SearchLimits {
+            depth: 3,
+            ..SearchLimits::default()
+        };
+        assert_eq!(search(fen, limits).lines.len(), 1);
+    }
+
+    #[test]
+    fn test_ponder() {
+        let mut pos = Position::from_fen(START_FEN).unwrap();
+        let tt = TranspositionTable::new(1);
+        let mut history = Histories::new();
+        let limits = SearchLimits {
+            movetime: 20,
+            ponder: true,
+            ..SearchLimits::default()
+        };
+        let ponderhit = Arc::new(AtomicBool::new(false));
+        let start = Instant::now();
+        let hitter = {
+            let ponderhit = ponderhit.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(100));
+                ponderhit.store(true, Ordering::Relaxed);
+            })
+        };
+        let mut search = Search::new(&mut pos, &tt, &mut history, None, limits);
+        search.set_ponderhit_signal(ponderhit);
+        let result = search.think(|_| ());
+        hitter.join().unwrap();
+        //The movetime counts from ponderhit, which came at least 100ms after the start
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(120));
+        assert!(result.time <= elapsed - Duration::from_millis(100));
+        assert_ne!(result.best_move, Move::none());
+    }
+
+    #[test]
+    fn test_history_is_learned() {
+        let limits = SearchLimits {
+            depth: 4,
+            ..SearchLimits::default()
+        };
+        let mut pos = Position::from_fen(START_FEN).unwrap();
+        let tt = TranspositionTable::new(1);
+        let mut history = Histories::new();
+        Search::new(&mut pos, &tt, &mut history, None, limits).think(|_| ());
+
+        //Some quiet move of each side was rewarded, and refuted a move of the other side
+        let moves: Vec<Move> = generate::<LEGAL>(&pos).iter().collect();
+        assert!(moves.iter().any(|&m| history.main.get(Color::White, m) > 0));
+        assert!(moves.iter().any(|&m| history.main.get(Color::White, m) < 0));
+        let pawn_moves = [(Piece::WPawn, Square::SqE4), (Piece::WPawn, Square::SqD4)];
+        assert!(pawn_moves
+            .iter()
+            .any(|&prev| history.counter_moves.get(prev) != Move::none()));
+        assert_ne!(history.correction.correct(&pos, VALUE_ZERO), VALUE_ZERO);
+    }
+
+    #[test]
+    fn test_draws() {
+        let limits = SearchLimits {
+            depth: 3,
+            ..SearchLimits::default()
+        };
+        //Every move but a mate completes the fifty moves
+        let result = search("7k/8/8/8/8/8/8/R6K w - - 99 80", limits.clone());
+        assert_eq!(result.score, VALUE_DRAW);
+        let result = search("7k/8/8/8/8/8/8/R6K w - - 96 80", limits.clone());
+        assert!(result.score > VALUE_DRAW);
+
+        //Taking the last pawn saves the game, the knight alone can not mate
+        let result = search("7k/8/8/8/8/8/6p1/2n4K w - - 0 1", limits);
+        assert_eq!(result.score, VALUE_DRAW);
+        assert_eq!(result.best_move.to_string(), "h1g2");
+    }
+}
@@ -0,0 +1,267 @@
+use crate::board::position::Position;
+use crate::history::Histories;
+use crate::nnue::Network;
+use crate::search::{Search, SearchLimits, SearchResult};
+use crate::tt::TranspositionTable;
+use crate::types::*;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+pub const MAX_THREADS: usize = 1024;
+//Helpers recurse as deep as the main thread, so they get the stack size of a typical main thread
+const STACK_SIZE: usize = 8 * 1024 * 1024;
+
+//Lazy smp: every thread searches the same root on its own copy of the position, and they only
+//share what they store in the transposition table. The main thread runs the clock and reports
+//its iterations, once it is done the helpers are stopped and the threads vote on the best move
+pub struct ThreadPool {
+    //One per thread, the first belongs to the main thread
+    histories: Vec<Histories>,
+    //Raised from outside to end the search, and to end pondering. Both are cleared by the
+    //owner before the next search
+    stop: Arc<AtomicBool>,
+    ponderhit: Arc<AtomicBool>,
+}
+
+impl Default for ThreadPool {
+    fn default() -> Self {
+        Self::new(1)
+    }
+}
+
+impl ThreadPool {
+    pub fn new(threads: usize) -> Self {
+        let mut pool = Self {
+            histories: Vec::new(),
+            stop: Arc::new(AtomicBool::new(false)),
+            ponderhit: Arc::new(AtomicBool::new(false)),
+        };
+        pool.set_size(threads);
+        pool
+    }
+
+    //Number of search threads, the main thread included. Clears the histories
+    pub fn set_size(&mut self, threads: usize) {
+        let threads = threads.clamp(1, MAX_THREADS);
+        self.histories = (0..threads).map(|_| Histories::new()).collect();
+    }
+
+    pub fn size(&self) -> usize {
+        self.histories.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.histories.iter_mut().for_each(Histories::clear);
+    }
+
+    pub fn stop_signal(&self) -> Arc<AtomicBool> {
+        self.stop.clone()
+    }
+
+    pub fn ponderhit_signal(&self) -> Arc<AtomicBool> {
+        self.ponderhit.clone()
+    }
+
+    //Searches pos with all threads until the limits of the main thread or the stop signal end
+    //it. A node limit applies to the nodes of all threads together, which are the nodes
+    //reported. on_iteration gets the iterations of the main thread, and the voted result as
+    //well when it comes from a helper
+    pub fn think(
+        &mut self,
+        pos: &mut Position,
+        tt: &mut TranspositionTable,
+        network: Option<&Network>,
+        limits: SearchLimits,
+        mut on_iteration: impl FnMut(&SearchResult),
+    ) -> SearchResult {
+        tt.new_search();
+        let tt = &*tt;
+        let (main_history, helper_histories) = self.histories.split_first_mut().unwrap();
+        //Helpers search until the main thread is done, whatever their own limits say. They still
+        //stop early at the shared node limit, the main thread is about to stop as well then
+        let helpers_stop = Arc::new(AtomicBool::new(false));
+        let total_nodes = Arc::new(AtomicU64::new(0));
+        let helper_limits = SearchLimits {
+            infinite: true,
+            ..limits.clone()
+        };
+
+        let results = thread::scope(|s| {
+            let helpers: Vec<_> = helper_histories
+                .iter_mut()
+                .enumerate()
+                .map(|(i, history)| {
+                    let mut pos = pos.clone();
+                    let limits = helper_limits.clone();
+                    let stop = helpers_stop.clone();
+                    let total_nodes = total_nodes.clone();
+                    thread::Builder::new()
+                        .stack_size(STACK_SIZE)
+                        .spawn_scoped(s, move || {
+                            let mut search = Search::new(&mut pos, tt, history, network, limits);
+                            search.set_stop_signal(stop);
+                            search.set_node_counter(total_nodes);
+                            search.set_thread_id(i + 1);
+                            search.think(|_| ())
+                        })
+                        .unwrap()
+                })
+                .collect();
+
+            let mut search = Search::new(pos, tt, main_history, network, limits);
+            search.set_stop_signal(self.stop.clone());
+            search.set_ponderhit_signal(self.ponderhit.clone());
+            search.set_node_counter(total_nodes.clone());
+            let main = search.think(&mut on_iteration);
+            helpers_stop.store(true, Ordering::Relaxed);
+
+            let mut results = vec![main];
+            results.extend(helpers.into_iter().map(|h| h.join().unwrap()));
+            results
+        });
+
+        let nodes = results.iter().map(|r| r.nodes).sum();
+        let best = best_thread(&results);
+        let mut result = results[best].clone();
+        result.nodes = nodes;
+        result.time = results[0].time;
+        result.hashfull = results[0].hashfull;
+        if best != 0 {
+            on_iteration(&result);
+        }
+        result
+    }
+}
+
+//Index of the result whose best move gets the most votes. Every thread votes for its own best
+//move, with more weight for a deeper search and a higher score. A proven mate beats the vote
+fn best_thread(results: &[SearchResult]) -> usize {
+    let min_score = results.iter().map(|r| r.score).min().unwrap();
+    let votes = |m: Move| -> i64 {
+        results
+            .iter()
+            .filter(|r| r.best_move == m && r.depth > 0)
+            .map(|r| (r.score - min_score + 14) as i64 * r.depth as i64)
+            .sum()
+    };
+
+    let mut best = 0;
+    for (i, r) in results.iter().enumerate().skip(1) {
+        let b = &results[best];
+        //A helper that has not completed an iteration has no opinion
+        if r.depth == 0 {
+            continue;
+        }
+        if b.score.abs() >= VALUE_MATE_IN_MAX_PLY {
+            if r.score > b.score {
+                best = i;
+            }
+        } else if r.score >= VALUE_MATE_IN_MAX_PLY
+            || (r.score > VALUE_MATED_IN_MAX_PLY && votes(r.best_move) > votes(b.best_move))
+        {
+            best = i;
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    fn result(from: Square, to: Square, score: Value, depth: Depth) -> SearchResult {
+        SearchResult {
+            best_move: Move::new_from_to_sq(from, to),
+            score,
+            depth,
+            nodes: 0,
+            time: Duration::ZERO,
+            hashfull: 0,
+            pv: Vec::new(),
+            lines: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_best_thread() {
+        //Two threads agreeing outvote a single one, even the main thread
+        let results = [
+            result(Square::SqA1, Square::SqA2, 30, 10),
+            result(Square::SqH1, Square::SqH2, 20, 10),
+            result(Square::SqH1, Square::SqH2, 20, 9),
+        ];
+        assert_eq!(best_thread(&results), 1);
+
+        //A deep enough search outweighs the majority, threads without an iteration do not count
+        let results = [
+            result(Square::SqA1, Square::SqA2, 30, 25),
+            result(Square::SqH1, Square::SqH2, 20, 10),
+            result(Square::SqH1, Square::SqH2, 20, 0),
+        ];
+        assert_eq!(best_thread(&results), 0);
+
+        //The shortest mate wins regardless of votes
+        let results = [
+            result(Square::SqA1, Square::SqA2, 30, 10),
+            result(Square::SqH1, Square::SqH2, 30, 10),
+            result(Square::SqH1, Square::SqH2, 30, 10),
+            result(Square::SqE1, Square::SqG1, mate_in(5), 6),
+            result(Square::SqA1, Square::SqA8, mate_in(3), 4),
+        ];
+        assert_eq!(best_thread(&results), 4);
+    }
+
+    #[test]
+    fn test_smp_search() {
+        let mut pos =
+            Position::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq -")
+                .unwrap();
+        let fen = pos.fen();
+        let mut tt = TranspositionTable::new(1);
+        let mut pool = ThreadPool::new(4);
+        assert_eq!(pool.size(), 4);
+        let limits = SearchLimits {
+            depth: 5,
+            ..SearchLimits::default()
+        };
+        let mut iterations = 0;
+        let result = pool.think(&mut pos, &mut tt, None, limits, |_| iterations += 1);
+        assert!(iterations >= 5);
+        assert!(result.depth >= 1);
+        assert_eq!(result.pv[0], result.best_move);
+        assert_eq!(pos.fen(), fen);
+
+        //A mate is found with any number of threads
+        let mut pos = Position::from_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1").unwrap();
+        for threads in [1, 3] {
+            pool.set_size(threads);
+            let limits = SearchLimits {
+                depth: 4,
+                ..SearchLimits::default()
+            };
+            let result = pool.think(&mut pos, &mut tt, None, limits, |_| ());
+            assert_eq!(result.best_move.to_string(), "a1a8");
+            assert_eq!(result.score, mate_in(1));
+        }
+    }
+
+    #[test]
+    fn test_smp_node_limit() {
+        let mut pos =
+            Position::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq -")
+                .unwrap();
+        let mut tt = TranspositionTable::new(1);
+        let mut pool = ThreadPool::new(4);
+        let limits = SearchLimits {
+            nodes: 20000,
+            ..SearchLimits::default()
+        };
+        let result = pool.think(&mut pos, &mut tt, None, limits, |_| ());
+        //A helper may count one more node before it sees that the limit was reached
+        assert!(result.nodes >= 20000);
+        assert!(result.nodes < 20000 + pool.size() as u64);
+        assert!(result.depth >= 1);
+    }
+}
@@ -0,0 +1,235 @@
+use crate::search::SearchLimits;
+use crate::types::*;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_MOVE_OVERHEAD: u64 = 10;
+pub const MAX_MOVE_OVERHEAD: u64 = 5000;
+//The remaining time is spread over at most this many moves
+const MOVE_HORIZON: i32 = 50;
+//A single legal move is played quickly, but not instantly so a gui can show the search
+const SINGLE_MOVE_TIME: f64 = 500.0;
+
+//Turns the clock of the side to move into an optimum time, which is adjusted after every
+//iteration, and a maximum time after which the search is stopped
+pub struct TimeManager {
+    start: Instant,
+    //In milliseconds. Without a clock only movetime sets a maximum
+    optimum: Option<f64>,
+    maximum: Option<f64>,
+    best_move: Move,
+    best_move_depth: Depth,
+    //Decays every iteration, so recent changes weigh more
+    best_move_changes: f64,
+    scores: Vec<Value>,
+    previous_time_reduction: f64,
+}
+
+impl TimeManager {
+    pub fn new(limits: &SearchLimits, us: Color, game_ply: i32) -> Self {
+        let mut tm = Self {
+            start: limits.start,
+            optimum: None,
+            maximum: None,
+            best_move: Move::none(),
+            best_move_depth: 0,
+            best_move_changes: 0.0,
+            scores: Vec::new(),
+            previous_time_reduction: 1.0,
+        };
+
+        if limits.movetime != 0 {
+            tm.maximum = Some(limits.movetime as f64);
+            return tm;
+        }
+        let time = limits.time[us as usize] as f64;
+        if time == 0.0 {
+            return tm;
+        }
+
+        let inc = limits.inc[us as usize] as f64;
+        let overhead = limits.move_overhead as f64;
+        let ply = game_ply as f64;
+        let mtg = if limits.movestogo > 0 {
+            limits.movestogo.min(MOVE_HORIZON)
+        } else {
+            MOVE_HORIZON
+        } as f64;
+        //Time for the remaining moves of the horizon, it is used as a divisor so never zero
+        let time_left = (time + inc * (mtg - 1.0) - overhead * (2.0 + mtg)).max(1.0);
+
+        //opt_scale is the part of time_left for this move, max_scale how far the optimum may
+        //be exceeded
+        let (opt_scale, max_scale) = if limits.movestogo == 0 {
+            (
+                (0.0120 + (ply + 3.0).powf(0.45) * 0.0039).min(0.2 * time / time_left),
+                (4.0 + ply / 12.0).min(7.0),
+            )
+        } else {
+            (
+                ((0.88 + ply / 116.4) / mtg).min(0.88 * time / time_left),
+                (1.5 + 0.11 * mtg).min(6.3),
+            )
+        };
+
+        let optimum = opt_scale * time_left;
+        tm.optimum = Some(optimum);
+        tm.maximum = Some(((0.84 * time - overhead).min(max_scale * optimum) - 10.0).max(0.0));
+        tm
+    }
+
+    //The time spent pondering was the opponent's, the clock starts running now
+    pub fn ponderhit(&mut self) {
+        self.start = Instant::now();
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    pub fn optimum(&self) -> Option<Duration> {
+        self.optimum.map(Duration::from_secs_f64).map(|d| d / 1000)
+    }
+
+    pub fn maximum(&self) -> Option<Duration> {
+        self.maximum.map(Duration::from_secs_f64).map(|d| d / 1000)
+    }
+
+    //Checked while searching, a running iteration is abandoned past the maximum time
+    pub fn out_of_time(&self) -> bool {
+        self.maximum.is_some_and(|m| self.elapsed_ms() >= m)
+    }
+
+    //Called after every completed iteration, whether starting another one is worth it. A
+    //best move that stays the same saves time, a changing best move or a falling score
+    //earn more
+    pub fn stop_after_iteration(
+        &mut self,
+        depth: Depth,
+        best_move: Move,
+        score: Value,
+        single_move: bool,
+    ) -> bool {
+        self.best_move_changes /= 2.0;
+        if best_move != self.best_move {
+            self.best_move = best_move;
+            self.best_move_depth = depth;
+            self.best_move_changes += 1.0;
+        }
+        self.scores.push(score);
+
+        let Some(optimum) = self.optimum else {
+            return false;
+        };
+
+        //Compared to the average score so far and to the score four iterations ago
+        let average = self.scores.iter().map(|&v| v as f64).sum::<f64>() / self.scores.len() as f64;
+        let earlier = self.scores[self.scores.len().saturating_sub(4)] as f64;
+        let score = score as f64;
+        let falling_eval =
+            ((69.0 + 13.0 * (average - score) + 6.0 * (earlier - score)) / 619.6).clamp(0.5, 1.5);
+
+        let time_reduction = if self.best_move_depth + 8 < depth {
+            1.57
+        } else {
+            0.65
+        };
+        let reduction = (1.4 + self.previous_time_reduction) / (2.08 * time_reduction);
+        self.previous_time_reduction = time_reduction;
+        let instability = 1.0 + 1.8 * self.best_move_changes;
+
+        let mut total = optimum * falling_eval * reduction * instability;
+        if single_move {
+            total = total.min(SINGLE_MOVE_TIME);
+        }
+        self.elapsed_ms() > total
+    }
+
+    fn elapsed_ms(&self) -> f64 {
+        self.elapsed().as_secs_f64() * 1000.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn clock(time: u64, inc: u64, movestogo: i32) -> SearchLimits {
+        SearchLimits {
+            time: [time, 0],
+            inc: [inc, 0],
+            movestogo,
+            ..SearchLimits::default()
+        }
+    }
+
+    fn millis(d: Option<Duration>) -> u128 {
+        d.unwrap().as_millis()
+    }
+
+    #[test]
+    fn test_time_allocation() {
+        //Without a clock the search is not timed, movetime only sets a hard limit
+        let tm = TimeManager::new(&SearchLimits::default(), Color::White, 0);
+        assert!(tm.optimum().is_none() && tm.maximum().is_none());
+        let limits = SearchLimits {
+            movetime: 300,
+            ..SearchLimits::default()
+        };
+        let tm = TimeManager::new(&limits, Color::White, 0);
+        assert!(tm.optimum().is_none());
+        assert_eq!(millis(tm.maximum()), 300);
+
+        //Only the clock of the side to move counts
+        let tm = TimeManager::new(&clock(60_000, 0, 0), Color::Black, 0);
+        assert!(tm.optimum().is_none());
+
+        let tm = TimeManager::new(&clock(60_000, 0, 0), Color::White, 0);
+        let (optimum, maximum) = (millis(tm.optimum()), millis(tm.maximum()));
+        assert!((600..2000).contains(&optimum), "{}", optimum);
+        assert!(optimum < maximum && maximum < 60_000 / 4, "{}", maximum);
+
+        //More time later in the game, with an increment and with fewer moves to go
+        let later = TimeManager::new(&clock(60_000, 0, 0), Color::White, 80);
+        assert!(millis(later.optimum()) > optimum);
+        let inc = TimeManager::new(&clock(60_000, 1000, 0), Color::White, 0);
+        assert!(millis(inc.optimum()) > optimum);
+        let mtg = TimeManager::new(&clock(60_000, 0, 5), Color::White, 0);
+        assert!(millis(mtg.optimum()) > 60_000 / 10);
+        assert!(millis(mtg.maximum()) < 60_000);
+
+        //Move overhead is kept in reserve, so a nearly empty clock is never overstepped
+        let mut limits = clock(100, 0, 0);
+        limits.move_overhead = 50;
+        let tm = TimeManager::new(&limits, Color::White, 40);
+        assert!(millis(tm.maximum()) < 50);
+        let tm = TimeManager::new(&clock(5, 0, 0), Color::White, 40);
+        assert_eq!(millis(tm.maximum()), 0);
+        assert!(tm.out_of_time());
+    }
+
+    #[test]
+    fn test_stop_after_iteration() {
+        //Pretend the optimum time has passed, a search with a changing best move and a
+        //falling score goes on, while a settled one stops
+        let mut limits = clock(60_000, 0, 0);
+        limits.start = Instant::now() - Duration::from_millis(1000);
+        let a = Move::new_from_to_sq(Square::SqE2, Square::SqE4);
+        let b = Move::new_from_to_sq(Square::SqD2, Square::SqD4);
+
+        let mut tm = TimeManager::new(&limits, Color::White, 0);
+        assert!(!tm.out_of_time());
+        let stop = (1..=12).map(|depth| tm.stop_after_iteration(depth, a, 20, false));
+        assert_eq!(stop.last(), Some(true));
+
+        let mut tm = TimeManager::new(&limits, Color::White, 0);
+        for depth in 1..=12 {
+            let best = if depth % 2 == 0 { a } else { b };
+            let stop = tm.stop_after_iteration(depth, best, 100 - 20 * depth, false);
+            assert!(!stop, "{}", depth);
+        }
+
+        //No clock, no early stop
+        let mut tm = TimeManager::new(&SearchLimits::default(), Color::White, 0);
+        assert!(!tm.stop_after_iteration(30, a, 0, true));
+    }
+}
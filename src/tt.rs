@@ -0,0 +1,406 @@
+use crate::types::*;
+use std::collections::TryReserveError;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+//Entries store depth - DEPTH_ENTRY_OFFSET so that a stored depth of 0 marks an empty slot
+const DEPTH_ENTRY_OFFSET: Depth = -3;
+
+//The lower 3 bits of gen_bound8 hold the pv flag and the bound, the generation uses the rest
+const GENERATION_BITS: u32 = 3;
+const GENERATION_DELTA: u8 = 1 << GENERATION_BITS;
+const GENERATION_CYCLE: u16 = 255 + GENERATION_DELTA as u16;
+const GENERATION_MASK: u16 = (0xFF << GENERATION_BITS) & 0xFF;
+
+const CLUSTER_SIZE: usize = 3;
+
+pub const DEFAULT_HASH_MB: usize = 16;
+
+//10 bytes per entry, 3 entries and 2 bytes of padding make up a 32 byte cluster. In the table
+//an entry is split into its key16 and the other 8 bytes, see Cluster
+#[derive(Debug, Clone, Copy, Default)]
+struct TTEntry {
+    key16: u16,
+    depth8: u8,
+    gen_bound8: u8,
+    move16: u16,
+    value16: i16,
+    eval16: i16,
+}
+
+impl TTEntry {
+    fn from_data(key16: u16, data: u64) -> Self {
+        Self {
+            key16,
+            move16: data as u16,
+            value16: (data >> 16) as i16,
+            eval16: (data >> 32) as i16,
+            depth8: (data >> 48) as u8,
+            gen_bound8: (data >> 56) as u8,
+        }
+    }
+
+    fn data(&self) -> u64 {
+        self.move16 as u64
+            | (self.value16 as u16 as u64) << 16
+            | (self.eval16 as u16 as u64) << 32
+            | (self.depth8 as u64) << 48
+            | (self.gen_bound8 as u64) << 56
+    }
+
+    fn bound(&self) -> Bound {
+        match self.gen_bound8 & 0x3 {
+            1 => Bound::BoundUpper,
+            2 => Bound::BoundLower,
+            3 => Bound::BoundExact,
+            _ => Bound::BoundNone,
+        }
+    }
+
+    fn is_occupied(&self) -> bool {
+        self.depth8 != 0
+    }
+
+    //Number of generations since the entry was written, scaled by GENERATION_DELTA
+    fn relative_age(&self, generation8: u8) -> u8 {
+        ((GENERATION_CYCLE + generation8 as u16 - self.gen_bound8 as u16) & GENERATION_MASK) as u8
+    }
+
+    fn read(&self) -> TTData {
+        TTData {
+            mv: Move::new(self.move16),
+            value: self.value16 as Value,
+            eval: self.eval16 as Value,
+            depth: self.depth8 as Depth + DEPTH_ENTRY_OFFSET,
+            bound: self.bound(),
+            is_pv: self.gen_bound8 & 0x4 != 0,
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn save(
+        &mut self,
+        key: Key,
+        value: Value,
+        is_pv: bool,
+        bound: Bound,
+        depth: Depth,
+        m: Move,
+        eval: Value,
+        generation8: u8,
+    ) {
+        let key16 = key as u16;
+
+        //Keep the old move if we have no new one for the same position
+        if m != Move::none() || key16 != self.key16 {
+            self.move16 = m.raw();
+        }
+
+        //Overwrite less valuable entries, an exact bound or a different position always wins
+        if bound == Bound::BoundExact
+            || key16 != self.key16
+            || depth - DEPTH_ENTRY_OFFSET + 2 * is_pv as Depth > self.depth8 as Depth - 4
+            || self.relative_age(generation8) != 0
+        {
+            debug_assert!(depth > DEPTH_ENTRY_OFFSET && depth < 256 + DEPTH_ENTRY_OFFSET);
+
+            self.key16 = key16;
+            self.depth8 = (depth - DEPTH_ENTRY_OFFSET) as u8;
+            self.gen_bound8 = generation8 | ((is_pv as u8) << 2) | bound as u8;
+            self.value16 = value as i16;
+            self.eval16 = eval as i16;
+        }
+    }
+}
+
+//Shared by all search threads without locking. The three key16 are packed into one word, so
+//every read and write of a key or of the rest of an entry is a single atomic access. A key and
+//its data may still be torn by two threads writing at once, which only costs a bad tt move or
+//value that the search has to cope with anyway
+#[derive(Debug, Default)]
+#[repr(C, align(32))]
+struct Cluster {
+    keys: AtomicU64,
+    data: [AtomicU64; CLUSTER_SIZE],
+}
+
+impl Cluster {
+    fn entry(&self, i: usize) -> TTEntry {
+        let key16 = (self.keys.load(Ordering::Relaxed) >> (16 * i)) as u16;
+        TTEntry::from_data(key16, self.data[i].load(Ordering::Relaxed))
+    }
+
+    fn entries(&self) -> [TTEntry; CLUSTER_SIZE] {
+        std::array::from_fn(|i| self.entry(i))
+    }
+
+    fn set_entry(&self, i: usize, e: &TTEntry) {
+        self.data[i].store(e.data(), Ordering::Relaxed);
+        let shift = 16 * i;
+        let _ = self
+            .keys
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |k| {
+                Some(k & !(0xFFFF << shift) | (e.key16 as u64) << shift)
+            });
+    }
+}
+
+//What a probe hands back to the search
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TTData {
+    pub mv: Move,
+    pub value: Value,
+    pub eval: Value,
+    pub depth: Depth,
+    pub bound: Bound,
+    pub is_pv: bool,
+}
+
+pub struct TranspositionTable {
+    table: Vec<Cluster>,
+    generation8: u8,
+}
+
+impl Default for TranspositionTable {
+    fn default() -> Self {
+        Self::new(DEFAULT_HASH_MB)
+    }
+}
+
+impl TranspositionTable {
+    pub fn new(mb_size: usize) -> Self {
+        let mut tt = Self {
+            table: Vec::new(),
+            generation8: 0,
+        };
+        tt.resize(mb_size)
+            .expect("not enough memory for the transposition table");
+        tt
+    }
+
+    //Reallocates the table with the given size in megabytes, dropping all entries. The old
+    //table is freed first so that both never take memory at the same time. When the new one
+    //does not fit, a table of the old size is allocated again
+    pub fn resize(&mut self, mb_size: usize) -> Result<(), TryReserveError> {
+        let cluster_count =
+            (mb_size.saturating_mul(1024 * 1024) / std::mem::size_of::<Cluster>()).max(1);
+        let old_count = self.table.len().max(1);
+        self.table = Vec::new();
+        self.generation8 = 0;
+
+        let result = self.table.try_reserve_exact(cluster_count);
+        let count = if result.is_ok() {
+            cluster_count
+        } else {
+            old_count
+        };
+        self.table.resize_with(count, Cluster::default);
+        result
+    }
+
+    pub fn clear(&mut self) {
+        self.table.fill_with(Cluster::default);
+        self.generation8 = 0;
+    }
+
+    //Called at the start of every search so that entries of older searches age out
+    pub fn new_search(&mut self) {
+        self.generation8 = self.generation8.wrapping_add(GENERATION_DELTA);
+    }
+
+    pub fn probe(&self, key: Key) -> Option<TTData> {
+        let key16 = key as u16;
+        self.cluster(key)
+            .entries()
+            .iter()
+            .find(|e| e.key16 == key16 && e.is_occupied())
+            .map(TTEntry::read)
+    }
+
+    //Stores into the slot of the same position if there is one, otherwise replaces the
+    //shallowest and oldest entry of the cluster
+    #[allow(clippy::too_many_arguments)]
+    pub fn store(
+        &self,
+        key: Key,
+        value: Value,
+        is_pv: bool,
+        bound: Bound,
+        depth: Depth,
+        m: Move,
+        eval: Value,
+    ) {
+        let key16 = key as u16;
+        let generation8 = self.generation8;
+        let cluster = self.cluster(key);
+        let mut entries = cluster.entries();
+
+        let slot = match entries.iter().position(|e| e.key16 == key16) {
+            Some(i) => i,
+            None => (0..CLUSTER_SIZE)
+                .min_by_key(|&i| {
+                    entries[i].depth8 as i32 - entries[i].relative_age(generation8) as i32
+                })
+                .unwrap(),
+        };
+        entries[slot].save(key, value, is_pv, bound, depth, m, eval, generation8);
+        cluster.set_entry(slot, &entries[slot]);
+    }
+
+    //Approximate table usage in permille, only counting entries of the current search
+    pub fn hashfull(&self) -> usize {
+        let sample = self.table.len().min(1000);
+        let count: usize = self.table[..sample]
+            .iter()
+            .map(|c| {
+                c.entries()
+                    .iter()
+                    .filter(|e| e.is_occupied() && e.relative_age(self.generation8) == 0)
+                    .count()
+            })
+            .sum();
+        count * 1000 / (sample * CLUSTER_SIZE)
+    }
+
+    //The high bits of key * cluster_count, which maps the key uniformly onto the table
+    fn cluster_index(&self, key: Key) -> usize {
+        ((key as u128 * self.table.len() as u128) >> 64) as usize
+    }
+
+    fn cluster(&self, key: Key) -> &Cluster {
+        &self.table[self.cluster_index(key)]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn some_move() -> Move {
+        Move::new_from_to_sq(Square::SqE2, Square::SqE4)
+    }
+
+    #[test]
+    fn test_cluster_size() {
+        assert_eq!(std::mem::size_of::<TTEntry>(), 10);
+        assert_eq!(std::mem::size_of::<Cluster>(), 32);
+    }
+
+    #[test]
+    fn test_store_and_probe() {
+        let tt = TranspositionTable::new(1);
+        let key: Key = 0x1234_5678_9abc_def0;
+        assert_eq!(tt.probe(key), None);
+
+        tt.store(key, -57, true, Bound::BoundLower, 7, some_move(), 12);
+        let data = tt.probe(key).unwrap();
+        assert_eq!(data.mv, some_move());
+        assert_eq!(data.value, -57);
+        assert_eq!(data.eval, 12);
+        assert_eq!(data.depth, 7);
+        assert_eq!(data.bound, Bound::BoundLower);
+        assert!(data.is_pv);
+
+        //A shallower result without a move keeps the old move and the deeper entry
+        tt.store(key, 3, false, Bound::BoundUpper, 1, Move::none(), 0);
+        let data = tt.probe(key).unwrap();
+        assert_eq!(data.mv, some_move());
+        assert_eq!(data.depth, 7);
+
+        //But an exact bound always overwrites
+        tt.store(key, 3, false, Bound::BoundExact, 1, Move::none(), 0);
+        let data = tt.probe(key).unwrap();
+        assert_eq!(data.mv, some_move());
+        assert_eq!(
+            (data.value, data.depth, data.bound),
+            (3, 1, Bound::BoundExact)
+        );
+    }
+
+    #[test]
+    fn test_replacement() {
+        let mut tt = TranspositionTable::new(1);
+        //Keys that only differ in the low bits land in the same cluster
+        let keys: Vec<Key> = (1..=4).map(|i| 0xabcd_0000_0000_0000 | i).collect();
+        for (i, &key) in keys[..3].iter().enumerate() {
+            tt.store(
+                key,
+                0,
+                false,
+                Bound::BoundExact,
+                10 - i as Depth,
+                some_move(),
+                0,
+            );
+        }
+        tt.store(keys[3], 0, false, Bound::BoundExact, 20, some_move(), 0);
+        //The shallowest entry made room
+        assert!(tt.probe(keys[2]).is_none());
+        assert!(keys[..2].iter().all(|&k| tt.probe(k).is_some()));
+        assert_eq!(tt.probe(keys[3]).unwrap().depth, 20);
+
+        //Entries of older searches are replaced before shallower entries of the current one
+        tt.clear();
+        for &key in &keys[..3] {
+            tt.store(key, 0, false, Bound::BoundExact, 5, some_move(), 0);
+        }
+        tt.new_search();
+        tt.store(keys[1], 0, false, Bound::BoundExact, 3, some_move(), 0);
+        tt.store(keys[3], 0, false, Bound::BoundExact, 20, some_move(), 0);
+        assert!(tt.probe(keys[0]).is_none());
+        assert_eq!(tt.probe(keys[1]).unwrap().depth, 3);
+        assert!(tt.probe(keys[2]).is_some());
+    }
+
+    #[test]
+    fn test_hashfull_clear_and_resize() {
+        let mut tt = TranspositionTable::new(1);
+        assert_eq!(tt.hashfull(), 0);
+        for i in 0..100_000u64 {
+            let key = i.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+            tt.store(key, 0, false, Bound::BoundExact, 5, some_move(), 0);
+        }
+        let full = tt.hashfull();
+        assert!(full > 500 && full <= 1000);
+
+        //Old entries do not count towards hashfull
+        tt.new_search();
+        assert_eq!(tt.hashfull(), 0);
+
+        tt.clear();
+        assert_eq!(tt.hashfull(), 0);
+        assert_eq!(tt.probe(0x9e37_79b9_7f4a_7c15), None);
+
+        tt.store(42, 0, false, Bound::BoundExact, 5, some_move(), 0);
+        tt.resize(2).unwrap();
+        assert_eq!(tt.table.len(), 2 * 1024 * 1024 / 32);
+        assert_eq!(tt.probe(42), None);
+
+        //A size that can not be allocated keeps a working table of the old size
+        assert!(tt.resize(usize::MAX).is_err());
+        assert_eq!(tt.table.len(), 2 * 1024 * 1024 / 32);
+        tt.store(42, 0, false, Bound::BoundExact, 5, some_move(), 0);
+        assert_eq!(tt.probe(42).unwrap().mv, some_move());
+    }
+
+    #[test]
+    fn test_shared_between_threads() {
+        let tt = TranspositionTable::new(1);
+        std::thread::scope(|s| {
+            for t in 0..4u64 {
+                let tt = &tt;
+                s.spawn(move || {
+                    for i in 0..1000u64 {
+                        let key = (t * 1000 + i).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+                        tt.store(key, t as Value, false, Bound::BoundExact, 5, some_move(), 0);
+                    }
+                });
+            }
+        });
+        //Apart from entries replaced by a later store, every thread's entries are intact
+        let found = (0..4000u64)
+            .filter_map(|i| tt.probe(i.wrapping_mul(0x9e37_79b9_7f4a_7c15)))
+            .inspect(|data| assert_eq!((data.depth, data.mv), (5, some_move())))
+            .count();
+        assert!(found > 3900, "{}", found);
+    }
+}
@@ -1,3 +1,4 @@
+use std::fmt;
 use std::ops::{
     Add, AddAssign, BitAnd, BitAndAssign, BitOr, BitOrAssign, BitXor, BitXorAssign, Mul, Not, Sub,
     SubAssign,
@@ -8,15 +9,19 @@ pub type Key = u64;
 pub type Depth = i32;
 
 const MAX_MOVES: i32 = 256;
-const MAX_PLY: i32 = 246;
-
-const VALUE_ZERO: Value = 0;
-const VALUE_DRAW: Value = 0;
-const VALUE_NONE: Value = 32002;
-const VALUE_INFINITE: Value = 32001;
-const VALUE_MATE: Value = 32000;
-const VALUE_MATE_IN_MAX_PLY: Value = VALUE_MATE - MAX_PLY;
-const VALUE_MATED_IN_MAX_PLY: Value = -VALUE_MATE_IN_MAX_PLY;
+pub const MAX_PLY: i32 = 246;
+
+//Quiescence search depths, quiet checks are only tried at the first qsearch ply
+pub const DEPTH_QS_CHECKS: Depth = 0;
+pub const DEPTH_QS_NO_CHECKS: Depth = -1;
+
+pub const VALUE_ZERO: Value = 0;
+pub const VALUE_DRAW: Value = 0;
+pub const VALUE_NONE: Value = 32002;
+pub const VALUE_INFINITE: Value = 32001;
+pub const VALUE_MATE: Value = 32000;
+pub const VALUE_MATE_IN_MAX_PLY: Value = VALUE_MATE - MAX_PLY;
+pub const VALUE_MATED_IN_MAX_PLY: Value = -VALUE_MATE_IN_MAX_PLY;
 const VALUE_TB: Value = VALUE_MATE_IN_MAX_PLY - 1;
 const VALUE_TB_WIN_IN_MAX_PLY: Value = VALUE_TB - MAX_PLY;
 const VALUE_TB_LOSS_IN_MAX_PLY: Value = -VALUE_TB_WIN_IN_MAX_PLY;
@@ -145,6 +150,7 @@ pub enum Color {
 }
 
 #[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Bound {
     BoundNone = 0,
     BoundUpper,
@@ -405,6 +411,7 @@ pub enum Square {
     SqG3,
     SqH3,
     SqA4,
+    SqB4,
     SqC4,
     SqD4,
     SqE4,
@@ -575,6 +582,30 @@ impl Square {
         let k = *self as i32 ^ (c as i32 * 56);
         return Square::new_from_n(k);
     }
+
+    //Parses a square in algebraic notation such as "e4"
+    pub fn from_algebraic(s: &str) -> Option<Square> {
+        let bytes = s.as_bytes();
+        if bytes.len() != 2 {
+            return None;
+        }
+        let (f, r) = (bytes[0], bytes[1]);
+        if !(b'a'..=b'h').contains(&f) || !(b'1'..=b'8').contains(&r) {
+            return None;
+        }
+        Some(make_square((f - b'a') as usize, (r - b'1') as usize))
+    }
+}
+
+impl fmt::Display for Square {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !Square::is_square_valid(*self as i32) {
+            return write!(f, "-");
+        }
+        let file = (b'a' + self.file_of() as u8) as char;
+        let rank = (b'1' + self.rank_of() as u8) as char;
+        write!(f, "{}{}", file, rank)
+    }
 }
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum PieceType {
@@ -647,6 +678,7 @@ impl BitXorAssign<Square> for Bitboard {
 #[repr(i32)]
 #[derive(Debug, PartialEq, Clone, Copy, Default)]
 pub enum CastlingRights {
+    #[default]
     NoCastling = 0,
     WhiteOO = 1,
     WhiteOOO = 1 << 1,
@@ -657,7 +689,14 @@ pub enum CastlingRights {
     QueenSide = (1 << 1) | (1 << 3),
     WhiteCastling = 1 | (1 << 1),
     BlackCastling = (1 << 2) | (1 << 3),
-    #[default]
+
+    //Remaining mixed combinations so every 4 bit mask has a variant
+    WhiteOOOBlackOO = (1 << 1) | (1 << 2),
+    WhiteBlackOO = 1 | (1 << 1) | (1 << 2),
+    WhiteOOBlackOOO = 1 | (1 << 3),
+    WhiteBlackOOO = 1 | (1 << 1) | (1 << 3),
+    WhiteOOBlack = 1 | (1 << 2) | (1 << 3),
+    WhiteOOOBlack = (1 << 1) | (1 << 2) | (1 << 3),
     AnyCastling = 1 | (1 << 1) | (1 << 2) | (1 << 3),
 
     CastlingRightsNb = 16,
@@ -675,6 +714,12 @@ impl CastlingRights {
             10 => Self::QueenSide,
             3 => Self::WhiteCastling,
             12 => Self::BlackCastling,
+            6 => Self::WhiteOOOBlackOO,
+            7 => Self::WhiteBlackOO,
+            9 => Self::WhiteOOBlackOOO,
+            11 => Self::WhiteBlackOOO,
+            13 => Self::WhiteOOBlack,
+            14 => Self::WhiteOOOBlack,
             15 => Self::AnyCastling,
             16 => Self::CastlingRightsNb,
             _ => panic!(
@@ -719,8 +764,8 @@ impl BitAndAssign for CastlingRights {
 impl Not for CastlingRights {
     type Output = Self;
     fn not(self) -> CastlingRights {
-        let nw = self as i32;
-        return CastlingRights::new_from_n(nw);
+        let nw = self as i32 ^ CastlingRights::AnyCastling as i32;
+        CastlingRights::new_from_n(nw)
     }
 }
 
@@ -952,8 +997,51 @@ impl Move {
         self.data != Self::none().data && self.data != Self::null().data
     }
 
-    pub fn make(move_type: MoveType, from: Square, to: Square, pt: PieceType) {
-        todo!();
+    //Builds a special move. The promotion piece type is only encoded for promotions
+    pub const fn make(move_type: MoveType, from: Square, to: Square, pt: PieceType) -> Self {
+        let promotion = match move_type {
+            MoveType::Promotion => (pt as i32 - PieceType::Knight as i32) << 12,
+            _ => 0,
+        };
+        let data = move_type as i32 | promotion | ((from as i32) << 6) | to as i32;
+        Move { data: data as u16 }
+    }
+
+    //Coordinate notation used by the uci protocol. Castling moves are stored as king captures
+    //rook, which is how chess960 prints them. Otherwise they are printed as the king's two
+    //square move
+    pub fn to_uci(&self, chess960: bool) -> String {
+        if *self == Move::none() {
+            return "(none)".to_string();
+        }
+        if *self == Move::null() {
+            return "0000".to_string();
+        }
+
+        let from = self.from_sq();
+        let mut to = self.to_sq();
+        if self.type_of() == MoveType::Castling && !chess960 {
+            let file = if to > from { File::FileG } else { File::FileC };
+            to = make_square(file as usize, from.rank_of() as usize);
+        }
+        let mut s = format!("{}{}", from, to);
+
+        if self.type_of() == MoveType::Promotion {
+            s.push(match self.promotion_type() {
+                PieceType::Knight => 'n',
+                PieceType::Bishop => 'b',
+                PieceType::Rook => 'r',
+                _ => 'q',
+            });
+        }
+        s
+    }
+}
+
+//Coordinate notation used by the uci protocol, see Move::to_uci
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_uci(false))
     }
 }
 
@@ -1048,7 +1136,7 @@ mod tests {
 
     #[test]
     fn test_move_type() {
-        let mv = Move::new(11 << 14);
+        let mv = Move::new(1 << 14);
         assert_eq!(mv.type_of(), MoveType::Promotion);
     }
 
@@ -1065,6 +1153,22 @@ mod tests {
         assert!(!Move::null().is_ok());
         assert!(!Move::none().is_ok());
     }
+
+    #[test]
+    fn test_move_to_uci() {
+        use Square::*;
+        let castling = Move::make(MoveType::Castling, SqE1, SqH1, PieceType::Knight);
+        assert_eq!(castling.to_uci(false), "e1g1");
+        assert_eq!(castling.to_uci(true), "e1h1");
+        let castling = Move::make(MoveType::Castling, SqE8, SqA8, PieceType::Knight);
+        assert_eq!(castling.to_uci(false), "e8c8");
+        assert_eq!(castling.to_uci(true), "e8a8");
+        let promotion = Move::make(MoveType::Promotion, SqB7, SqA8, PieceType::Rook);
+        assert_eq!(promotion.to_uci(true), "b7a8r");
+        assert_eq!(Move::new_from_to_sq(SqG1, SqF3).to_string(), "g1f3");
+        assert_eq!(Move::null().to_uci(true), "0000");
+        assert_eq!(Move::none().to_string(), "(none)");
+    }
     #[test]
     fn test_square_flip_rank() {
         assert_eq!(Square::SqA1.flip_rank(), Square::SqA8);
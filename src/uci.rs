@@ -0,0 +1,981 @@
+use crate::board::movegen::generate;
+use crate::board::perft;
+use crate::board::position::{FenError, Position, StateInfo, START_FEN};
+use crate::eval;
+use crate::nnue::{self, Accumulator, Network};
+use crate::search::{SearchLimits, SearchResult};
+use crate::thread::{ThreadPool, MAX_THREADS};
+use crate::timeman::{DEFAULT_MOVE_OVERHEAD, MAX_MOVE_OVERHEAD};
+use crate::tt::{TranspositionTable, DEFAULT_HASH_MB};
+use crate::types::*;
+use std::io::{self, BufRead, Write};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Instant;
+
+const ENGINE_NAME: &str = "RustyRaven";
+const ENGINE_AUTHOR: &str = "the RustyRaven developers";
+
+const MAX_HASH_MB: usize = 33554432;
+const MAX_MULTIPV: usize = 256;
+//EvalFile value that turns the nnue evaluation off
+const NO_EVAL_FILE: &str = "<empty>";
+
+pub struct Uci {
+    pos: Position,
+    tt: TranspositionTable,
+    threads: ThreadPool,
+    network: Option<Network>,
+    move_overhead: u64,
+    multipv: usize,
+    signals: Arc<GoSignals>,
+    //Number of go commands executed so far, the id of the last one
+    searches: usize,
+}
+
+//Ties a stop or ponderhit to the go it was sent after. The input thread reads these ahead of
+//the command loop, so a signal for a go that is still queued is kept until that go starts and
+//one for a go that is already over is dropped
+struct GoSignals {
+    stop: Arc<AtomicBool>,
+    ponderhit: Arc<AtomicBool>,
+    //Id of the last go that was stopped or got a ponderhit, a signal for a go also ends the
+    //ones queued before it
+    stopped: AtomicUsize,
+    hit: AtomicUsize,
+}
+
+impl GoSignals {
+    fn new(threads: &ThreadPool) -> Self {
+        Self {
+            stop: threads.stop_signal(),
+            ponderhit: threads.ponderhit_signal(),
+            stopped: AtomicUsize::new(0),
+            hit: AtomicUsize::new(0),
+        }
+    }
+
+    //Clears the flags for go number id, then raises the ones already sent for it
+    fn start(&self, id: usize) {
+        self.stop.store(false, Ordering::SeqCst);
+        self.ponderhit.store(false, Ordering::SeqCst);
+        if self.stopped.load(Ordering::SeqCst) >= id {
+            self.stop.store(true, Ordering::SeqCst);
+        }
+        if self.hit.load(Ordering::SeqCst) >= id {
+            self.ponderhit.store(true, Ordering::SeqCst);
+        }
+    }
+
+    //The flag may be raised while go number id is still queued, its start raises it again
+    fn stop(&self, id: usize) {
+        self.stopped.store(id, Ordering::SeqCst);
+        self.stop.store(true, Ordering::SeqCst);
+    }
+
+    fn ponderhit(&self, id: usize) {
+        self.hit.store(id, Ordering::SeqCst);
+        self.ponderhit.store(true, Ordering::SeqCst);
+    }
+}
+
+impl Default for Uci {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Uci {
+    pub fn new() -> Self {
+        let mut pos = Position::default();
+        pos.set(START_FEN).unwrap();
+        let threads = ThreadPool::default();
+        Self {
+            pos,
+            tt: TranspositionTable::new(DEFAULT_HASH_MB),
+            signals: Arc::new(GoSignals::new(&threads)),
+            threads,
+            network: None,
+            move_overhead: DEFAULT_MOVE_OVERHEAD,
+            multipv: 1,
+            searches: 0,
+        }
+    }
+
+    //Reads commands from stdin until "quit" or the end of input
+    pub fn run(&mut self) {
+        self.serve(io::BufReader::new(io::stdin()), io::stdout);
+    }
+
+    //A separate thread reads the commands, so stop, quit and ponderhit reach a running search.
+    //The command loop is blocked until bestmove, so that thread also answers isready while a
+    //go is pending
+    fn serve<W: Write>(
+        &mut self,
+        input: impl BufRead + Send + 'static,
+        output: impl Fn() -> W + Clone + Send + 'static,
+    ) {
+        let (sender, receiver) = mpsc::channel();
+        let signals = self.signals.clone();
+        //Go commands sent to the command loop, the id of the go a stop is meant for
+        let mut queued = self.searches;
+        //Go commands sent to the command loop that it has not finished yet
+        let pending_go = Arc::new(AtomicUsize::new(0));
+        let searching = pending_go.clone();
+        let reader_output = output.clone();
+        thread::spawn(move || {
+            for line in input.lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                match line.trim() {
+                    "stop" | "quit" => signals.stop(queued),
+                    "ponderhit" => signals.ponderhit(queued),
+                    "isready" if searching.load(Ordering::SeqCst) > 0 => {
+                        let mut out = reader_output();
+                        writeln!(out, "readyok").unwrap();
+                        out.flush().unwrap();
+                        continue;
+                    }
+                    _ => (),
+                }
+                if is_go(&line) {
+                    queued += 1;
+                    searching.fetch_add(1, Ordering::SeqCst);
+                }
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+            //The end of input is a quit, so a go infinite or go ponder does not run forever
+            signals.stop(queued);
+            let _ = sender.send("quit".to_string());
+        });
+
+        let mut out = output();
+        for line in receiver {
+            let running = self.execute(&line, &mut out);
+            out.flush().unwrap();
+            if is_go(&line) {
+                pending_go.fetch_sub(1, Ordering::SeqCst);
+            }
+            if !running {
+                break;
+            }
+        }
+    }
+
+    //The flags that end a running go and its pondering, for callers of execute that read
+    //commands themselves. Each go clears them when it starts
+    pub fn stop_signal(&self) -> Arc<AtomicBool> {
+        self.threads.stop_signal()
+    }
+
+    pub fn ponderhit_signal(&self) -> Arc<AtomicBool> {
+        self.threads.ponderhit_signal()
+    }
+
+    //Executes a single command. Returns false once the engine should shut down
+    pub fn execute(&mut self, cmd: &str, out: &mut impl Write) -> bool {
+        let mut tokens = cmd.split_whitespace();
+        let Some(token) = tokens.next() else {
+            return true;
+        };
+
+        match token {
+            "uci" => {
+                writeln!(out, "id name {}", ENGINE_NAME).unwrap();
+                writeln!(out, "id author {}", ENGINE_AUTHOR).unwrap();
+                writeln!(out).unwrap();
+                writeln!(
+                    out,
+                    "option name Hash type spin default {} min 1 max {}",
+                    DEFAULT_HASH_MB, MAX_HASH_MB
+                )
+                .unwrap();
+                writeln!(out, "option name Clear Hash type button").unwrap();
+                writeln!(
+                    out,
+                    "option name Threads type spin default 1 min 1 max {}",
+                    MAX_THREADS
+                )
+                .unwrap();
+                writeln!(
+                    out,
+                    "option name EvalFile type string default {}",
+                    NO_EVAL_FILE
+                )
+                .unwrap();
+                writeln!(
+                    out,
+                    "option name Move Overhead type spin default {} min 0 max {}",
+                    DEFAULT_MOVE_OVERHEAD, MAX_MOVE_OVERHEAD
+                )
+                .unwrap();
+                writeln!(
+                    out,
+                    "option name MultiPV type spin default 1 min 1 max {}",
+                    MAX_MULTIPV
+                )
+                .unwrap();
+                writeln!(out, "option name Ponder type check default false").unwrap();
+                writeln!(out, "option name UCI_Chess960 type check default false").unwrap();
+                writeln!(out, "uciok").unwrap();
+            }
+            "isready" => writeln!(out, "readyok").unwrap(),
+            "ucinewgame" => {
+                self.pos.set(START_FEN).unwrap();
+                self.tt.clear();
+                self.threads.clear();
+            }
+            "position" => {
+                if let Err(e) = self.position(tokens) {
+                    writeln!(out, "info string {}", e).unwrap();
+                }
+            }
+            "setoption" => self.setoption(tokens, out),
+            "go" => self.go(tokens, out),
+            //The search these were meant for is already over
+            "stop" | "ponderhit" => (),
+            "quit" => return false,
+            "d" => {
+                writeln!(out, "{}", self.pos).unwrap();
+                writeln!(out, "Fen: {}", self.pos.fen()).unwrap();
+            }
+            "eval" => {
+                //Both evaluations are from the side to move's point of view
+                let sign = match self.pos.side_to_move() {
+                    Color::White => 1.0,
+                    _ => -1.0,
+                };
+                let mut v = sign * eval::evaluate(&self.pos) as f64 / PawnValue as f64;
+                writeln!(out, "Classical evaluation: {:+.2} (white side)", v).unwrap();
+                if let Some(net) = &self.network {
+                    v = sign * nnue::evaluate(net, &self.pos, &mut [Accumulator::default()]) as f64
+                        / PawnValue as f64;
+                    writeln!(out, "NNUE evaluation: {:+.2} (white side)", v).unwrap();
+                }
+                writeln!(out, "Final evaluation: {:+.2} (white side)", v).unwrap();
+            }
+            _ => writeln!(out, "Unknown command: '{}'", cmd).unwrap(),
+        }
+        true
+    }
+
+    //position startpos|fen <fen> [moves <m1> <m2> ...]
+    fn position<'a>(&mut self, mut tokens: impl Iterator<Item = &'a str>) -> Result<(), UciError> {
+        let fen = match tokens.next() {
+            Some("startpos") => {
+                if let Some(token) = tokens.next() {
+                    if token != "moves" {
+                        return Err(UciError::UnexpectedToken(token.to_string()));
+                    }
+                }
+                START_FEN.to_string()
+            }
+            Some("fen") => tokens
+                .by_ref()
+                .take_while(|&t| t != "moves")
+                .collect::<Vec<&str>>()
+                .join(" "),
+            Some(token) => return Err(UciError::UnexpectedToken(token.to_string())),
+            None => return Err(UciError::MissingArgument("position")),
+        };
+
+        let mut pos = Position::default();
+        pos.set_chess960(self.pos.is_chess960());
+        pos.set(&fen).map_err(UciError::Fen)?;
+
+        for token in tokens {
+            let m = pos
+                .parse_uci_move(token)
+                .ok_or(UciError::InvalidMove(token.to_string()))?;
+            let mut st = StateInfo::default();
+            let gives_check = pos.gives_check(m);
+            pos.do_move(m, &mut st, gives_check);
+        }
+        self.pos = pos;
+        Ok(())
+    }
+
+    //setoption name <id> [value <x>]
+    fn setoption<'a>(&mut self, tokens: impl Iterator<Item = &'a str>, out: &mut impl Write) {
+        let tokens: Vec<&str> = tokens.collect();
+        let value_idx = tokens.iter().position(|&t| t == "value");
+        let name = tokens[..value_idx.unwrap_or(tokens.len())]
+            .iter()
+            .skip_while(|&&t| t == "name")
+            .copied()
+            .collect::<Vec<&str>>()
+            .join(" ");
+        let value = value_idx.map(|i| tokens[i + 1..].join(" "));
+
+        //Option names are case insensitive
+        match name.to_lowercase().as_str() {
+            "hash" => match value.as_deref().map(str::parse::<usize>) {
+                //The table keeps its old size when the new one can not be allocated
+                Some(Ok(mb)) => {
+                    if self.tt.resize(mb.clamp(1, MAX_HASH_MB)).is_err() {
+                        writeln!(out, "info string Not enough memory for {} MB of hash", mb)
+                            .unwrap();
+                    }
+                }
+                _ => writeln!(out, "info string Invalid value for option {}", name).unwrap(),
+            },
+            "clear hash" => self.tt.clear(),
+            "threads" => match value.as_deref().map(str::parse::<usize>) {
+                Some(Ok(n)) => self.threads.set_size(n),
+                _ => writeln!(out, "info string Invalid value for option {}", name).unwrap(),
+            },
+            "move overhead" => match value.as_deref().map(str::parse::<u64>) {
+                Some(Ok(ms)) => self.move_overhead = ms.min(MAX_MOVE_OVERHEAD),
+                _ => writeln!(out, "info string Invalid value for option {}", name).unwrap(),
+            },
+            "evalfile" => match value.as_deref() {
+                None | Some("") | Some(NO_EVAL_FILE) => {
+                    self.network = None;
+                    writeln!(out, "info string NNUE evaluation disabled").unwrap();
+                }
+                Some(path) => match Network::from_file(path) {
+                    Ok(net) => {
+                        writeln!(out, "info string NNUE evaluation using {}", path).unwrap();
+                        self.network = Some(net);
+                    }
+                    Err(e) => writeln!(out, "info string {}: {}", path, e).unwrap(),
+                },
+            },
+            "multipv" => match value.as_deref().map(str::parse::<usize>) {
+                Some(Ok(n)) => self.multipv = n.clamp(1, MAX_MULTIPV),
+                _ => writeln!(out, "info string Invalid value for option {}", name).unwrap(),
+            },
+            //Only tells that the gui may send go ponder, which is always supported
+            "ponder" => (),
+            "uci_chess960" => match value.as_deref() {
+                Some("true") => self.pos.set_chess960(true),
+                Some("false") => self.pos.set_chess960(false),
+                _ => writeln!(out, "info string Invalid value for option {}", name).unwrap(),
+            },
+            _ => writeln!(out, "No such option: {}", name).unwrap(),
+        }
+    }
+
+    //go [perft <depth>] [depth <n>] [nodes <n>] [mate <n>] [movetime <ms>] [wtime <ms>]
+    //[btime <ms>] [winc <ms>] [binc <ms>] [movestogo <n>] [infinite] [ponder]
+    //[searchmoves <move>...]
+    fn go<'a>(&mut self, tokens: impl Iterator<Item = &'a str>, out: &mut impl Write) {
+        let start = Instant::now();
+        self.searches += 1;
+        self.signals.start(self.searches);
+        let mut tokens = tokens.peekable();
+        if let Some(&"perft") = tokens.peek() {
+            tokens.next();
+            match tokens.next().map(str::parse::<Depth>) {
+                Some(Ok(depth)) => self.perft(depth, out),
+                Some(Err(_)) => writeln!(out, "info string Invalid perft depth").unwrap(),
+                None => writeln!(out, "info string Missing perft depth").unwrap(),
+            }
+            return;
+        }
+
+        let mut limits = SearchLimits {
+            move_overhead: self.move_overhead,
+            multipv: self.multipv,
+            start,
+            ..SearchLimits::default()
+        };
+        let (w, b) = (Color::White as usize, Color::Black as usize);
+        while let Some(token) = tokens.next() {
+            let result = match token {
+                "depth" => parse_value(tokens.next(), "depth").map(|d| limits.depth = d),
+                "nodes" => parse_value(tokens.next(), "nodes").map(|n| limits.nodes = n),
+                "mate" => parse_value(tokens.next(), "mate").map(|n| limits.mate = n),
+                "movetime" => parse_ms(tokens.next(), "movetime").map(|t| limits.movetime = t),
+                "wtime" => parse_ms(tokens.next(), "wtime").map(|t| limits.time[w] = t),
+                "btime" => parse_ms(tokens.next(), "btime").map(|t| limits.time[b] = t),
+                "winc" => parse_ms(tokens.next(), "winc").map(|t| limits.inc[w] = t),
+                "binc" => parse_ms(tokens.next(), "binc").map(|t| limits.inc[b] = t),
+                "movestogo" => {
+                    parse_value(tokens.next(), "movestogo").map(|n| limits.movestogo = n)
+                }
+                "infinite" => {
+                    limits.infinite = true;
+                    Ok(())
+                }
+                "ponder" => {
+                    limits.ponder = true;
+                    Ok(())
+                }
+                //Takes moves up to the first token that is not a legal move
+                "searchmoves" => {
+                    while let Some(m) = tokens.peek().and_then(|t| self.pos.parse_uci_move(t)) {
+                        limits.searchmoves.push(m);
+                        tokens.next();
+                    }
+                    Ok(())
+                }
+                _ => Ok(()),
+            };
+            if let Err(e) = result {
+                writeln!(out, "info string {}", e).unwrap();
+                return;
+            }
+        }
+
+        let chess960 = self.pos.is_chess960();
+        let result = self.threads.think(
+            &mut self.pos,
+            &mut self.tt,
+            self.network.as_ref(),
+            limits,
+            |r| writeln!(out, "{}", info(r, chess960)).unwrap(),
+        );
+        let best = uci_move(result.best_move, chess960);
+        match self.ponder_move(&result) {
+            Some(m) => writeln!(out, "bestmove {} ponder {}", best, m.to_uci(chess960)).unwrap(),
+            None => writeln!(out, "bestmove {}", best).unwrap(),
+        }
+    }
+
+    //The expected reply to the best move, taken from the transposition table when the pv
+    //ends after the best move
+    fn ponder_move(&mut self, result: &SearchResult) -> Option<Move> {
+        if let Some(&m) = result.pv.get(1) {
+            return Some(m);
+        }
+        let best = result.best_move;
+        if !best.is_ok() {
+            return None;
+        }
+        let mut st = StateInfo::default();
+        let gives_check = self.pos.gives_check(best);
+        self.pos.do_move(best, &mut st, gives_check);
+        let reply = self
+            .tt
+            .probe(self.pos.key())
+            .map(|data| data.mv)
+            .filter(|&m| generate::<LEGAL>(&self.pos).contains(m));
+        self.pos.undo_move(best);
+        reply
+    }
+
+    //go perft <depth>, prints the node count below every root move
+    fn perft(&mut self, depth: Depth, out: &mut impl Write) {
+        let depth = depth.max(1);
+        let mut nodes = 0;
+        for (m, count) in perft::divide(&mut self.pos, depth) {
+            writeln!(out, "{}: {}", m, count).unwrap();
+            nodes += count;
+        }
+        writeln!(out).unwrap();
+        writeln!(out, "Nodes searched: {}", nodes).unwrap();
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum UciError {
+    MissingArgument(&'static str),
+    UnexpectedToken(String),
+    InvalidMove(String),
+    Fen(FenError),
+}
+
+impl std::fmt::Display for UciError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            UciError::MissingArgument(cmd) => write!(f, "missing argument to {}", cmd),
+            UciError::UnexpectedToken(t) => write!(f, "unexpected token: {}", t),
+            UciError::InvalidMove(m) => write!(f, "invalid move: {}", m),
+            UciError::Fen(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+fn is_go(cmd: &str) -> bool {
+    cmd.split_whitespace().next() == Some("go")
+}
+
+//Some guis send a negative clock once the time is up
+fn parse_ms(token: Option<&str>, name: &'static str) -> Result<u64, UciError> {
+    parse_value::<i64>(token, name).map(|ms| ms.max(0) as u64)
+}
+
+fn parse_value<T: std::str::FromStr>(
+    token: Option<&str>,
+    name: &'static str,
+) -> Result<T, UciError> {
+    let token = token.ok_or(UciError::MissingArgument(name))?;
+    token
+        .parse()
+        .map_err(|_| UciError::UnexpectedToken(token.to_string()))
+}
+
+//The score in centipawns, or in moves to mate
+fn score(v: Value) -> String {
+    if v.abs() >= VALUE_MATE_IN_MAX_PLY {
+        let moves = if v > 0 {
+            (VALUE_MATE - v + 1) / 2
+        } else {
+            -(VALUE_MATE + v) / 2
+        };
+        format!("mate {}", moves)
+    } else {
+        format!("cp {}", v * 100 / PawnValue)
+    }
+}
+
+//A null move is sent as 0000 when there is no legal move to play
+fn uci_move(m: Move, chess960: bool) -> String {
+    if m == Move::none() {
+        Move::null().to_string()
+    } else {
+        m.to_uci(chess960)
+    }
+}
+
+//One line per multipv line, numbered only when there is more than one
+fn info(r: &SearchResult, chess960: bool) -> String {
+    let ms = r.time.as_millis() as u64;
+    let nps = r.nodes * 1000 / ms.max(1);
+    let line = |multipv: String, v: Value, pv: &[Move]| {
+        let pv: Vec<String> = pv.iter().map(|m| m.to_uci(chess960)).collect();
+        format!(
+            "info depth {}{} score {} nodes {} nps {} hashfull {} time {} pv {}",
+            r.depth,
+            multipv,
+            score(v),
+            r.nodes,
+            nps,
+            r.hashfull,
+            ms,
+            pv.join(" ")
+        )
+    };
+
+    if r.lines.len() <= 1 {
+        return line(String::new(), r.score, &r.pv);
+    }
+    let lines: Vec<String> = r
+        .lines
+        .iter()
+        .enumerate()
+        .map(|(i, rm)| line(format!(" multipv {}", i + 1), rm.score, &rm.pv))
+        .collect();
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::board::bitboard as bb;
+    use crate::nnue::features::FeatureSet;
+    use crate::nnue::network::random_network_bytes;
+    use std::sync::Mutex;
+
+    fn run(uci: &mut Uci, cmd: &str) -> String {
+        let mut out = Vec::new();
+        uci.execute(cmd, &mut out);
+        String::from_utf8(out).unwrap()
+    }
+
+    fn fen_of(uci: &mut Uci) -> String {
+        let out = run(uci, "d");
+        out.lines()
+            .find_map(|l| l.strip_prefix("Fen: "))
+            .unwrap()
+            .to_string()
+    }
+
+    fn new_uci() -> Uci {
+        bb::init();
+        Position::init();
+        Uci::new()
+    }
+
+    //Output shared by the command loop and the input thread. Like stdout, every writeln holds
+    //the lock for the whole line
+    #[derive(Clone, Default)]
+    struct SharedOutput(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedOutput {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn write_fmt(&mut self, args: std::fmt::Arguments) -> io::Result<()> {
+            self.0.lock().unwrap().write_fmt(args)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_handshake() {
+        let mut uci = new_uci();
+        let out = run(&mut uci, "uci");
+        assert!(out.starts_with("id name"));
+        assert!(out.ends_with("uciok\n"));
+        assert_eq!(run(&mut uci, "isready"), "readyok\n");
+        assert!(!uci.execute("quit", &mut Vec::new()));
+    }
+
+    #[test]
+    fn test_isready_while_searching() {
+        let mut uci = new_uci();
+        let output = SharedOutput::default();
+        let input = io::Cursor::new("isready\ngo infinite\nisready\nstop\n");
+        let out = output.clone();
+        uci.serve(input, move || out.clone());
+        let out = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = out.lines().filter(|l| !l.starts_with("info")).collect();
+        //The search only ends on stop, so the second readyok cannot wait for it
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[..2], ["readyok", "readyok"]);
+        assert!(lines[2].starts_with("bestmove"));
+    }
+
+    #[test]
+    fn test_pipelined_stop() {
+        let mut uci = new_uci();
+        let output = SharedOutput::default();
+        let out = output.clone();
+        //The input stays open, so only the stops can end the searches. Both are read before
+        //the first search ends and each must stop its own go
+        let (input, mut writer) = io::pipe().unwrap();
+        let server =
+            thread::spawn(move || uci.serve(io::BufReader::new(input), move || out.clone()));
+        writer
+            .write_all(b"go infinite\nstop\ngo infinite\nstop\n")
+            .unwrap();
+        let bestmoves = || {
+            let out = output.0.lock().unwrap();
+            String::from_utf8_lossy(&out).matches("bestmove ").count()
+        };
+        let start = Instant::now();
+        while bestmoves() < 2 && start.elapsed().as_secs() < 10 {
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(bestmoves(), 2);
+        drop(writer);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_end_of_input() {
+        let mut uci = new_uci();
+        let output = SharedOutput::default();
+        let out = output.clone();
+        uci.serve(io::Cursor::new("go infinite\n"), move || out.clone());
+        let out = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        assert!(out.lines().last().unwrap().starts_with("bestmove "));
+    }
+
+    #[test]
+    fn test_position_moves() {
+        let mut uci = new_uci();
+        run(&mut uci, "position startpos moves e2e4 c7c5 g1f3 d7d6");
+        assert_eq!(
+            fen_of(&mut uci),
+            "rnbqkbnr/pp2pppp/3p4/2p5/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 0 3"
+        );
+
+        //Castling, en passant and promotion
+        run(
+            &mut uci,
+            "position fen r3k2r/6P1/8/8/3p4/8/4P3/R3K2R w KQkq - 0 1 moves e1g1 e8c8 e2e4 d4e3 g7g8q",
+        );
+        assert_eq!(fen_of(&mut uci), "2kr2Qr/8/8/8/8/4p3/8/R4RK1 b - - 0 3");
+    }
+
+    #[test]
+    fn test_chess960() {
+        let mut uci = new_uci();
+        assert!(
+            run(&mut uci, "uci").contains("option name UCI_Chess960 type check default false\n")
+        );
+        assert_eq!(run(&mut uci, "setoption name UCI_Chess960 value true"), "");
+        //Castling is sent as the king capturing its rook
+        let fen = "4k3/8/8/8/8/8/8/1R1K3R w HB - 0 1";
+        let out = run(&mut uci, &format!("position fen {} moves d1g1", fen));
+        assert_eq!(out, "info string invalid move: d1g1\n");
+        run(&mut uci, &format!("position fen {} moves d1h1", fen));
+        assert_eq!(fen_of(&mut uci), "4k3/8/8/8/8/8/8/1R3RK1 b - - 1 1");
+        assert!(run(&mut uci, "setoption name UCI_Chess960 value maybe").starts_with("info string"));
+    }
+
+    #[test]
+    fn test_position_errors() {
+        let mut uci = new_uci();
+        run(&mut uci, "position startpos moves e2e4");
+        let before = fen_of(&mut uci);
+
+        assert!(run(&mut uci, "position fen 8/8/8 w - - 0 1").starts_with("info string"));
+        assert!(run(&mut uci, "position startpos moves e3e4").starts_with("info string"));
+        assert!(run(&mut uci, "position startpos moves e2e4 e7e8x").starts_with("info string"));
+        assert_eq!(fen_of(&mut uci), before);
+
+        run(&mut uci, "ucinewgame");
+        assert_eq!(fen_of(&mut uci), START_FEN);
+    }
+
+    #[test]
+    fn test_go_perft() {
+        let mut uci = new_uci();
+        let out = run(&mut uci, "go perft 2");
+        assert_eq!(out.lines().filter(|l| l.contains(": ")).count(), 21);
+        assert!(out.contains("e2e4: 20\n"));
+        assert!(out.ends_with("Nodes searched: 400\n"));
+
+        run(
+            &mut uci,
+            "position fen r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1",
+        );
+        let out = run(&mut uci, "go perft 1");
+        assert!(out.contains("e1g1: 1\n"));
+        assert!(out.contains("e1c1: 1\n"));
+        assert!(run(&mut uci, "go perft x").starts_with("info string"));
+        assert_eq!(fen_of(&mut uci), "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1");
+    }
+
+    #[test]
+    fn test_go() {
+        let mut uci = new_uci();
+        run(&mut uci, "position fen 6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1");
+        let out = run(&mut uci, "go depth 2");
+        assert!(out.contains("info depth 1 score mate 1 "));
+        assert!(out.contains(" pv a1a8\n"));
+        assert!(out.ends_with("bestmove a1a8\n"));
+
+        run(&mut uci, "position fen R5k1/5ppp/8/8/8/8/8/6K1 b - - 0 1");
+        assert_eq!(run(&mut uci, "go depth 3"), "bestmove 0000\n");
+
+        run(&mut uci, "position startpos");
+        let out = run(&mut uci, "go nodes 2000 movetime 5000");
+        assert!(out.lines().last().unwrap().starts_with("bestmove "));
+        assert!(run(&mut uci, "go depth x").starts_with("info string"));
+        assert_eq!(fen_of(&mut uci), START_FEN);
+    }
+
+    #[test]
+    fn test_go_clock() {
+        let mut uci = new_uci();
+        assert!(run(&mut uci, "uci").contains("option name Move Overhead type spin default 10 "));
+        assert_eq!(run(&mut uci, "setoption name Move Overhead value 20"), "");
+        assert_eq!(uci.move_overhead, 20);
+        assert!(run(&mut uci, "setoption name Move Overhead value -1").starts_with("info string"));
+
+        //Only the clock of the side to move is used, a negative one counts as empty. How the
+        //time is split is up to the time manager, the search only has to end
+        let start = Instant::now();
+        let out = run(
+            &mut uci,
+            "go wtime 500 btime -50 winc 10 binc 10 movestogo 20",
+        );
+        assert!(start.elapsed().as_secs() < 5);
+        assert!(out.lines().last().unwrap().starts_with("bestmove "));
+        assert!(run(&mut uci, "go wtime").starts_with("info string"));
+    }
+
+    #[test]
+    fn test_go_limits() {
+        let mut uci = new_uci();
+        run(&mut uci, "position fen k7/8/2K5/8/8/8/8/7R w - - 0 1");
+        let out = run(&mut uci, "go mate 2");
+        assert!(out.contains(" score mate 2 "));
+        assert!(run(&mut uci, "go mate x").starts_with("info string"));
+
+        //searchmoves ends at the first token that is not a legal move
+        run(&mut uci, "position startpos");
+        let out = run(&mut uci, "go searchmoves h2h3 h2h5 depth 2");
+        assert!(out.contains("info depth 2 "));
+        assert!(out.contains("\nbestmove h2h3 ponder "));
+
+        //go infinite returns once stop is signalled
+        let stop = uci.stop_signal();
+        let stopper = thread::spawn(move || {
+            thread::sleep(std::time::Duration::from_millis(50));
+            stop.store(true, Ordering::Relaxed);
+        });
+        let out = run(&mut uci, "go infinite depth 3");
+        stopper.join().unwrap();
+        assert!(out.contains("info depth 3 "));
+        assert!(out.lines().last().unwrap().starts_with("bestmove "));
+        //The next search clears the signal
+        assert!(run(&mut uci, "go depth 1").contains("info depth 1 "));
+    }
+
+    #[test]
+    fn test_multipv() {
+        let mut uci = new_uci();
+        assert!(run(&mut uci, "uci").contains("option name MultiPV type spin default 1 min 1 "));
+        assert_eq!(run(&mut uci, "setoption name MultiPV value 3"), "");
+        let out = run(&mut uci, "go depth 3");
+        for depth in 1..=3 {
+            for i in 1..=3 {
+                let prefix = format!("info depth {} multipv {} score ", depth, i);
+                assert_eq!(out.lines().filter(|l| l.starts_with(&prefix)).count(), 1);
+            }
+        }
+        assert!(!out.contains("multipv 4"));
+        //The first line is the move that is played
+        let first = out.lines().rev().nth(3).unwrap();
+        let best = first
+            .split(" pv ")
+            .nth(1)
+            .unwrap()
+            .split(' ')
+            .next()
+            .unwrap();
+        assert!(out
+            .lines()
+            .last()
+            .unwrap()
+            .starts_with(&format!("bestmove {} ", best)));
+
+        assert_eq!(run(&mut uci, "setoption name MultiPV value 1"), "");
+        assert!(run(&mut uci, "go depth 1").starts_with("info depth 1 score "));
+        assert!(run(&mut uci, "setoption name MultiPV value x").starts_with("info string"));
+    }
+
+    #[test]
+    fn test_ponder() {
+        let mut uci = new_uci();
+        assert!(run(&mut uci, "uci").contains("option name Ponder type check default false\n"));
+        assert_eq!(run(&mut uci, "setoption name Ponder value true"), "");
+
+        //The best move comes with the expected reply, from the pv or else from the table
+        let out = run(&mut uci, "go depth 3");
+        let line = out.lines().last().unwrap();
+        let pv = out
+            .lines()
+            .rev()
+            .nth(1)
+            .unwrap()
+            .split(" pv ")
+            .nth(1)
+            .unwrap();
+        let pv: Vec<&str> = pv.split(' ').collect();
+        assert_eq!(line, format!("bestmove {} ponder {}", pv[0], pv[1]));
+        run(&mut uci, "position fen 6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1");
+        assert!(run(&mut uci, "go depth 3").ends_with("bestmove a1a8\n"));
+        run(&mut uci, "position startpos");
+        let result = SearchResult {
+            best_move: uci.pos.parse_uci_move("e2e4").unwrap(),
+            score: 0,
+            depth: 1,
+            nodes: 0,
+            time: std::time::Duration::ZERO,
+            hashfull: 0,
+            pv: Vec::new(),
+            lines: Vec::new(),
+        };
+        run(&mut uci, "setoption name Clear Hash");
+        assert_eq!(uci.ponder_move(&result), None);
+        run(&mut uci, "go depth 4");
+        run(&mut uci, "position startpos moves e2e4");
+        let reply = uci.tt.probe(uci.pos.key()).map(|data| data.mv);
+        run(&mut uci, "position startpos");
+        assert_eq!(uci.ponder_move(&result), reply);
+
+        //Pondering ignores the clock until ponderhit, after that the search is timed
+        let ponderhit = uci.ponderhit_signal();
+        let start = Instant::now();
+        let hitter = thread::spawn(move || {
+            thread::sleep(std::time::Duration::from_millis(200));
+            ponderhit.store(true, Ordering::Relaxed);
+        });
+        let out = run(&mut uci, "go ponder wtime 100 btime 100");
+        hitter.join().unwrap();
+        let elapsed = start.elapsed().as_millis();
+        assert!((200..5000).contains(&elapsed), "{}", elapsed);
+        assert!(out.lines().last().unwrap().starts_with("bestmove "));
+
+        //Or it is stopped because the opponent played another move
+        let stop = uci.stop_signal();
+        let stopper = thread::spawn(move || {
+            thread::sleep(std::time::Duration::from_millis(50));
+            stop.store(true, Ordering::Relaxed);
+        });
+        let out = run(&mut uci, "go ponder wtime 100000 btime 100000");
+        stopper.join().unwrap();
+        assert!(out.lines().last().unwrap().starts_with("bestmove "));
+    }
+
+    #[test]
+    fn test_hash_options() {
+        let mut uci = new_uci();
+        assert!(run(&mut uci, "uci").contains("option name Hash type spin default 16 "));
+        assert_eq!(run(&mut uci, "setoption name Hash value 2"), "");
+        run(&mut uci, "go depth 3");
+        assert!(uci.tt.probe(uci.pos.key()).is_some());
+        assert_eq!(run(&mut uci, "setoption name clear hash"), "");
+        assert!(uci.tt.probe(uci.pos.key()).is_none());
+        assert!(run(&mut uci, "setoption name Hash value lots").starts_with("info string"));
+        assert!(run(&mut uci, "setoption name Hashes value 1").starts_with("No such option"));
+    }
+
+    #[test]
+    fn test_threads_option() {
+        let mut uci = new_uci();
+        assert!(run(&mut uci, "uci").contains("option name Threads type spin default 1 min 1 "));
+        assert_eq!(run(&mut uci, "setoption name Threads value 3"), "");
+        assert_eq!(uci.threads.size(), 3);
+        run(&mut uci, "position fen 6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1");
+        let out = run(&mut uci, "go depth 4");
+        assert!(out.ends_with("bestmove a1a8\n"));
+        assert_eq!(run(&mut uci, "setoption name Threads value 0"), "");
+        assert_eq!(uci.threads.size(), 1);
+        assert!(run(&mut uci, "setoption name Threads value x").starts_with("info string"));
+    }
+
+    #[test]
+    fn test_eval() {
+        let mut uci = new_uci();
+        run(&mut uci, "position fen 4k3/8/8/8/8/8/8/3QK3 b - - 0 1");
+        let out = run(&mut uci, "eval");
+        assert!(out.starts_with("Classical evaluation: +"));
+        assert!(out.contains("\nFinal evaluation: +"));
+        assert!(!out.contains("NNUE"));
+        assert!(out.ends_with(" (white side)\n"));
+    }
+
+    #[test]
+    fn test_eval_file() {
+        let mut uci = new_uci();
+        assert!(run(&mut uci, "uci").contains("option name EvalFile type string default <empty>\n"));
+        let out = run(
+            &mut uci,
+            "setoption name EvalFile value /nonexistent/net.nnue",
+        );
+        assert!(out.starts_with("info string /nonexistent/net.nnue: could not read network"));
+        assert!(uci.network.is_none());
+
+        let path = std::env::temp_dir().join(format!("rusty_raven_{}.nnue", std::process::id()));
+        std::fs::write(&path, random_network_bytes(FeatureSet::HalfKP, 3)).unwrap();
+        let out = run(
+            &mut uci,
+            &format!("setoption name EvalFile value {}", path.display()),
+        );
+        std::fs::remove_file(&path).unwrap();
+        assert!(out.starts_with("info string NNUE evaluation using "));
+        assert!(uci.network.is_some());
+
+        run(&mut uci, "position startpos moves e2e4 e7e5 g1f3");
+        assert!(run(&mut uci, "eval").contains("\nNNUE evaluation: "));
+        assert!(run(&mut uci, "go depth 3").contains("bestmove "));
+
+        run(&mut uci, "setoption name EvalFile value <empty>");
+        assert!(uci.network.is_none());
+    }
+
+    #[test]
+    fn test_score() {
+        assert_eq!(score(PawnValue), "cp 100");
+        assert_eq!(score(-PawnValue / 2), "cp -50");
+        assert_eq!(score(mate_in(1)), "mate 1");
+        assert_eq!(score(mate_in(3)), "mate 2");
+        assert_eq!(score(mated_in(2)), "mate -1");
+    }
+}
@@ -0,0 +1,112 @@
+use rusty_screbby::board::perft::{divide, perft};
+use rusty_screbby::board::position::{chess960_fen, Position, START_FEN};
+
+const KIWIPETE: &str = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+const POSITION3: &str = "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1";
+const POSITION4: &str = "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1";
+const POSITION5: &str = "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8";
+const POSITION6: &str = "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10";
+
+//Node counts from https://www.chessprogramming.org/Perft_Results
+fn check(fen: &str, expected: &[u64]) {
+    check_position(Position::from_fen(fen).unwrap(), fen, expected);
+}
+
+fn check_chess960(fen: &str, expected: &[u64]) {
+    check_position(Position::from_chess960_fen(fen).unwrap(), fen, expected);
+}
+
+fn check_position(mut pos: Position, fen: &str, expected: &[u64]) {
+    for (depth, &nodes) in expected.iter().enumerate() {
+        assert_eq!(
+            perft(&mut pos, depth as i32 + 1),
+            nodes,
+            "{} depth {}",
+            fen,
+            depth + 1
+        );
+    }
+    assert_eq!(pos.fen(), fen);
+}
+
+#[test]
+fn test_perft_startpos() {
+    check(START_FEN, &[20, 400, 8902, 197281]);
+}
+
+#[test]
+fn test_perft_kiwipete() {
+    check(KIWIPETE, &[48, 2039, 97862]);
+}
+
+#[test]
+fn test_perft_position3() {
+    check(POSITION3, &[14, 191, 2812, 43238]);
+}
+
+#[test]
+fn test_perft_position4() {
+    check(POSITION4, &[6, 264, 9467]);
+}
+
+#[test]
+fn test_perft_position5() {
+    check(POSITION5, &[44, 1486, 62379]);
+}
+
+#[test]
+fn test_perft_position6() {
+    check(POSITION6, &[46, 2079, 89890]);
+}
+
+//Node counts from https://www.chessprogramming.org/Chess960_Perft_Results
+#[test]
+fn test_perft_chess960() {
+    for (fen, expected) in [
+        (
+            "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9",
+            [21, 528, 12189],
+        ),
+        (
+            "2nnrbkr/p1qppppp/8/1ppb4/6PP/3PP3/PPP2P2/BQNNRBKR w HEhe - 1 9",
+            [21, 807, 18002],
+        ),
+        (
+            "b1q1rrkb/pppppppp/3nn3/8/P7/1PPP4/4PPPP/BQNNRKRB w GE - 1 9",
+            [20, 479, 10471],
+        ),
+        (
+            "qbbnnrkr/2pp2pp/p7/1p2pp2/8/P3PP2/1PPP1KPP/QBBNNR1R w hf - 0 9",
+            [22, 593, 13440],
+        ),
+    ] {
+        check_chess960(fen, &expected);
+    }
+}
+
+#[test]
+fn test_perft_chess960_start_positions() {
+    //The standard start position has number 518
+    assert_eq!(chess960_fen(518).unwrap(), START_FEN);
+    for (n, expected) in [(0, [20, 400]), (959, [20, 400])] {
+        let fen = chess960_fen(n).unwrap();
+        let mut pos = Position::from_chess960_fen(&fen).unwrap();
+        for (depth, &nodes) in expected.iter().enumerate() {
+            assert_eq!(perft(&mut pos, depth as i32 + 1), nodes, "{}", fen);
+        }
+    }
+}
+
+#[test]
+fn test_divide() {
+    let mut pos = Position::from_fen(KIWIPETE).unwrap();
+    let counts = divide(&mut pos, 3);
+    assert_eq!(counts.len(), 48);
+    assert_eq!(counts.iter().map(|(_, n)| n).sum::<u64>(), 97862);
+
+    let mut pos = Position::from_fen(START_FEN).unwrap();
+    for (m, n) in divide(&mut pos, 1) {
+        assert_eq!(n, 1, "{}", m);
+    }
+    assert_eq!(perft(&mut pos, 0), 1);
+}